@@ -0,0 +1,211 @@
+use super::board::{Board, Color, Tile, TileMask};
+use super::position::{Position, Direction};
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct PerColor<T> {
+    pub black: T,
+    pub white: T
+}
+
+impl<T> PerColor<T> {
+    pub fn get(&self, color: &Color) -> &T {
+        match color {
+            Color::Black => &self.black,
+            Color::White => &self.white
+        }
+    }
+
+    pub fn get_mut(&mut self, color: &Color) -> &mut T {
+        match color {
+            Color::Black => &mut self.black,
+            Color::White => &mut self.white
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct Statistics {
+    pub mobility: usize,                            /* Number of legal moves */
+    pub potential_mobility: usize,                  /* Number of empty tiles next to an opponent's disc */
+    pub frontier: usize                             /* Number of own discs next to an empty tile */
+}
+
+/* The four lines going through a tile, each as its two opposite directions */
+static AXES: [(Direction, Direction); 4] = [
+    (Direction::Left, Direction::Right),
+    (Direction::Up, Direction::Down),
+    (Direction::UpLeft, Direction::DownRight),
+    (Direction::UpRight, Direction::DownLeft)
+];
+
+impl Board {
+    fn ray_full(&self, position: &Position, direction: &Direction) -> bool {
+        let mut current = position.advance(direction, &self.size);
+        while let Some(pos) = current {
+            if !self.taken(&pos) { return false; }
+            current = pos.advance(direction, &self.size);
+        }
+        true
+    }
+
+    /* Whether the neighbour in that direction is the edge of the board or a stable disc of the same color */
+    fn anchored(&self, position: &Position, direction: &Direction, color: &Color, stable: &TileMask) -> bool {
+        match position.advance(direction, &self.size) {
+            Some(pos) => stable.contains(self.index(&pos)) && self.get(&pos).0 == Some(*color),
+            None => true
+        }
+    }
+
+    fn has_neighbour(&self, position: &Position, predicate: impl Fn(&Tile) -> bool) -> bool {
+        Direction::iter_all()
+                  .filter_map(|direction| position.advance(direction, &self.size))
+                  .any(|pos| predicate(&self.get(&pos)))
+    }
+
+    /* The number of discs of each color */
+    pub fn discs(&self) -> PerColor<usize> {
+        let mut discs = PerColor::<usize>::default();
+        for pos in self.iter_all_positions() {
+            if let Some(color) = self.get(&pos).0 { *discs.get_mut(&color) += 1; }
+        }
+        discs
+    }
+
+    pub fn statistics(&self) -> PerColor<Statistics> {
+        let mut statistics = PerColor::<Statistics>::default();
+        for color in [Color::Black, Color::White].iter() {
+            statistics.get_mut(color).mobility = self.legal_moves(*color).count();
+        }
+        for pos in self.iter_all_positions() {
+            match self.get(&pos).0 {
+                Some(color) => {
+                    if self.has_neighbour(&pos, |tile| tile.0.is_none()) { statistics.get_mut(&color).frontier += 1; }
+                },
+                None => {
+                    for color in [Color::Black, Color::White].iter() {
+                        if self.has_neighbour(&pos, |tile| tile.0 == Some(color.opposite())) { statistics.get_mut(color).potential_mobility += 1; }
+                    }
+                }
+            }
+        }
+        statistics
+    }
+
+    /*
+    A disc can't be flipped along a line if the line is full, or if it's anchored to the edge or to a stable disc of its own color.
+    A disc for which this holds along all four lines is stable. Corners are stable right away, and stability then spreads from them,
+    along edges and inwards, until nothing changes. This is conservative - every disc found is truly stable, but not the other way around.
+    */
+    pub fn stable_discs(&self) -> PerColor<TileMask> {
+        let mut stable = TileMask::default();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for pos in self.iter_all_positions() {
+                let idx = self.index(&pos);
+                let color = match self.get(&pos).0 { Some(color) if !stable.contains(idx) => color, _ => continue };
+                let is_stable = AXES.iter().all(|(forward, backward)| {
+                    (self.ray_full(&pos, forward) && self.ray_full(&pos, backward)) ||
+                    self.anchored(&pos, forward, &color, &stable) ||
+                    self.anchored(&pos, backward, &color, &stable)
+                });
+                if is_stable {
+                    stable.insert(idx);
+                    changed = true;
+                }
+            }
+        }
+
+        let mut discs = PerColor::<TileMask>::default();
+        for idx in stable.iter() {
+            if let Some(color) = self.get(&self.position(idx)).0 {
+                discs.get_mut(&color).insert(idx);
+            }
+        }
+        discs
+    }
+}
+
+/* What's sent to the front-end when asking to analyze the current board */
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Analysis {
+    pub stable: PerColor<Vec<Position>>,
+    pub statistics: PerColor<Statistics>
+}
+
+impl Analysis {
+    pub fn of(board: &Board) -> Analysis {
+        let stable = board.stable_discs();
+        Analysis {
+            stable: PerColor {
+                black: board.positions_of(stable.black).collect(),
+                white: board.positions_of(stable.white).collect()
+            },
+            statistics: board.statistics()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::board::{Board, Color};
+    use super::super::position::Position;
+    use super::{Analysis, PerColor, Statistics};
+
+    #[test]
+    fn stable_discs_test_empty() {
+        let stable = Board::new(4, 4).stable_discs();
+        assert!(stable.black.is_empty() && stable.white.is_empty());
+    }
+
+    #[test]
+    fn stable_discs_test_corner_and_edge() {
+        let mut board = Board::new(6, 6);
+        board.set(&Position{x: 0, y: 0}, &Color::Black);
+        board.set(&Position{x: 1, y: 0}, &Color::Black);
+        board.set(&Position{x: 2, y: 0}, &Color::White);
+        board.set(&Position{x: 1, y: 1}, &Color::Black);
+        let analysis = Analysis::of(&board);
+        assert_eq!(analysis.stable.black, vec![Position{x: 0, y: 0}, Position{x: 1, y: 0}]);
+        assert!(analysis.stable.white.is_empty());
+    }
+
+    #[test]
+    fn stable_discs_test_full_line() {
+        let mut board = Board::new(3, 3);
+        for x in 0..3 {
+            board.set(&Position{x, y: 0}, &Color::White);
+            board.set(&Position{x, y: 2}, &Color::White);
+        }
+        board.set(&Position{x: 0, y: 1}, &Color::Black);
+        board.set(&Position{x: 1, y: 1}, &Color::Black);
+        board.set(&Position{x: 2, y: 1}, &Color::White);
+        let stable = board.stable_discs();
+        assert_eq!(stable.white.count(), 7);
+        assert_eq!(stable.black.count(), 2);
+    }
+
+    #[test]
+    fn stable_discs_test_interior_not_stable() {
+        let mut board = Board::new(6, 6);
+        board.set(&Position{x: 2, y: 2}, &Color::White);
+        board.set(&Position{x: 3, y: 3}, &Color::White);
+        board.set(&Position{x: 2, y: 3}, &Color::Black);
+        board.set(&Position{x: 3, y: 2}, &Color::Black);
+        let stable = board.stable_discs();
+        assert!(stable.black.is_empty() && stable.white.is_empty());
+    }
+
+    #[test]
+    fn statistics_test() {
+        let mut board = Board::new(4, 4);
+        board.set(&Position{x: 0, y: 0}, &Color::Black);
+        board.set(&Position{x: 1, y: 0}, &Color::White);
+        board.set(&Position{x: 0, y: 1}, &Color::White);
+        board.set(&Position{x: 1, y: 1}, &Color::White);
+        let statistics = board.statistics();
+        assert_eq!(statistics.black, Statistics{ mobility: 3, potential_mobility: 5, frontier: 0 });
+        assert_eq!(statistics.white, Statistics{ mobility: 0, potential_mobility: 0, frontier: 3 });
+        assert_eq!(board.discs(), PerColor{ black: 1, white: 3 });
+    }
+}
@@ -0,0 +1,689 @@
+use super::handler::{Request, Response, Setting, Query, Encoding, Capability, Palette, Rgb};
+use super::parser::ParsingError;
+use super::game::{ChangeSet, Snapshot, Variant};
+use super::rooms::{GameOptions, TimeControl};
+use super::board::{Tile, Color};
+use super::position::{Position, Size};
+use super::analysis::{Analysis, PerColor, Statistics};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+
+/*
+The binary counterpart of parser.rs, for clients on poor links. Every message is a fixed-size header followed by a payload:
+    [type: u8][id: u16][payload length: u16]                    (big-endian - an id of 0 means the message has none)
+Coordinates are a byte each, and tiles are two bits each (0 empty, 1 Black, 2 White), packed from the most significant bits on.
+Change sets are a bitmask of the changed tiles, followed by the new tiles packed in row-major order.
+*/
+
+pub const HEADER_SIZE: usize = 5;
+
+const START: u8 = 0x01;
+const DO_TURN: u8 = 0x02;
+const CANCEL: u8 = 0x03;
+const HINT: u8 = 0x04;
+const ANALYZE: u8 = 0x05;
+const SET: u8 = 0x06;
+const HELLO: u8 = 0x07;
+const QUERY: u8 = 0x08;
+const CREATE: u8 = 0x09;
+const JOIN: u8 = 0x0A;
+const LEAVE: u8 = 0x0B;
+const LOBBY: u8 = 0x0C;
+const MATCH: u8 = 0x0D;
+const WATCH: u8 = 0x0E;
+const SESSION: u8 = 0x0F;
+const RESUME: u8 = 0x10;
+const ARCHIVE: u8 = 0x11;
+const LOAD: u8 = 0x12;
+const PING: u8 = 0x13;
+
+const UPDATE: u8 = 0x81;
+const SNAPSHOT: u8 = 0x82;
+const HINT_RESPONSE: u8 = 0x83;
+const ANALYSIS: u8 = 0x84;
+const OK: u8 = 0x85;
+const HELLO_RESPONSE: u8 = 0x86;
+const ERROR: u8 = 0x87;
+const PLAYER: u8 = 0x88;
+const SCORE: u8 = 0x89;
+const MOVE_NUMBER: u8 = 0x8A;
+const MOVES: u8 = 0x8B;
+const ROOM: u8 = 0x8C;
+const LOBBY_RESPONSE: u8 = 0x8D;
+const SPECTATORS: u8 = 0x8E;
+const SESSION_RESPONSE: u8 = 0x8F;
+const ARCHIVE_RESPONSE: u8 = 0x90;
+const PONG: u8 = 0x91;
+
+const ALL_QUERIES: [Query; 6] = [Query::Board, Query::Player, Query::Score, Query::MoveNumber, Query::History, Query::LastMove];
+
+const ALL_CAPABILITIES: [Capability; 7] = [
+    Capability::Json, Capability::Snapshots, Capability::FullBoards, Capability::Statistics, Capability::Hints, Capability::Analysis, Capability::Binary
+];
+
+/* The length of the payload following a header */
+pub fn payload_len(header: &[u8; HEADER_SIZE]) -> usize {
+    u16::from_be_bytes([header[3], header[4]]) as usize
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn frame(kind: u8, id: Option<u16>, payload: Vec<u8>) -> Vec<u8> {
+    let len = u16::try_from(payload.len()).expect("Impossible event: payload longer than a frame allows (binary_parser::frame)");
+    let mut message = vec![kind];
+    message.extend_from_slice(&id.unwrap_or(0).to_be_bytes());
+    message.extend_from_slice(&len.to_be_bytes());
+    message.extend(payload);
+    message
+}
+
+/* A message's type and payload */
+type Unframed<'a> = Result<(u8, &'a [u8]), ParsingError>;
+
+/* Splits a message into its type, id and payload - the id is returned even when the rest is invalid */
+fn unframe(source: &[u8]) -> (Option<u16>, Unframed<'_>) {
+    if source.len() < HEADER_SIZE { return (None, Err(ParsingError::missing("Binary::header", &hex(source)))); }
+    let id = Some(u16::from_be_bytes([source[1], source[2]])).filter(|id| *id != 0);
+    let len = u16::from_be_bytes([source[3], source[4]]) as usize;
+    if source.len() != HEADER_SIZE + len { return (id, Err(ParsingError::invalid_value("Binary::length", &hex(source)))); }
+    (id, Ok((source[0], &source[HEADER_SIZE..])))
+}
+
+/* Reads a payload front to back, failing on anything missing */
+struct Reader<'a> {
+    bytes: &'a [u8],
+    idx: usize
+}
+
+impl<'a> Reader<'a> {
+    fn u8(&mut self, role: &'static str) -> Result<u8, ParsingError> {
+        let byte = *self.bytes.get(self.idx).ok_or_else(|| ParsingError::missing(role, &hex(self.bytes)))?;
+        self.idx += 1;
+        Ok(byte)
+    }
+
+    fn u16(&mut self, role: &'static str) -> Result<u16, ParsingError> {
+        Ok(u16::from_be_bytes([self.u8(role)?, self.u8(role)?]))
+    }
+
+    fn u64(&mut self, role: &'static str) -> Result<u64, ParsingError> {
+        let bytes = self.take(8, role)?;
+        Ok(u64::from_be_bytes(<[u8; 8]>::try_from(bytes).expect("Impossible event: take returned the wrong length (Reader::u64)")))
+    }
+
+    fn take(&mut self, len: usize, role: &'static str) -> Result<&'a [u8], ParsingError> {
+        if self.idx + len > self.bytes.len() { return Err(ParsingError::missing(role, &hex(self.bytes))); }
+        let taken = &self.bytes[self.idx..self.idx + len];
+        self.idx += len;
+        Ok(taken)
+    }
+
+    fn remaining(&self) -> usize {
+        self.bytes.len() - self.idx
+    }
+
+    fn finish<T>(&self, value: T) -> Result<T, ParsingError> {
+        if self.remaining() > 0 { return Err(ParsingError::invalid_value("Binary::trailing", &hex(self.bytes))); }
+        Ok(value)
+    }
+
+    /* The rest of the payload, as UTF-8 */
+    fn text(&mut self, role: &'static str) -> Result<String, ParsingError> {
+        let bytes = self.take(self.remaining(), role)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| ParsingError::invalid_value(role, &hex(bytes)))
+    }
+
+    fn switch(&mut self, role: &'static str) -> Result<bool, ParsingError> {
+        match self.u8(role)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            value => Err(ParsingError::invalid_switch(role, &value.to_string()))
+        }
+    }
+
+    fn position(&mut self, role: &'static str) -> Result<Position, ParsingError> {
+        Ok(Position{x: self.u8(role)? as usize, y: self.u8(role)? as usize})
+    }
+
+    fn color(&mut self, role: &'static str) -> Result<Color, ParsingError> {
+        match tile_of(self.u8(role)?) {
+            Some(Tile(Some(color))) => Ok(color),
+            _ => Err(ParsingError::invalid_value(role, &hex(self.bytes)))
+        }
+    }
+
+    fn rgb(&mut self, role: &'static str) -> Result<Rgb, ParsingError> {
+        Ok(Rgb(self.u8(role)?, self.u8(role)?, self.u8(role)?))
+    }
+
+    fn positions(&mut self, role: &'static str) -> Result<Vec<Position>, ParsingError> {
+        let count = self.u8(role)?;
+        (0..count).map(|_| self.position(role)).collect()
+    }
+
+    /* Two bits per tile, as written by pack_tiles */
+    fn tiles(&mut self, count: usize, role: &'static str) -> Result<Vec<Tile>, ParsingError> {
+        let packed = self.take(count.div_ceil(4), role)?;
+        (0..count).map(|idx| tile_of(packed[idx / 4] >> (6 - 2 * (idx % 4)) & 0b11).ok_or_else(|| ParsingError::invalid_value(role, &hex(packed))))
+                  .collect()
+    }
+
+    /* Width, height, variant, then the time control as two u16 - no time control at all if the first is 0 */
+    fn options(&mut self) -> Result<GameOptions, ParsingError> {
+        let size = Size{ width: self.u8("GameOptions::width")? as usize, height: self.u8("GameOptions::height")? as usize };
+        let variant = match self.u8("GameOptions::variant")? {
+            0 => Variant::Standard,
+            1 => Variant::Parallel,
+            value => return Err(ParsingError::invalid_value("GameOptions::variant", &value.to_string()))
+        };
+        let (initial, increment) = (self.u16("TimeControl::initial")? as u32, self.u16("TimeControl::increment")? as u32);
+        let time_control = if initial == 0 { None } else { Some(TimeControl{ initial, increment }) };
+        Ok(GameOptions{ size, variant, time_control })
+    }
+
+    fn statistics(&mut self) -> Result<PerColor<Statistics>, ParsingError> {
+        let mut values = [0usize; 6];
+        for value in values.iter_mut() {
+            *value = self.u8("Binary::Statistics")? as usize;
+        }
+        Ok(PerColor{
+            black: Statistics{ mobility: values[0], potential_mobility: values[2], frontier: values[4] },
+            white: Statistics{ mobility: values[1], potential_mobility: values[3], frontier: values[5] }
+        })
+    }
+}
+
+fn tile_of(value: u8) -> Option<Tile> {
+    match value {
+        0 => Some(Tile(None)),
+        1 => Some(Tile(Some(Color::Black))),
+        2 => Some(Tile(Some(Color::White))),
+        _ => None
+    }
+}
+
+fn tile_value(tile: &Tile) -> u8 {
+    match tile.0 {
+        None => 0,
+        Some(Color::Black) => 1,
+        Some(Color::White) => 2
+    }
+}
+
+fn pack_tiles<'a>(tiles: impl Iterator<Item=&'a Tile>) -> Vec<u8> {
+    let mut packed = Vec::new();
+    for (idx, tile) in tiles.enumerate() {
+        if idx % 4 == 0 { packed.push(0); }
+        *packed.last_mut().expect("Impossible event: no byte to pack into (binary_parser::pack_tiles)") |= tile_value(tile) << (6 - 2 * (idx % 4));
+    }
+    packed
+}
+
+fn push_position(payload: &mut Vec<u8>, position: &Position) {
+    payload.push(position.x as u8);
+    payload.push(position.y as u8);
+}
+
+fn push_positions(payload: &mut Vec<u8>, positions: &[Position]) {
+    payload.push(positions.len() as u8);
+    for position in positions.iter() {
+        push_position(payload, position);
+    }
+}
+
+fn push_options(payload: &mut Vec<u8>, options: &GameOptions) {
+    let variant = match options.variant {
+        Variant::Standard => 0,
+        Variant::Parallel => 1
+    };
+    payload.extend_from_slice(&[options.size.width as u8, options.size.height as u8, variant]);
+    let (initial, increment) = options.time_control.map_or((0, 0), |time_control| (time_control.initial, time_control.increment));
+    payload.extend_from_slice(&(initial.min(u16::MAX as u32) as u16).to_be_bytes());
+    payload.extend_from_slice(&(increment.min(u16::MAX as u32) as u16).to_be_bytes());
+}
+
+fn push_statistics(payload: &mut Vec<u8>, statistics: &PerColor<Statistics>) {
+    let (black, white) = (&statistics.black, &statistics.white);
+    for value in [black.mobility, white.mobility, black.potential_mobility, white.potential_mobility, black.frontier, white.frontier].iter() {
+        payload.push(*value as u8);
+    }
+}
+
+fn capabilities_mask(capabilities: &[Capability]) -> u16 {
+    ALL_CAPABILITIES.iter().enumerate()
+                    .filter(|(_, capability)| capabilities.contains(capability))
+                    .fold(0, |mask, (bit, _)| mask | 1 << bit)
+}
+
+/* Unknown bits are left out, just like unknown capabilities in the text encoding */
+fn capabilities_of(mask: u16) -> Vec<Capability> {
+    ALL_CAPABILITIES.iter().enumerate().filter(|(bit, _)| mask & 1 << bit != 0).map(|(_, capability)| *capability).collect()
+}
+
+fn encoding_value(encoding: &Encoding) -> u8 {
+    match encoding {
+        Encoding::Text => 0,
+        Encoding::Json => 1,
+        Encoding::Binary => 2
+    }
+}
+
+impl Setting {
+    fn encode_binary(&self, payload: &mut Vec<u8>) {
+        match self {
+            Setting::Statistics(include) => payload.extend_from_slice(&[1, *include as u8]),
+            Setting::Encoding(encoding) => payload.extend_from_slice(&[2, encoding_value(encoding)]),
+            Setting::Snapshots(snapshots) => payload.extend_from_slice(&[3, *snapshots as u8]),
+            Setting::FullBoards(full_boards) => payload.extend_from_slice(&[4, *full_boards as u8]),
+            Setting::Palette(Palette::Symbolic) => payload.extend_from_slice(&[5, 0]),
+            Setting::Palette(Palette::Rgb{ black, white, empty }) => {
+                payload.extend_from_slice(&[5, 1]);
+                for rgb in [black, white, empty].iter() {
+                    payload.extend_from_slice(&[rgb.0, rgb.1, rgb.2]);
+                }
+            }
+        }
+    }
+
+    fn decode_binary(reader: &mut Reader) -> Result<Setting, ParsingError> {
+        match reader.u8("Request::Set::Setting")? {
+            1 => Ok(Setting::Statistics(reader.switch("Setting::Statistics::value")?)),
+            2 => match reader.u8("Setting::Encoding::value")? {
+                0 => Ok(Setting::Encoding(Encoding::Text)),
+                1 => Ok(Setting::Encoding(Encoding::Json)),
+                2 => Ok(Setting::Encoding(Encoding::Binary)),
+                value => Err(ParsingError::unrecognized_encoding(&value.to_string()))
+            },
+            3 => Ok(Setting::Snapshots(reader.switch("Setting::Snapshots::value")?)),
+            4 => Ok(Setting::FullBoards(reader.switch("Setting::FullBoards::value")?)),
+            5 => match reader.u8("Setting::Palette::value")? {
+                0 => Ok(Setting::Palette(Palette::Symbolic)),
+                1 => {
+                    let (black, white, empty) = (reader.rgb("Palette::black")?, reader.rgb("Palette::white")?, reader.rgb("Palette::empty")?);
+                    Palette::rgb(black, white, empty).map(Setting::Palette).ok_or_else(|| ParsingError::invalid_value("Setting::Palette", &hex(reader.bytes)))
+                },
+                value => Err(ParsingError::invalid_value("Setting::Palette::value", &value.to_string()))
+            },
+            setting => Err(ParsingError::unrecognized_setting(&setting.to_string()))
+        }
+    }
+}
+
+/* Queries are numbered from 1, in the order of ALL_QUERIES */
+fn query_value(query: &Query) -> u8 {
+    ALL_QUERIES.iter().position(|known| known == query).expect("Impossible event: query missing from ALL_QUERIES (binary_parser::query_value)") as u8 + 1
+}
+
+impl Request {
+    pub fn encode_binary(&self, id: Option<u16>) -> Vec<u8> {
+        let mut payload = Vec::new();
+        let kind = match self {
+            Request::Start => START,
+            Request::DoTurn(position) => { push_position(&mut payload, position); DO_TURN },
+            Request::Cancel => CANCEL,
+            Request::Hint => HINT,
+            Request::Analyze => ANALYZE,
+            Request::Set(setting) => { setting.encode_binary(&mut payload); SET },
+            Request::Create(options) => { push_options(&mut payload, options); CREATE },
+            Request::Lobby => LOBBY,
+            Request::Match(options) => { push_options(&mut payload, options); MATCH },
+            Request::Join(code) => { payload.extend_from_slice(code.as_bytes()); JOIN },
+            Request::Watch(code) => { payload.extend_from_slice(code.as_bytes()); WATCH },
+            Request::Leave => LEAVE,
+            Request::Session => SESSION,
+            Request::Resume(token) => { payload.extend_from_slice(token.as_bytes()); RESUME },
+            Request::Archive => ARCHIVE,
+            Request::Ping => PING,
+            Request::Load(id) => { payload.extend_from_slice(&id.to_be_bytes()); LOAD },
+            Request::Query(query) => { payload.push(query_value(query)); QUERY },
+            Request::Hello(version, capabilities) => {
+                payload.extend_from_slice(&(*version as u16).to_be_bytes());
+                payload.extend_from_slice(&capabilities_mask(capabilities).to_be_bytes());
+                HELLO
+            }
+        };
+        frame(kind, id, payload)
+    }
+
+    /* The request's id is returned even when the request itself is invalid, so errors can be correlated */
+    pub fn decode_binary(source: &[u8]) -> (Option<u16>, Result<Request, ParsingError>) {
+        let (id, unframed) = unframe(source);
+        (id, unframed.and_then(|(kind, payload)| Request::decode_payload(kind, payload)))
+    }
+
+    fn decode_payload(kind: u8, payload: &[u8]) -> Result<Request, ParsingError> {
+        let mut reader = Reader{ bytes: payload, idx: 0 };
+        let request = match kind {
+            START => Request::Start,
+            DO_TURN => Request::DoTurn(reader.position("Request::DoTurn::Position")?),
+            CANCEL => Request::Cancel,
+            HINT => Request::Hint,
+            ANALYZE => Request::Analyze,
+            SET => Request::Set(Setting::decode_binary(&mut reader)?),
+            CREATE => Request::Create(reader.options()?),
+            LOBBY => Request::Lobby,
+            MATCH => Request::Match(reader.options()?),
+            JOIN => Request::Join(reader.text("Request::Join::code")?),
+            WATCH => Request::Watch(reader.text("Request::Watch::code")?),
+            LEAVE => Request::Leave,
+            SESSION => Request::Session,
+            RESUME => Request::Resume(reader.text("Request::Resume::token")?),
+            ARCHIVE => Request::Archive,
+            PING => Request::Ping,
+            LOAD => Request::Load(reader.u64("Request::Load::id")?),
+            QUERY => {
+                let value = reader.u8("Request::Query::Query")?;
+                let query = (value as usize).checked_sub(1).and_then(|idx| ALL_QUERIES.get(idx));
+                Request::Query(*query.ok_or_else(|| ParsingError::invalid_value("Request::Query::Query", &value.to_string()))?)
+            },
+            HELLO => Request::Hello(reader.u16("Request::Hello::version")? as u32, capabilities_of(reader.u16("Request::Hello::capabilities")?)),
+            _ => return Err(ParsingError::unrecognized_request_type(&format!("{:02x}", kind)))
+        };
+        reader.finish(request)
+    }
+}
+
+impl Response {
+    pub fn encode_binary(&self, id: Option<u16>) -> Vec<u8> {
+        let mut payload = Vec::new();
+        let kind = match self {
+            Response::Update(change_set, statistics) => {
+                /* The mask covers the rows and columns from 0,0 up to the last change - the whole board for a summary */
+                let width = change_set.tiles.keys().map(|pos| pos.x + 1).max().unwrap_or(0);
+                let height = change_set.tiles.keys().map(|pos| pos.y + 1).max().unwrap_or(0);
+                let mut mask = vec![0u8; (width * height).div_ceil(8)];
+                for pos in change_set.tiles.keys() {
+                    let idx = pos.y * width + pos.x;
+                    mask[idx / 8] |= 0x80 >> (idx % 8);
+                }
+                payload.extend_from_slice(&[tile_value(&Tile(Some(change_set.player))), width as u8, height as u8]);
+                payload.extend(mask);
+                payload.extend(pack_tiles(change_set.tiles.values()));
+                if let Some(statistics) = statistics { push_statistics(&mut payload, statistics); }
+                UPDATE
+            },
+            Response::Snapshot(snapshot) => {
+                payload.extend_from_slice(&[tile_value(&Tile(Some(snapshot.player))), snapshot.size.width as u8, snapshot.size.height as u8]);
+                payload.extend(pack_tiles(snapshot.tiles.iter()));
+                SNAPSHOT
+            },
+            Response::Hint(position) => {
+                if let Some(position) = position { push_position(&mut payload, position); }
+                HINT_RESPONSE
+            },
+            Response::Analysis(analysis) => {
+                push_positions(&mut payload, &analysis.stable.black);
+                push_positions(&mut payload, &analysis.stable.white);
+                push_statistics(&mut payload, &analysis.statistics);
+                ANALYSIS
+            },
+            Response::Ok => OK,
+            Response::Pong => PONG,
+            Response::Hello(version, capabilities) => {
+                payload.extend_from_slice(&(*version as u16).to_be_bytes());
+                payload.extend_from_slice(&capabilities_mask(capabilities).to_be_bytes());
+                HELLO_RESPONSE
+            },
+            Response::Lobby(games) => {
+                /* The oldest games are the likeliest to be joined, so those beyond what a count byte can tell are left out */
+                let games = &games[..games.len().min(u8::MAX as usize)];
+                payload.push(games.len() as u8);
+                for (code, options) in games.iter() {
+                    push_options(&mut payload, options);
+                    payload.push(code.len() as u8);
+                    payload.extend_from_slice(code.as_bytes());
+                }
+                LOBBY_RESPONSE
+            },
+            Response::Room(code, color) => {
+                payload.push(tile_value(&Tile(Some(*color))));
+                payload.extend_from_slice(code.as_bytes());
+                ROOM
+            },
+            Response::Session(token) => { payload.extend_from_slice(token.as_bytes()); SESSION_RESPONSE },
+            Response::Archive(games) => {
+                /* Unlike the lobby, the latest games are the likeliest to be looked at */
+                let games = &games[games.len().saturating_sub(u8::MAX as usize)..];
+                payload.push(games.len() as u8);
+                for (id, options) in games.iter() {
+                    payload.extend_from_slice(&id.to_be_bytes());
+                    push_options(&mut payload, options);
+                }
+                ARCHIVE_RESPONSE
+            },
+            Response::Spectators(count) => { payload.extend_from_slice(&((*count).min(u16::MAX as usize) as u16).to_be_bytes()); SPECTATORS },
+            Response::Player(player) => { payload.push(tile_value(&Tile(Some(*player)))); PLAYER },
+            Response::Score(discs) => { payload.extend_from_slice(&[discs.black as u8, discs.white as u8]); SCORE },
+            Response::MoveNumber(number) => { payload.push(*number as u8); MOVE_NUMBER },
+            Response::Moves(moves) => {
+                payload.push(moves.len() as u8);
+                for (position, player) in moves.iter() {
+                    push_position(&mut payload, position);
+                    payload.push(tile_value(&Tile(Some(*player))));
+                }
+                MOVES
+            },
+            Response::Error(details) => {
+                /* Errors quote the request, which may be as long as a whole frame */
+                let mut end = details.len().min(u16::MAX as usize);
+                while !details.is_char_boundary(end) { end -= 1; }
+                payload.extend_from_slice(&details.as_bytes()[..end]);
+                ERROR
+            }
+        };
+        frame(kind, id, payload)
+    }
+
+    /* The counterpart of Response::encode_binary, for clients written in Rust */
+    pub fn decode_binary(source: &[u8]) -> (Option<u16>, Result<Response, ParsingError>) {
+        let (id, unframed) = unframe(source);
+        (id, unframed.and_then(|(kind, payload)| Response::decode_payload(kind, payload)))
+    }
+
+    fn decode_payload(kind: u8, payload: &[u8]) -> Result<Response, ParsingError> {
+        let mut reader = Reader{ bytes: payload, idx: 0 };
+        let response = match kind {
+            UPDATE => {
+                let player = reader.color("Response::Update::player")?;
+                let (width, height) = (reader.u8("Response::Update::width")? as usize, reader.u8("Response::Update::height")? as usize);
+                let mask = reader.take((width * height).div_ceil(8), "Response::Update::mask")?;
+                let positions: Vec<Position> = (0..width * height).filter(|idx| mask[idx / 8] & 0x80 >> (idx % 8) != 0)
+                                                                  .map(|idx| Position{x: idx % width, y: idx / width})
+                                                                  .collect();
+                let tiles = reader.tiles(positions.len(), "Response::Update::tiles")?;
+                let statistics = if reader.remaining() > 0 { Some(reader.statistics()?) } else { None };
+                Response::Update(ChangeSet{ tiles: positions.into_iter().zip(tiles).collect::<BTreeMap<Position, Tile>>(), player }, statistics)
+            },
+            SNAPSHOT => {
+                let player = reader.color("Response::Snapshot::player")?;
+                let size = Size{ width: reader.u8("Response::Snapshot::width")? as usize, height: reader.u8("Response::Snapshot::height")? as usize };
+                let tiles = reader.tiles(size.width * size.height, "Response::Snapshot::tiles")?;
+                Response::Snapshot(Snapshot{ size, tiles, player })
+            },
+            HINT_RESPONSE => Response::Hint(if reader.remaining() > 0 { Some(reader.position("Response::Hint::position")?) } else { None }),
+            ANALYSIS => {
+                let stable = PerColor{ black: reader.positions("Response::Analysis::stable")?, white: reader.positions("Response::Analysis::stable")? };
+                Response::Analysis(Analysis{ stable, statistics: reader.statistics()? })
+            },
+            OK => Response::Ok,
+            PONG => Response::Pong,
+            HELLO_RESPONSE => Response::Hello(reader.u16("Response::Hello::version")? as u32, capabilities_of(reader.u16("Response::Hello::capabilities")?)),
+            LOBBY_RESPONSE => {
+                let count = reader.u8("Response::Lobby::count")?;
+                let games = (0..count).map(|_| {
+                    let options = reader.options()?;
+                    let len = reader.u8("Response::Lobby::code")? as usize;
+                    let code = reader.take(len, "Response::Lobby::code")?;
+                    let code = String::from_utf8(code.to_vec()).map_err(|_| ParsingError::invalid_value("Response::Lobby::code", &hex(code)))?;
+                    Ok((code, options))
+                }).collect::<Result<Vec<(String, GameOptions)>, ParsingError>>()?;
+                Response::Lobby(games)
+            },
+            ROOM => {
+                let color = reader.color("Response::Room::color")?;
+                Response::Room(reader.text("Response::Room::code")?, color)
+            },
+            SESSION_RESPONSE => Response::Session(reader.text("Response::Session::token")?),
+            ARCHIVE_RESPONSE => {
+                let count = reader.u8("Response::Archive::count")?;
+                let games = (0..count).map(|_| Ok((reader.u64("Response::Archive::id")?, reader.options()?)))
+                                      .collect::<Result<Vec<(u64, GameOptions)>, ParsingError>>()?;
+                Response::Archive(games)
+            },
+            SPECTATORS => Response::Spectators(reader.u16("Response::Spectators::count")? as usize),
+            PLAYER => Response::Player(reader.color("Response::Player::player")?),
+            SCORE => Response::Score(PerColor{ black: reader.u8("Response::Score::black")? as usize, white: reader.u8("Response::Score::white")? as usize }),
+            MOVE_NUMBER => Response::MoveNumber(reader.u8("Response::MoveNumber::number")? as usize),
+            MOVES => {
+                let count = reader.u8("Response::Moves::count")?;
+                let moves = (0..count).map(|_| Ok((reader.position("Response::Moves::position")?, reader.color("Response::Moves::player")?)))
+                                      .collect::<Result<Vec<(Position, Color)>, ParsingError>>()?;
+                Response::Moves(moves)
+            },
+            ERROR => Response::Error(reader.text("Response::Error::message")?),
+            _ => return Err(ParsingError::unrecognized_response_type(&format!("{:02x}", kind)))
+        };
+        reader.finish(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::parser::ParsingError;
+    use super::super::handler::{Request, Response, Setting, Query, Encoding, Capability, Palette, Rgb};
+    use super::super::game::{ChangeSet, Snapshot};
+    use super::super::board::{Tile, Color};
+    use super::super::position::{Position, Size};
+    use super::super::analysis::{Analysis, PerColor, Statistics};
+    use super::super::game::Variant;
+    use super::super::rooms::{GameOptions, TimeControl};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn request_test_layout() {
+        assert_eq!(Request::DoTurn(Position{x: 3, y: 4}).encode_binary(Some(258)), vec![0x02, 1, 2, 0, 2, 3, 4]);
+        assert_eq!(Request::Start.encode_binary(None), vec![0x01, 0, 0, 0, 0]);
+        assert_eq!(Request::decode_binary(&[0x02, 0, 7, 0, 2, 3, 4]), (Some(7), Ok(Request::DoTurn(Position{x: 3, y: 4}))));
+    }
+
+    #[test]
+    fn request_test_invalid() {
+        assert_eq!(Request::decode_binary(&[0x02, 0]), (None, Err(ParsingError::missing("Binary::header", "0200"))));
+        assert_eq!(Request::decode_binary(&[0x02, 0, 9, 0, 1, 3]), (Some(9), Err(ParsingError::missing("Request::DoTurn::Position", "03"))));
+        assert_eq!(Request::decode_binary(&[0x01, 0, 0, 0, 1, 3]), (None, Err(ParsingError::invalid_value("Binary::trailing", "03"))));
+        assert_eq!(Request::decode_binary(&[0x01, 0, 0, 0, 3, 3]), (None, Err(ParsingError::invalid_value("Binary::length", "010000000303"))));
+        assert_eq!(Request::decode_binary(&[0x42, 0, 0, 0, 0]), (None, Err(ParsingError::unrecognized_request_type("42"))));
+        assert_eq!(Request::decode_binary(&[0x08, 0, 0, 0, 1, 7]), (None, Err(ParsingError::invalid_value("Request::Query::Query", "7"))));
+    }
+
+    #[test]
+    fn request_test_round_trip() {
+        let requests = vec![
+            Request::Start,
+            Request::DoTurn(Position{x: 9, y: 0}),
+            Request::Cancel,
+            Request::Hint,
+            Request::Analyze,
+            Request::Set(Setting::Statistics(true)),
+            Request::Set(Setting::Encoding(Encoding::Binary)),
+            Request::Set(Setting::Snapshots(false)),
+            Request::Set(Setting::FullBoards(true)),
+            Request::Set(Setting::Palette(Palette::Symbolic)),
+            Request::Set(Setting::Palette(Palette::Rgb{black: Rgb(1, 2, 3), white: Rgb(4, 5, 6), empty: Rgb(7, 8, 9)})),
+            Request::Create(GameOptions::default()),
+            Request::Match(GameOptions{ size: Size{width: 4, height: 6}, variant: Variant::Parallel, time_control: Some(TimeControl{initial: 600, increment: 10}) }),
+            Request::Lobby,
+            Request::Join(String::from("K7Q2MZ")),
+            Request::Watch(String::from("K7Q2MZ")),
+            Request::Leave,
+            Request::Session,
+            Request::Archive,
+            Request::Ping,
+            Request::Load(u64::MAX - 1),
+            Request::Resume(String::from("0123456789abcdef0123456789abcdef")),
+            Request::Query(Query::Board),
+            Request::Query(Query::LastMove),
+            Request::Hello(2, vec![Capability::Json, Capability::Binary])
+        ];
+        for request in requests {
+            assert_eq!(Request::decode_binary(&request.encode_binary(Some(1))), (Some(1), Ok(request)));
+        }
+    }
+
+    #[test]
+    fn response_test_update_layout() {
+        let mut tiles = BTreeMap::new();
+        tiles.insert(Position{x: 3, y: 4}, Tile(Some(Color::Black)));
+        tiles.insert(Position{x: 4, y: 4}, Tile(Some(Color::Black)));
+        /* A 5x5 region: tiles 23 and 24 are set, then Black and Black packed into a byte */
+        assert_eq!(Response::Update(ChangeSet{tiles, player: Color::White}, None).encode_binary(None),
+                   vec![0x81, 0, 0, 0, 8, 2, 5, 5, 0, 0, 0b0000_0001, 0b1000_0000, 0b0101_0000]);
+    }
+
+    #[test]
+    fn response_test_snapshot_layout() {
+        let snapshot = Snapshot{
+            size: Size{width: 3, height: 2},
+            tiles: vec![Tile(None), Tile(Some(Color::Black)), Tile(None), Tile(Some(Color::White)), Tile(None), Tile(None)],
+            player: Color::Black
+        };
+        assert_eq!(Response::Snapshot(snapshot).encode_binary(None), vec![0x82, 0, 0, 0, 5, 1, 3, 2, 0b0001_0010, 0b0000_0000]);
+    }
+
+    #[test]
+    fn response_test_round_trip() {
+        let mut tiles = BTreeMap::new();
+        tiles.insert(Position{x: 4, y: 5}, Tile(Some(Color::White)));
+        tiles.insert(Position{x: 8, y: 5}, Tile(None));
+        tiles.insert(Position{x: 0, y: 9}, Tile(Some(Color::Black)));
+        let statistics = PerColor{
+            black: Statistics{mobility: 1, potential_mobility: 2, frontier: 3},
+            white: Statistics{mobility: 4, potential_mobility: 5, frontier: 6}
+        };
+        let responses = vec![
+            Response::Update(ChangeSet{tiles: tiles.clone(), player: Color::Black}, None),
+            Response::Update(ChangeSet{tiles, player: Color::White}, Some(statistics)),
+            Response::Update(ChangeSet{tiles: BTreeMap::new(), player: Color::White}, None),
+            Response::Snapshot(Snapshot{
+                size: Size{width: 3, height: 3},
+                tiles: vec![Tile(Some(Color::Black)), Tile(None), Tile(None), Tile(Some(Color::White)), Tile(None),
+                            Tile(None), Tile(None), Tile(None), Tile(Some(Color::White))],
+                player: Color::White
+            }),
+            Response::Hint(Some(Position{x: 3, y: 4})),
+            Response::Hint(None),
+            Response::Analysis(Analysis{
+                stable: PerColor{black: vec![Position{x: 0, y: 0}, Position{x: 1, y: 0}], white: Vec::new()},
+                statistics
+            }),
+            Response::Ok,
+            Response::Pong,
+            Response::Hello(2, vec![Capability::Snapshots, Capability::Binary]),
+            Response::Room(String::from("K7Q2MZ"), Color::Black),
+            Response::Spectators(300),
+            Response::Session(String::from("0123456789abcdef0123456789abcdef")),
+            Response::Archive(vec![(1, GameOptions::default()), (1 << 40, GameOptions{ variant: Variant::Parallel, ..GameOptions::default() })]),
+            Response::Archive(Vec::new()),
+            Response::Lobby(vec![(String::from("K7Q2MZ"), GameOptions::default()), (String::from("AB"), GameOptions{ variant: Variant::Parallel, ..GameOptions::default() })]),
+            Response::Lobby(Vec::new()),
+            Response::Player(Color::Black),
+            Response::Score(PerColor{black: 60, white: 40}),
+            Response::MoveNumber(96),
+            Response::Moves(vec![(Position{x: 3, y: 4}, Color::Black), (Position{x: 3, y: 3}, Color::White)]),
+            Response::Moves(Vec::new()),
+            Response::Error(String::from("Something went wrong"))
+        ];
+        for response in responses {
+            assert_eq!(Response::decode_binary(&response.encode_binary(Some(65535))), (Some(65535), Ok(response.clone())));
+        }
+    }
+
+    #[test]
+    fn response_test_long_error() {
+        let encoded = Response::Error("é".repeat(40_000)).encode_binary(None);
+        assert_eq!(encoded.len(), super::HEADER_SIZE + 65534);
+        assert!(Response::decode_binary(&encoded).1.is_ok());
+    }
+}
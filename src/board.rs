@@ -1,6 +1,6 @@
 use super::position::{Position, Size, Direction};
 
-#[derive(PartialEq, Debug, Clone, Copy)]
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
 pub enum Color {
     White, Black
 }
@@ -17,6 +17,55 @@ impl Color {
 #[derive(PartialEq, Debug, Clone)]
 pub struct Tile(pub Option<Color>);
 
+/* A set of tiles, as a bitmask over their row-major indices. This is what limits a board to 128 tiles */
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct TileMask(pub u128);
+
+impl TileMask {
+    pub fn insert(&mut self, index: usize) {
+        self.0 |= 1 << index;
+    }
+
+    pub fn contains(&self, index: usize) -> bool {
+        self.0 & (1 << index) != 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn count(&self) -> usize {
+        self.0.count_ones() as usize
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=usize> {
+        let mut remaining = self.0;
+        std::iter::from_fn(move || {
+            if remaining == 0 { return None; }
+            let index = remaining.trailing_zeros() as usize;
+            remaining &= remaining - 1;
+            Some(index)
+        })
+    }
+}
+
+impl std::ops::BitOr for TileMask {
+    type Output = TileMask;
+
+    fn bitor(self, other: TileMask) -> TileMask {
+        TileMask(self.0 | other.0)
+    }
+}
+
+/* A single placement, with everything needed to undo it */
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct Move {
+    pub position: Position,
+    pub player: Color,
+    pub flips: TileMask
+}
+
+#[derive(PartialEq, Debug, Clone)]
 pub struct Board {
     tiles: Vec<Tile>,
     pub size: Size
@@ -24,18 +73,28 @@ pub struct Board {
 
 impl Board {
     pub fn new(width: usize, height: usize) -> Board {
+        assert!(width * height <= 128, "A board can't have more than 128 tiles (TileMask)");
         Board {
             tiles: vec![Tile(None); width * height],
             size: Size{ width: width, height: height }
         }
     }
 
+    pub fn index(&self, pos: &Position) -> usize {
+        pos.y * self.size.width + pos.x
+    }
+
+    pub fn position(&self, index: usize) -> Position {
+        Position{x: index % self.size.width, y: index / self.size.width}
+    }
+
     fn at(&self, pos: &Position) -> &Tile {
-        &self.tiles[pos.y * self.size.width + pos.x]
+        &self.tiles[self.index(pos)]
     }
 
     fn at_mut(&mut self, pos: &Position) -> &mut Tile {
-        &mut self.tiles[pos.y * self.size.width + pos.x]
+        let index = self.index(pos);
+        &mut self.tiles[index]
     }
 
     pub fn get(&self, pos: &Position) -> Tile {
@@ -59,43 +118,64 @@ impl Board {
         self.at(pos).0.is_some()
     }
 
-    fn calculate_flip_vector(&self, position: &Position, direction: &Direction, player: &Color) -> Option<Vec<Position>> {
+    pub fn positions_of(&self, mask: TileMask) -> impl Iterator<Item=Position> {
+        let width = self.size.width;
+        mask.iter().map(move |idx| Position{x: idx % width, y: idx / width})
+    }
+
+    fn calculate_flip_vector(&self, position: &Position, direction: &Direction, player: &Color) -> TileMask {
         let mut current = position.advance(direction, &self.size);
-        let mut flip_vector: Vec<Position> = Vec::new();
-        loop {
-            match current {
-                Some(pos) => {
-                    match self.at(&pos).0 {
-                        Some(color) => {
-                            if color == *player {
-                                return if flip_vector.is_empty() { None } else { Some(flip_vector) }
-                            } else {
-                                flip_vector.push(pos);
-                                current = pos.advance(direction, &self.size);
-                            }
-                        },
-                        None => { return None; }
-                    }
+        let mut flip_vector = TileMask::default();
+        while let Some(pos) = current {
+            match self.at(&pos).0 {
+                Some(color) if color == *player => { return flip_vector; },
+                Some(_) => {
+                    flip_vector.insert(self.index(&pos));
+                    current = pos.advance(direction, &self.size);
                 },
-                None => { return None; }
+                None => { return TileMask::default(); }
             }
         }
+        TileMask::default()
     }
 
-    pub fn calculate_flip_positions(&self, position: &Position, player: &Color) -> Vec<Position> {
+    pub fn calculate_flip_mask(&self, position: &Position, player: &Color) -> TileMask {
         Direction::iter_all()
-                  .filter_map(|direction|
-                      self.calculate_flip_vector(position, direction, player)
-                  )
-                  .flatten()
-                  .collect::<Vec<Position>>()
+                  .map(|direction| self.calculate_flip_vector(position, direction, player))
+                  .fold(TileMask::default(), |acc, flips| acc | flips)
+    }
+
+    pub fn calculate_flip_positions(&self, position: &Position, player: &Color) -> Vec<Position> {
+        self.positions_of(self.calculate_flip_mask(position, player)).collect()
+    }
+
+    /* All legal placements for player, in row-major order */
+    pub fn legal_moves(&self, player: Color) -> impl Iterator<Item=Move> + '_ {
+        self.iter_all_positions()
+            .filter(move |pos| !self.taken(pos))
+            .map(move |position| Move{ position, player, flips: self.calculate_flip_mask(&position, &player) })
+            .filter(|mv| !mv.flips.is_empty())
+    }
+
+    pub fn apply(&mut self, mv: &Move) {
+        for idx in mv.flips.iter() {
+            self.tiles[idx] = Tile(Some(mv.player));
+        }
+        self.set(&mv.position, &mv.player);
+    }
+
+    pub fn revert(&mut self, mv: &Move) {
+        for idx in mv.flips.iter() {
+            self.tiles[idx] = Tile(Some(mv.player.opposite()));
+        }
+        self.unset(&mv.position);
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::super::position::Position;
-    use super::{Board, Color};
+    use super::{Board, Color, Move, TileMask};
 
     #[test]
     fn iter_all_positions_test() {
@@ -125,4 +205,45 @@ mod tests {
         assert_eq!(board.calculate_flip_positions(&Position{x: 0, y: 2}, &Color::Black), vec![Position{x: 0, y: 1}]);
         assert_eq!(board.calculate_flip_positions(&Position{x: 1, y: 1}, &Color::Black), Vec::new());
     }
+
+    #[test]
+    fn tile_mask_test() {
+        let mut mask = TileMask::default();
+        assert!(mask.is_empty());
+        mask.insert(3);
+        mask.insert(127);
+        mask.insert(0);
+        assert!(mask.contains(127));
+        assert!(!mask.contains(4));
+        assert_eq!(mask.count(), 3);
+        assert_eq!(mask.iter().collect::<Vec<usize>>(), vec![0, 3, 127]);
+    }
+
+    #[test]
+    fn apply_and_revert_test() {
+        let mut board = Board::new(4, 4);
+        board.set(&Position{x: 0, y: 0}, &Color::Black);
+        board.set(&Position{x: 1, y: 0}, &Color::White);
+        board.set(&Position{x: 2, y: 0}, &Color::White);
+        let position = Position{x: 3, y: 0};
+        let mv = Move{ position, player: Color::Black, flips: board.calculate_flip_mask(&position, &Color::Black) };
+        assert_eq!(mv.flips.count(), 2);
+
+        board.apply(&mv);
+        assert!(board.iter_all_positions().filter(|pos| pos.y == 0).all(|pos| board.get(&pos).0 == Some(Color::Black)));
+        board.revert(&mv);
+        assert_eq!(board.get(&Position{x: 1, y: 0}).0, Some(Color::White));
+        assert_eq!(board.get(&Position{x: 2, y: 0}).0, Some(Color::White));
+        assert!(!board.taken(&position));
+    }
+
+    #[test]
+    fn legal_moves_test() {
+        let mut board = Board::new(4, 4);
+        board.set(&Position{x: 1, y: 1}, &Color::White);
+        board.set(&Position{x: 2, y: 2}, &Color::Black);
+        let moves: Vec<Position> = board.legal_moves(Color::Black).map(|mv| mv.position).collect();
+        assert_eq!(moves, vec![Position{x: 0, y: 0}]);
+        assert_eq!(board.legal_moves(Color::White).count(), 1);
+    }
 }
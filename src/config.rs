@@ -0,0 +1,202 @@
+use super::server::Timeouts;
+use super::search::{SearchConfig, MAX_SEARCH_DEPTH};
+
+use std::time::Duration;
+
+/*
+Server options, taken from the command line and the environment - flags win over environment variables, which win over the defaults:
+    --host <address>            REVERSI_HOST            The address all listeners bind to (127.0.0.1)
+    --port <port>               REVERSI_PORT            The line-based protocol (5040)
+    --websocket-port <port>     REVERSI_WEBSOCKET_PORT  WebSocket (5041)
+    --http-port <port>          REVERSI_HTTP_PORT       The HTTP API (5042)
+    --session-grace <seconds>   REVERSI_SESSION_GRACE   How long the game of a dropped connection waits to be resumed (60, at most 3600)
+    --data-dir <path>           REVERSI_DATA_DIR        Where games are kept across restarts (games)
+    --max-connections <count>   REVERSI_MAX_CONNECTIONS How many connections are served at once, over all listeners (256)
+    --idle-timeout <seconds>    REVERSI_IDLE_TIMEOUT    How long a connection may send nothing before it's closed (300)
+    --write-timeout <seconds>   REVERSI_WRITE_TIMEOUT   How long writing to a connection may block before it's closed (10)
+    --search-depth <plies>      REVERSI_SEARCH_DEPTH    How far the engine looks ahead (4, at most 128)
+    --search-threads <count>    REVERSI_SEARCH_THREADS  How many threads a single search runs on (1 when serving, every core with --nboard)
+    --nboard                                            Speak the NBoard protocol on stdin/stdout instead of serving
+Port 0 lets the system pick a free port, which is printed once bound. A timeout of 0 seconds means none.
+*/
+
+pub const USAGE: &str = "Usage: reversi_backend [--host <address>] [--port <port>] [--websocket-port <port>] [--http-port <port>] [--session-grace <seconds>] [--data-dir <path>] [--max-connections <count>] [--idle-timeout <seconds>] [--write-timeout <seconds>] [--search-depth <plies>] [--search-threads <count>] [--nboard]";
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Config {
+    pub host: String,
+    pub port: u16,
+    pub websocket_port: u16,
+    pub http_port: u16,
+    pub session_grace: u64,                         /* Seconds */
+    pub data_dir: String,
+    pub max_connections: usize,
+    pub idle_timeout: u64,                          /* Seconds */
+    pub write_timeout: u64,                         /* Seconds */
+    pub search_depth: Option<u32>,                  /* "None" for the engine's default */
+    pub search_threads: Option<usize>,              /* "None" for SERVER_SEARCH_THREADS, or every core with --nboard */
+    pub nboard: bool
+}
+
+/* Every connection may run a search at once, so on the server a single one mustn't take every core */
+pub const SERVER_SEARCH_THREADS: usize = 1;
+
+impl Default for Config {
+    fn default() -> Config {
+        Config::new()
+    }
+}
+
+fn parse_port(name: &str, value: &str) -> Result<u16, String> {
+    value.parse::<u16>().map_err(|_| format!("Invalid {}: {}", name, value))
+}
+
+/* At least 1 - with no connections at all, there's nothing to serve */
+fn parse_count(name: &str, value: &str) -> Result<usize, String> {
+    value.parse::<usize>().ok().filter(|count| *count > 0).ok_or_else(|| format!("Invalid {}: {}", name, value))
+}
+
+fn parse_seconds(name: &str, value: &str) -> Result<u64, String> {
+    value.parse::<u64>().map_err(|_| format!("Invalid {}: {}", name, value))
+}
+
+fn parse_depth(name: &str, value: &str) -> Result<u32, String> {
+    let depth = value.parse::<u32>().ok().filter(|depth| *depth > 0).ok_or_else(|| format!("Invalid {}: {}", name, value))?;
+    if depth > MAX_SEARCH_DEPTH { return Err(format!("Invalid {}: {} (at most {} plies)", name, value, MAX_SEARCH_DEPTH)); }
+    Ok(depth)
+}
+
+/* Parked games hold their seats and memory until the grace period is over, so it can't be made arbitrarily long */
+pub const MAX_SESSION_GRACE: u64 = 3600;
+
+fn parse_grace(name: &str, value: &str) -> Result<u64, String> {
+    let grace = parse_seconds(name, value)?;
+    if grace > MAX_SESSION_GRACE { return Err(format!("Invalid {}: {} (at most {} seconds)", name, value, MAX_SESSION_GRACE)); }
+    Ok(grace)
+}
+
+impl Config {
+    pub fn new() -> Config {
+        Config{ host: String::from("127.0.0.1"), port: 5040, websocket_port: 5041, http_port: 5042, session_grace: 60, data_dir: String::from("games"), max_connections: 256,
+                idle_timeout: 300, write_timeout: 10, search_depth: None, search_threads: None, nboard: false }
+    }
+
+    /* The options of this process */
+    pub fn from_env() -> Result<Config, String> {
+        Config::parse(std::env::args().skip(1), |name| std::env::var(name).ok())
+    }
+
+    /* Parses the given arguments (without the program name), falling back on env for what they leave out */
+    pub fn parse(args: impl IntoIterator<Item=String>, env: impl Fn(&str) -> Option<String>) -> Result<Config, String> {
+        let mut config = Config::new();
+        if let Some(host) = env("REVERSI_HOST") { config.host = host; }
+        if let Some(port) = env("REVERSI_PORT") { config.port = parse_port("REVERSI_PORT", &port)?; }
+        if let Some(port) = env("REVERSI_WEBSOCKET_PORT") { config.websocket_port = parse_port("REVERSI_WEBSOCKET_PORT", &port)?; }
+        if let Some(port) = env("REVERSI_HTTP_PORT") { config.http_port = parse_port("REVERSI_HTTP_PORT", &port)?; }
+        if let Some(grace) = env("REVERSI_SESSION_GRACE") { config.session_grace = parse_grace("REVERSI_SESSION_GRACE", &grace)?; }
+        if let Some(dir) = env("REVERSI_DATA_DIR") { config.data_dir = dir; }
+        if let Some(count) = env("REVERSI_MAX_CONNECTIONS") { config.max_connections = parse_count("REVERSI_MAX_CONNECTIONS", &count)?; }
+        if let Some(timeout) = env("REVERSI_IDLE_TIMEOUT") { config.idle_timeout = parse_seconds("REVERSI_IDLE_TIMEOUT", &timeout)?; }
+        if let Some(timeout) = env("REVERSI_WRITE_TIMEOUT") { config.write_timeout = parse_seconds("REVERSI_WRITE_TIMEOUT", &timeout)?; }
+        if let Some(depth) = env("REVERSI_SEARCH_DEPTH") { config.search_depth = Some(parse_depth("REVERSI_SEARCH_DEPTH", &depth)?); }
+        if let Some(threads) = env("REVERSI_SEARCH_THREADS") { config.search_threads = Some(parse_count("REVERSI_SEARCH_THREADS", &threads)?); }
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            /* Both "--port 5040" and "--port=5040" */
+            let (flag, inline_value) = match arg.split_once('=') {
+                Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+                None => (arg.clone(), None)
+            };
+            let mut value = || inline_value.clone().or_else(|| args.next()).ok_or_else(|| format!("Missing value for {}", flag));
+            match flag.as_str() {
+                "--host" => config.host = value()?,
+                "--port" => config.port = parse_port("port", &value()?)?,
+                "--websocket-port" => config.websocket_port = parse_port("WebSocket port", &value()?)?,
+                "--http-port" => config.http_port = parse_port("HTTP port", &value()?)?,
+                "--session-grace" => config.session_grace = parse_grace("session grace period", &value()?)?,
+                "--data-dir" => config.data_dir = value()?,
+                "--max-connections" => config.max_connections = parse_count("connection limit", &value()?)?,
+                "--idle-timeout" => config.idle_timeout = parse_seconds("idle timeout", &value()?)?,
+                "--write-timeout" => config.write_timeout = parse_seconds("write timeout", &value()?)?,
+                "--search-depth" => config.search_depth = Some(parse_depth("search depth", &value()?)?),
+                "--search-threads" => config.search_threads = Some(parse_count("search thread count", &value()?)?),
+                "--nboard" => config.nboard = true,
+                _ => return Err(format!("Unknown option: {}", arg))
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn timeouts(&self) -> Timeouts {
+        let seconds = |timeout: u64| Some(Duration::from_secs(timeout)).filter(|_| timeout > 0);
+        Timeouts{ idle: seconds(self.idle_timeout), write: seconds(self.write_timeout) }
+    }
+
+    pub fn search_config(&self) -> SearchConfig {
+        let default = SearchConfig::default();
+        let threads = if self.nboard { default.threads } else { SERVER_SEARCH_THREADS };
+        SearchConfig{ depth: self.search_depth.unwrap_or(default.depth), threads: self.search_threads.unwrap_or(threads) }
+    }
+
+    pub fn address(&self, port: u16) -> String {
+        /* IPv6 addresses need brackets once a port is appended */
+        if self.host.contains(':') { format!("[{}]:{}", self.host, port) } else { format!("{}:{}", self.host, port) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Config, SERVER_SEARCH_THREADS};
+    use super::super::search::SearchConfig;
+    use super::super::server::Timeouts;
+    use std::time::Duration;
+
+    fn parse(args: &[&str], env: &[(&str, &str)]) -> Result<Config, String> {
+        let env: Vec<(String, String)> = env.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+        Config::parse(args.iter().map(|arg| arg.to_string()),
+                      |name| env.iter().find(|(key, _)| key == name).map(|(_, value)| value.clone()))
+    }
+
+    #[test]
+    fn parse_test_defaults() {
+        assert_eq!(parse(&[], &[]), Ok(Config::new()));
+        assert_eq!(Config::new().address(5040), "127.0.0.1:5040");
+        assert_eq!(Config::new().search_config(), SearchConfig{ depth: 4, threads: SERVER_SEARCH_THREADS });
+    }
+
+    #[test]
+    fn parse_test_search() {
+        let config = parse(&["--search-depth=12"], &[("REVERSI_SEARCH_DEPTH", "6"), ("REVERSI_SEARCH_THREADS", "3")]).unwrap();
+        assert_eq!(config.search_config(), SearchConfig{ depth: 12, threads: 3 });
+        assert_eq!(parse(&["--nboard"], &[]).unwrap().search_config().threads, SearchConfig::default().threads);
+        assert_eq!(parse(&[], &[("REVERSI_SEARCH_DEPTH", "4294967300")]), Err(String::from("Invalid REVERSI_SEARCH_DEPTH: 4294967300")));
+        assert_eq!(parse(&["--search-depth", "129"], &[]), Err(String::from("Invalid search depth: 129 (at most 128 plies)")));
+        assert_eq!(parse(&["--search-depth", "0"], &[]), Err(String::from("Invalid search depth: 0")));
+        assert_eq!(parse(&["--search-threads", "0"], &[]), Err(String::from("Invalid search thread count: 0")));
+    }
+
+    #[test]
+    fn parse_test_flags_and_env() {
+        let config = parse(&["--port", "0", "--http-port=8080", "--nboard", "--session-grace", "5", "--idle-timeout", "0"],
+                           &[("REVERSI_HOST", "::1"), ("REVERSI_MAX_CONNECTIONS", "16"), ("REVERSI_PORT", "6000"), ("REVERSI_WEBSOCKET_PORT", "6001"), ("REVERSI_SESSION_GRACE", "30"), ("REVERSI_DATA_DIR", "/var/lib/reversi"),
+                             ("REVERSI_IDLE_TIMEOUT", "60"), ("REVERSI_WRITE_TIMEOUT", "3")]).unwrap();
+        assert_eq!(config, Config{ host: String::from("::1"), port: 0, websocket_port: 6001, http_port: 8080, session_grace: 5,
+                                   data_dir: String::from("/var/lib/reversi"), max_connections: 16, idle_timeout: 0, write_timeout: 3,
+                                   search_depth: None, search_threads: None, nboard: true });
+        assert_eq!(config.address(config.http_port), "[::1]:8080");
+        assert_eq!(config.timeouts(), Timeouts{ idle: None, write: Some(Duration::from_secs(3)) });
+    }
+
+    #[test]
+    fn parse_test_invalid() {
+        assert_eq!(parse(&["--port"], &[]), Err(String::from("Missing value for --port")));
+        assert_eq!(parse(&["--port", "70000"], &[]), Err(String::from("Invalid port: 70000")));
+        assert_eq!(parse(&["--verbose"], &[]), Err(String::from("Unknown option: --verbose")));
+        assert_eq!(parse(&[], &[("REVERSI_HTTP_PORT", "http")]), Err(String::from("Invalid REVERSI_HTTP_PORT: http")));
+        assert_eq!(parse(&["--session-grace=-1"], &[]), Err(String::from("Invalid session grace period: -1")));
+        assert_eq!(parse(&["--session-grace=3601"], &[]), Err(String::from("Invalid session grace period: 3601 (at most 3600 seconds)")));
+        assert_eq!(parse(&["--max-connections", "0"], &[]), Err(String::from("Invalid connection limit: 0")));
+        assert_eq!(parse(&["--write-timeout", "soon"], &[]), Err(String::from("Invalid write timeout: soon")));
+    }
+}
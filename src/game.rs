@@ -1,101 +1,98 @@
-use super::board::{Board, Tile, Color};
-use super::position::Position;
-use std::collections::HashMap;
+use super::board::{Board, Tile, Color, Move};
+use super::position::{Position, Size};
+use std::collections::BTreeMap;
 
 /*
-This struct is used in a doubled role. It represents a "hard" set of changes, non-regarding the previous game state
-The two roles are:
-1. As a return value to send the front-end - which doesn't know anything about the values.
-2. As a saved information in order to recreate previous states - as it's created anyway, and makes revoke_changes somewhat easier to implement
+This struct represents a "hard" set of changes, non-regarding the previous game state.
+It is what's sent to the front-end - which doesn't know anything about the values. Tiles are kept in row-major order.
+The game itself keeps only the compact Move history, and builds change sets out of it on demand.
 */
+#[derive(PartialEq, Debug, Clone)]
 pub struct ChangeSet {
-    pub tiles: HashMap<Position, Tile>,
+    pub tiles: BTreeMap<Position, Tile>,
     pub player: Color
 }
 
-struct ChangeLog {
-    pending_tile_changes: Vec<Position>,            /* List of pending tiles to change */
-    pending_player_change: bool,               
-    history: Vec<ChangeSet>                         /* A stack where each item is a list of changes, reversing one set of changes */
-}
-
-impl ChangeLog {
-    pub fn new() -> ChangeLog {
-        ChangeLog {
-            pending_tile_changes: Vec::new(),
-            pending_player_change: false,
-            history: Vec::new()
-        }
+impl ChangeSet {
+    /* The changes made by playing mv, leaving `player` to play next */
+    fn of_move(mv: &Move, board: &Board, player: Color) -> ChangeSet {
+        let mut tiles: BTreeMap<Position, Tile> = board.positions_of(mv.flips).map(|pos| (pos, Tile(Some(mv.player)))).collect();
+        tiles.insert(mv.position, Tile(Some(mv.player)));
+        ChangeSet { tiles, player }
     }
 
-    pub fn push_tile_change(&mut self, position: Position) {
-        self.pending_tile_changes.push(position);
-    }
-    pub fn push_player_change(&mut self) {
-        self.pending_player_change = !self.pending_player_change;
-    }
-    
-    pub fn discard_changes(&mut self) {
-        self.pending_tile_changes.clear();
-        self.pending_player_change = false;
+    /* The changes made by taking mv back */
+    fn of_undo(mv: &Move, board: &Board) -> ChangeSet {
+        let mut tiles: BTreeMap<Position, Tile> = board.positions_of(mv.flips).map(|pos| (pos, Tile(Some(mv.player.opposite())))).collect();
+        tiles.insert(mv.position, Tile(None));
+        ChangeSet { tiles, player: mv.player }
     }
 }
 
+/* The whole board at once - a compact alternative to a summary ChangeSet */
+#[derive(PartialEq, Debug, Clone)]
+pub struct Snapshot {
+    pub size: Size,
+    pub tiles: Vec<Tile>,                           /* In row-major order */
+    pub player: Color
+}
+
 pub type Result = std::result::Result<ChangeSet, String>;
 
+/* How the first four discs are placed in the center of the board */
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Variant {
+    Standard,                                       /* Crosswise - each color on one diagonal */
+    Parallel                                        /* Side by side - each color in one column */
+}
+
 pub struct Game {
     board: Board,
     current_player: Color,
-    change_log: ChangeLog
+    history: Vec<Move>                              /* A stack of the moves played, each one knowing how to revert itself */
 }
 
 impl Game {
     pub fn new() -> Game {
-        let mut board = Board::new(10, 10);
-        board.set(&Position{x: 4, y: 4}, &Color::White);
-        board.set(&Position{x: 4, y: 5}, &Color::Black);
-        board.set(&Position{x: 5, y: 4}, &Color::Black);
-        board.set(&Position{x: 5, y: 5}, &Color::White);
-
-        Game{ board: board, current_player: Color::Black, change_log: ChangeLog::new() }
+        Game::start(Size{ width: 10, height: 10 }, Variant::Standard).expect("Impossible event: the default board was refused (Game::new)")
     }
 
-    fn error(&mut self, message: &'static str) -> Result {
-        self.change_log.discard_changes();
-        Err(String::from(message))
+    /* A game on a board with even sides, starting from the variant's position. Black moves first */
+    pub fn start(size: Size, variant: Variant) -> std::result::Result<Game, &'static str> {
+        if size.width < 4 || size.height < 4 || !size.width.is_multiple_of(2) || !size.height.is_multiple_of(2) { return Err("The board's sides must be even and at least 4 long"); }
+        if size.width * size.height > 128 { return Err("The board can't have more than 128 tiles"); }
+
+        let (x, y) = (size.width / 2 - 1, size.height / 2 - 1);
+        let (below, right, diagonal) = match variant {
+            Variant::Standard => (Color::Black, Color::Black, Color::White),
+            Variant::Parallel => (Color::White, Color::Black, Color::Black)
+        };
+        let mut board = Board::new(size.width, size.height);
+        board.set(&Position{x, y}, &Color::White);
+        board.set(&Position{x, y: y + 1}, &below);
+        board.set(&Position{x: x + 1, y}, &right);
+        board.set(&Position{x: x + 1, y: y + 1}, &diagonal);
+        Ok(Game::with_board(board, Color::Black))
     }
 
-    fn flush_changes(&mut self) -> ChangeSet {
-        let mut tiles: HashMap<Position, Tile> = HashMap::new();
-        let mut history_tiles: HashMap<Position, Tile> = HashMap::new();
-        let history_player = self.current_player;
-
-        for pos in self.change_log.pending_tile_changes.iter() {
-            history_tiles.insert(*pos, self.board.get(pos));
-            self.board.set(pos, &self.current_player);
-            tiles.insert(*pos, self.board.get(pos));
-        }
-        if self.change_log.pending_player_change { self.current_player = self.current_player.opposite() }
-
-        self.change_log.history.push(ChangeSet { tiles: history_tiles, player: history_player });
-        
-        self.change_log.discard_changes();
+    /* A game starting from any position, e.g. one set up by an external tool */
+    pub fn with_board(board: Board, current_player: Color) -> Game {
+        /* A move fills a tile, so the history never outgrows the board and never reallocates */
+        let history = Vec::with_capacity(board.size.width * board.size.height);
+        Game{ board, current_player, history }
+    }
 
-        ChangeSet { tiles: tiles, player: self.current_player }
+    pub fn board(&self) -> &Board {
+        &self.board
     }
 
-    fn revoke_changes(&mut self) -> Option<ChangeSet> {
-        let change_set = self.change_log.history.pop()?;
-        
-        for (pos, tile) in change_set.tiles.iter() {
-            match tile.0 {
-                Some(color) => self.board.set(pos, &color),
-                None => self.board.unset(pos)
-            }
-        }
-        self.current_player = change_set.player;
+    pub fn current_player(&self) -> Color {
+        self.current_player
+    }
 
-        Some(change_set)
+    /* The moves played so far, oldest first */
+    pub fn history(&self) -> &[Move] {
+        &self.history
     }
 
     pub fn summary(&self) -> ChangeSet {
@@ -107,25 +104,140 @@ impl Game {
         }
     }
 
-    pub fn do_turn(&mut self, position: Position) -> Result {
-        if self.board.taken(&position) { return self.error("Position already taken"); }
-        
-        let flip_positions = self.board.calculate_flip_positions(&position, &self.current_player);
-        if flip_positions.is_empty() { return self.error("You must flip at least one tile"); }
-        
-        for pos in flip_positions.into_iter() {
-            self.change_log.push_tile_change(pos);
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot{
+            size: self.board.size,
+            tiles: self.board.iter_all_positions().map(|pos| self.board.get(&pos)).collect(),
+            player: self.current_player
         }
-        self.change_log.push_tile_change(position);
-        self.change_log.push_player_change();
+    }
+
+    /* Plays position for the current player without building a change set - no heap allocation involved */
+    pub fn make_move(&mut self, position: Position) -> std::result::Result<Move, &'static str> {
+        if !self.board.size.contains(&position) { return Err("Position is outside the board"); }
+        if self.board.taken(&position) { return Err("Position already taken"); }
 
-        Ok(self.flush_changes())
+        let flips = self.board.calculate_flip_mask(&position, &self.current_player);
+        if flips.is_empty() { return Err("You must flip at least one tile"); }
+
+        let mv = Move{ position, player: self.current_player, flips };
+        self.board.apply(&mv);
+        self.current_player = self.current_player.opposite();
+        self.history.push(mv);
+        Ok(mv)
+    }
+
+    /* Takes back the last move, returning it - "None" if there's nothing to take back */
+    pub fn unmake_move(&mut self) -> Option<Move> {
+        let mv = self.history.pop()?;
+        self.board.revert(&mv);
+        self.current_player = mv.player;
+        Some(mv)
+    }
+
+    /* Hands the turn over - only allowed when the current player has no legal move */
+    pub fn pass(&mut self) -> std::result::Result<(), &'static str> {
+        if self.board.legal_moves(self.current_player).next().is_some() { return Err("You can't pass while you have a legal move"); }
+        self.current_player = self.current_player.opposite();
+        Ok(())
+    }
+
+    /* Whether neither player has a legal move left */
+    pub fn finished(&self) -> bool {
+        self.board.legal_moves(self.current_player).next().is_none() && self.board.legal_moves(self.current_player.opposite()).next().is_none()
+    }
+
+    pub fn do_turn(&mut self, position: Position) -> Result {
+        let mv = self.make_move(position)?;
+        Ok(ChangeSet::of_move(&mv, &self.board, self.current_player))
     }
 
     pub fn cancel(&mut self) -> Result {
-        match self.revoke_changes() {
-            Some(change_set) => Ok(change_set),
-            None => self.error("No more moves to cancel")
+        match self.unmake_move() {
+            Some(mv) => Ok(ChangeSet::of_undo(&mv, &self.board)),
+            None => Err(String::from("No more moves to cancel"))
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::board::{Board, Tile, Color};
+    use super::super::position::{Position, Size};
+    use super::{Game, Variant};
+
+    #[test]
+    fn make_and_unmake_move_test() {
+        let mut game = Game::new();
+        let mv = game.make_move(Position{x: 3, y: 4}).unwrap();
+        assert_eq!(mv.player, Color::Black);
+        assert_eq!(mv.flips.count(), 1);
+        assert_eq!(game.current_player(), Color::White);
+        assert_eq!(game.board().get(&Position{x: 4, y: 4}), Tile(Some(Color::Black)));
+
+        assert_eq!(game.unmake_move(), Some(mv));
+        assert_eq!(game.current_player(), Color::Black);
+        assert_eq!(game.board().get(&Position{x: 4, y: 4}), Tile(Some(Color::White)));
+        assert!(!game.board().taken(&Position{x: 3, y: 4}));
+        assert_eq!(game.unmake_move(), None);
+    }
+
+    #[test]
+    fn make_move_test_illegal() {
+        let mut game = Game::new();
+        assert_eq!(game.make_move(Position{x: 4, y: 4}), Err("Position already taken"));
+        assert_eq!(game.make_move(Position{x: 0, y: 0}), Err("You must flip at least one tile"));
+        assert_eq!(game.make_move(Position{x: 10, y: 0}), Err("Position is outside the board"));
+        assert_eq!(game.make_move(Position{x: 0, y: 10}), Err("Position is outside the board"));
+        assert_eq!(game.current_player(), Color::Black);
+    }
+
+    #[test]
+    fn do_turn_and_cancel_test() {
+        let mut game = Game::new();
+        let change_set = game.do_turn(Position{x: 3, y: 4}).unwrap();
+        assert_eq!(change_set.player, Color::White);
+        assert_eq!(change_set.tiles.len(), 2);
+        assert_eq!(change_set.tiles[&Position{x: 3, y: 4}], Tile(Some(Color::Black)));
+        assert_eq!(change_set.tiles[&Position{x: 4, y: 4}], Tile(Some(Color::Black)));
+
+        let change_set = game.cancel().unwrap();
+        assert_eq!(change_set.player, Color::Black);
+        assert_eq!(change_set.tiles[&Position{x: 3, y: 4}], Tile(None));
+        assert_eq!(change_set.tiles[&Position{x: 4, y: 4}], Tile(Some(Color::White)));
+        assert!(game.cancel().is_err());
+    }
+
+    #[test]
+    fn pass_test() {
+        let mut game = Game::new();
+        assert!(game.pass().is_err());
+
+        let mut board = Board::new(4, 4);
+        board.set(&Position{x: 0, y: 0}, &Color::White);
+        board.set(&Position{x: 1, y: 1}, &Color::Black);
+        let mut game = Game::with_board(board, Color::Black);
+        assert_eq!(game.pass(), Ok(()));
+        assert_eq!(game.current_player(), Color::White);
+        assert!(game.make_move(Position{x: 2, y: 2}).is_ok());
+    }
+
+    #[test]
+    fn start_test() {
+        let game = Game::start(Size{ width: 6, height: 4 }, Variant::Parallel).unwrap();
+        let tiles: Vec<Tile> = [(2, 1), (2, 2), (3, 1), (3, 2)].iter().map(|(x, y)| game.board().get(&Position{x: *x, y: *y})).collect();
+        assert_eq!(tiles, vec![Tile(Some(Color::White)), Tile(Some(Color::White)), Tile(Some(Color::Black)), Tile(Some(Color::Black))]);
+        assert_eq!(Game::start(Size{ width: 10, height: 10 }, Variant::Standard).unwrap().snapshot(), Game::new().snapshot());
+        assert!(Game::start(Size{ width: 7, height: 8 }, Variant::Standard).is_err());
+        assert!(Game::start(Size{ width: 12, height: 12 }, Variant::Standard).is_err());
+    }
+
+    #[test]
+    fn finished_test() {
+        assert!(!Game::new().finished());
+        let mut board = Board::new(4, 4);
+        board.set(&Position{x: 0, y: 0}, &Color::Black);
+        board.set(&Position{x: 3, y: 3}, &Color::White);
+        assert!(Game::with_board(board, Color::White).finished());
+    }
+}
@@ -1,53 +1,1063 @@
 use super::game;
 use super::game::Game;
 use super::position::Position;
+use super::board::{Color, Move};
+use super::search;
+use super::search::SearchConfig;
+use super::analysis::{Analysis, PerColor, Statistics};
+use super::json::Json;
+use super::parser::ParsingError;
+use super::rooms::{Event, GameOptions, Player, Rooms};
+use super::sessions::Sessions;
+use super::storage::{Record, Storage};
+use std::convert::TryFrom;
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Encoding {
+    Text,                                                                   /* The original ";"-separated format */
+    Json,
+    Binary                                                                  /* Compact, length-prefixed frames instead of lines */
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+/* How colors and tiles are written in the text encoding. JSON always names them */
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Palette {
+    Rgb{ black: Rgb, white: Rgb, empty: Rgb },                              /* "r.g.b" triples, distinct from each other */
+    Symbolic                                                                /* "B", "W" and "." */
+}
+
+impl Palette {
+    /* "None" unless the three colors differ - otherwise clients couldn't tell the tiles apart */
+    pub fn rgb(black: Rgb, white: Rgb, empty: Rgb) -> Option<Palette> {
+        if black == white || black == empty || white == empty { return None; }
+        Some(Palette::Rgb{ black, white, empty })
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Palette {
+        Palette::Rgb{ black: Rgb(0, 0, 0), white: Rgb(255, 255, 255), empty: Rgb(128, 128, 128) }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Setting {
+    Statistics(bool),                                                       /* Whether updates carry the board statistics */
+    Encoding(Encoding),                                                     /* How requests and responses are written, starting with the response to this request */
+    Snapshots(bool),                                                        /* Whether the whole board is sent as a compact Snapshot rather than an Update */
+    FullBoards(bool),                                                       /* Whether moves are answered with the whole board rather than the changes */
+    Palette(Palette)                                                        /* How players and tiles are written in the text encoding */
+}
+
+/*
+The version of the protocol spoken by this server. Version 1 is the original protocol, assumed for clients which never say Hello.
+Bump it whenever a change to the protocol could surprise a client written against the previous version.
+*/
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/* Optional features a client may ask for in its Hello */
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Capability {
+    Json,                                                                   /* Setting::Encoding(Encoding::Json) */
+    Snapshots,                                                              /* Setting::Snapshots(true) */
+    FullBoards,                                                             /* Setting::FullBoards(true) */
+    Statistics,                                                             /* Setting::Statistics(true) */
+    Hints,                                                                  /* Request::Hint - always available, so never accepted */
+    Analysis,                                                               /* Request::Analyze - always available, so never accepted */
+    Binary                                                                  /* Setting::Encoding(Encoding::Binary) */
+}
+
+/* Read-only questions about the running game, so a client can resync without changing it */
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Query {
+    Board,                                                                  /* The whole board, as configured for this connection */
+    Player,                                                                 /* Whose turn it is */
+    Score,                                                                  /* The number of discs of each color */
+    MoveNumber,                                                             /* The number of moves played so far */
+    History,                                                                /* Every move played so far, oldest first */
+    LastMove                                                                /* The last move played - none at the start of the game */
+}
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum Request {
     Start,                                                                  /* Start of the game */
     DoTurn(Position),                                                       /* A move was played */
-    Cancel                                                                  /* Cancel last operation */
+    Cancel,                                                                 /* Cancel last operation */
+    Hint,                                                                   /* Ask the engine for a move */
+    Analyze,                                                                /* Ask for an analysis of the current board */
+    Set(Setting),                                                           /* Change a setting of this connection */
+    Create(GameOptions),                                                    /* Open a shared game, listed in the lobby for another connection to join, playing Black */
+    Join(String),                                                           /* Join the shared game with the given code */
+    Lobby,                                                                  /* List the shared games waiting for a second player */
+    Match(GameOptions),                                                     /* Join the longest waiting shared game with these options - or wait for someone else to */
+    Watch(String),                                                          /* Watch the shared game with the given code, without playing */
+    Leave,                                                                  /* Leave the shared game - or stop watching it */
+    Session,                                                                /* Ask for this connection's session token */
+    Resume(String),                                                         /* Take over the game of a dropped connection, by its session token */
+    Archive,                                                                /* List the finished games kept in storage */
+    Load(u64),                                                              /* Look at a finished game kept in storage, by its id */
+    Ping,                                                                   /* Keep an idle connection from being closed */
+    Query(Query),                                                           /* Ask about the running game without changing it */
+    Hello(u32, Vec<Capability>)                                             /* The client's protocol version and the capabilities it asks for. Unknown capabilities are left out */
 }
 
+#[derive(Debug, PartialEq, Clone)]
 pub enum Response {
-    Update(game::ChangeSet),                                                /* A board update */
+    Update(game::ChangeSet, Option<PerColor<Statistics>>),                  /* A board update, with the statistics of the board if asked for */
+    Snapshot(game::Snapshot),                                               /* The whole board */
+    Hint(Option<Position>),                                                 /* The engine's move - "None" if there is no legal move */
+    Analysis(Analysis),                                                     /* Analysis of the current board */
+    Ok,                                                                     /* The request was carried out, nothing else to tell */
+    Hello(u32, Vec<Capability>),                                            /* The server's protocol version and the capabilities it accepted */
+    Room(String, Color),                                                    /* The shared game's code, and the color played on this connection */
+    Lobby(Vec<(String, GameOptions)>),                                      /* The shared games waiting for a second player, oldest first, by code */
+    Spectators(usize),                                                      /* The number of connections watching the shared game */
+    Session(String),                                                        /* The token to resume this connection's game with, should it drop */
+    Archive(Vec<(u64, GameOptions)>),                                       /* The finished games kept in storage, by id */
+    Pong,                                                                   /* The answer to a ping */
+    Player(Color),                                                          /* Whose turn it is */
+    Score(PerColor<usize>),                                                 /* The number of discs of each color */
+    MoveNumber(usize),                                                      /* The number of moves played so far */
+    Moves(Vec<(Position, Color)>),                                          /* Moves played, oldest first, each with the player who made it */
     Error(String)                                                           /* Unrecoverable error */
 }
 
+/* A connection's place in a shared game */
+struct Seat {
+    code: String,
+    color: Color
+}
+
+/* A connection watching a shared game */
+struct Spectator {
+    code: String,
+    id: u64
+}
+
+/* A game left behind by a dropped connection, with the connection's place in it */
+pub struct Session {
+    game: Arc<Mutex<Game>>,
+    seat: Option<Seat>,
+    watching: Option<Spectator>,
+    record: Option<Record>,
+    unsaved: Option<GameOptions>
+}
+
+impl Session {
+    /* Gives up the place in a shared game of a session nobody resumed */
+    pub fn close(self, rooms: &mut Rooms) {
+        if let Some(seat) = self.seat { rooms.leave(&seat.code, seat.color); }
+        if let Some(spectator) = self.watching { rooms.unwatch(&spectator.code, spectator.id); }
+    }
+}
+
 pub struct Handler {
-    game: Option<Game>       /* "None" indicates no game is currently run - no game was started at all or no game was started since last error */
+    game: Option<Arc<Mutex<Game>>>,     /* "None" indicates no game is currently run - no game was started at all or no game was started since last error */
+    seat: Option<Seat>,                 /* "None" unless the game is shared with another connection */
+    watching: Option<Spectator>,        /* "None" unless the game is one watched, played by other connections */
+    record: Option<Record>,             /* How a game played alone is stored - shared games' records are kept by their rooms */
+    unsaved: Option<GameOptions>,       /* A game played alone which isn't stored yet - it's given a record once there's a move to store */
+    storage: Option<Arc<Storage>>,      /* Where games are kept across restarts - "None" if they aren't */
+    rooms: Arc<Mutex<Rooms>>,
+    outbox: Sender<Event>,              /* Where the other player's doings are pushed, for the connection to pass on through handle_event */
+    sessions: Arc<Mutex<Sessions>>,
+    token: String,                      /* The game is parked under this token when the connection drops */
+    token_told: bool,                   /* Whether the connection knows its token - it's pushed along with the first game otherwise */
+    search_config: SearchConfig,
+    version: u32,                       /* The protocol version agreed on in Hello - the original one until then */
+    include_statistics: bool,
+    encoding: Encoding,
+    snapshots: bool,
+    full_boards: bool,
+    palette: Palette
+}
+
+fn lock_game(game: &Mutex<Game>) -> std::sync::MutexGuard<'_, Game> {
+    game.lock().expect("Impossible event: a thread panicked while holding a game (handler::lock_game)")
+}
+
+fn lock_sessions(sessions: &Mutex<Sessions>) -> std::sync::MutexGuard<'_, Sessions> {
+    sessions.lock().expect("Impossible event: a thread panicked while holding the sessions (handler::lock_sessions)")
+}
+
+impl Drop for Handler {
+    fn drop(&mut self) {
+        self.leave();
+    }
 }
 
 impl Handler {
+    /* A handler of its own, whose shared games nobody else can join, and whose games nobody can resume */
     pub fn new() -> Handler {
-        Handler{ game: None }
+        Handler::connected(Arc::new(Mutex::new(Rooms::new())), Arc::new(Mutex::new(Sessions::new(Duration::from_secs(0)))), None, channel().0)
+    }
+
+    /* A handler sharing games through rooms, telling about the other player through outbox, parking its game in sessions and keeping it in storage */
+    pub fn connected(rooms: Arc<Mutex<Rooms>>, sessions: Arc<Mutex<Sessions>>, storage: Option<Arc<Storage>>, outbox: Sender<Event>) -> Handler {
+        let token = lock_sessions(&sessions).new_token();
+        Handler{
+            game: None,
+            seat: None,
+            watching: None,
+            record: None,
+            unsaved: None,
+            storage,
+            rooms,
+            outbox,
+            sessions,
+            token,
+            token_told: false,
+            search_config: SearchConfig::default(),
+            version: 1,
+            include_statistics: false,
+            encoding: Encoding::Text,
+            snapshots: false,
+            full_boards: false,
+            palette: Palette::default()
+        }
+    }
+
+    /* How this connection's hints are searched for */
+    pub fn search_with(&mut self, search_config: SearchConfig) {
+        self.search_config = search_config;
+    }
+
+    fn update(&self, change_set: game::ChangeSet) -> Response {
+        let statistics = match &self.game {
+            Some(game) if self.include_statistics => Some(lock_game(game).board().statistics()),
+            _ => None
+        };
+        Response::Update(change_set, statistics)
+    }
+
+    /* The whole board of the running game, as configured for this connection */
+    fn full_board(&self) -> Response {
+        let game = self.game.as_ref().expect("Impossible event: full_board called with no game running (Handler::full_board)");
+        if self.snapshots { return Response::Snapshot(lock_game(game).snapshot()); }
+        /* update locks the game again for the statistics, so it must be let go first */
+        let summary = lock_game(game).summary();
+        self.update(summary)
+    }
+
+    /* The response to a move or a cancel, which made the given changes */
+    fn changed(&self, change_set: game::ChangeSet) -> Response {
+        if self.full_boards { self.full_board() } else { self.update(change_set) }
     }
 
-    fn result_of(&mut self, task: impl Fn(&mut Game)->game::Result) -> Result<game::ChangeSet, String> {
-        self.game.as_mut().map_or(Err(String::from("No game is running")), task)
+    fn apply(&mut self, setting: Setting) {
+        match setting {
+            Setting::Statistics(include) => self.include_statistics = include,
+            Setting::Encoding(encoding) => self.encoding = encoding,
+            Setting::Snapshots(snapshots) => self.snapshots = snapshots,
+            Setting::FullBoards(full_boards) => self.full_boards = full_boards,
+            Setting::Palette(palette) => self.palette = palette
+        }
+    }
+
+    /*
+    Agrees on the lower of the two versions. The settings capabilities control start over from their defaults, so a second Hello replaces the first.
+    Only capabilities which change something are accepted - and none before version 2, which introduced them.
+    */
+    fn hello(&mut self, version: u32, capabilities: Vec<Capability>) -> Result<Response, String> {
+        if version == 0 { return Err(String::from("Unsupported protocol version")); }
+        self.version = version.min(PROTOCOL_VERSION);
+        self.include_statistics = false;
+        self.encoding = Encoding::Text;
+        self.snapshots = false;
+        self.full_boards = false;
+
+        let mut accepted: Vec<Capability> = Vec::new();
+        let offered = if self.version >= 2 { capabilities } else { Vec::new() };
+        for capability in offered {
+            let setting = match capability {
+                Capability::Json => Setting::Encoding(Encoding::Json),
+                Capability::Binary => Setting::Encoding(Encoding::Binary),
+                Capability::Snapshots => Setting::Snapshots(true),
+                Capability::FullBoards => Setting::FullBoards(true),
+                Capability::Statistics => Setting::Statistics(true),
+                Capability::Hints | Capability::Analysis => continue
+            };
+            /* Of several encodings, the last one asked for wins */
+            if let Setting::Encoding(_) = setting { accepted.retain(|accepted| *accepted != Capability::Json && *accepted != Capability::Binary); }
+            if !accepted.contains(&capability) { accepted.push(capability); }
+            self.apply(setting);
+        }
+        Ok(Response::Hello(self.version, accepted))
+    }
+
+    fn query(&self, query: Query) -> Result<Response, String> {
+        let game = self.game.as_ref().ok_or_else(|| String::from("No game is running"))?;
+        let played = |mv: &Move| (mv.position, mv.player);
+        Ok(match query {
+            Query::Board => self.full_board(),
+            Query::Player => Response::Player(lock_game(game).current_player()),
+            Query::Score => Response::Score(lock_game(game).board().discs()),
+            Query::MoveNumber => Response::MoveNumber(lock_game(game).history().len()),
+            Query::History => Response::Moves(lock_game(game).history().iter().map(played).collect()),
+            Query::LastMove => Response::Moves(lock_game(game).history().last().map(played).into_iter().collect())
+        })
+    }
+
+    fn result_of<T>(&mut self, task: impl FnOnce(&mut Game)->Result<T, String>) -> Result<T, String> {
+        self.game.as_ref().map_or(Err(String::from("No game is running")), |game| task(&mut lock_game(game)))
+    }
+
+    fn lock_rooms(&self) -> std::sync::MutexGuard<'_, Rooms> {
+        self.rooms.lock().expect("Impossible event: a thread panicked while holding the rooms (Handler::lock_rooms)")
+    }
+
+    /* Gives up this connection's seat in a shared game, or stops watching one, along with the game */
+    fn leave(&mut self) {
+        if self.seat.is_none() && self.watching.is_none() { return; }
+        let rooms = Arc::clone(&self.rooms);
+        let mut rooms = rooms.lock().expect("Impossible event: a thread panicked while holding the rooms (Handler::leave)");
+        self.leave_in(&mut rooms);
+    }
+
+    /* The counterpart of leave for callers already holding the rooms */
+    fn leave_in(&mut self, rooms: &mut Rooms) {
+        if let Some(seat) = self.seat.take() {
+            rooms.leave(&seat.code, seat.color);
+            self.game = None;
+        }
+        if let Some(spectator) = self.watching.take() {
+            rooms.unwatch(&spectator.code, spectator.id);
+            self.game = None;
+        }
+    }
+
+    /* Whether this connection is seated in, or watching, the room with this code */
+    fn in_room(&self, code: &str) -> bool {
+        let code = code.to_ascii_uppercase();
+        self.seat.as_ref().is_some_and(|seat| seat.code == code) || self.watching.as_ref().is_some_and(|spectator| spectator.code == code)
+    }
+
+    /* This connection, as seated in a room */
+    fn player(&self) -> Player {
+        Player{ outbox: self.outbox.clone(), token: self.token.clone() }
+    }
+
+    /* A record for a new game played alone - "None" if games aren't stored */
+    fn new_record(&self, options: GameOptions) -> Option<Record> {
+        let id = self.storage.as_ref()?.new_id();
+        Some(Record{ id, options, code: None, players: PerColor{ black: Some(self.token.clone()), white: None }, moves: Vec::new(), finished: false })
+    }
+
+    /*
+    Writes the running game to storage, if it's kept there - failing to do so doesn't stop the game.
+    Only taking the game's moves holds anything shared - the file is written after letting go of it
+    */
+    fn save(&mut self) {
+        let storage = match &self.storage { Some(storage) => Arc::clone(storage), None => return };
+        if let Some(options) = self.unsaved.take() { self.record = self.new_record(options); }
+        let record = match &self.seat {
+            Some(seat) => self.lock_rooms().record(&seat.code),
+            None => self.record.clone()
+        };
+        if let (Some(mut record), Some(game)) = (record, &self.game) {
+            let version = {
+                let game = lock_game(game);
+                record.moves = game.history().iter().map(|mv| (mv.position, mv.player)).collect();
+                record.finished = record.finished || game.finished();
+                storage.version()
+            };
+            if let Err(e) = storage.save(&record, version) { println!("Error while saving game {}: {}", record.id, e); }
+        }
+    }
+
+    fn archive(&self) -> Vec<(u64, GameOptions)> {
+        self.storage.as_ref().map_or(Vec::new(), |storage| storage.finished())
+    }
+
+    /* A finished game becomes this connection's own, to look through - changes to it aren't stored */
+    fn load(&mut self, id: u64) -> Result<Response, String> {
+        let record = self.storage.as_ref().map(|storage| storage.load(id));
+        let game = match record {
+            Some(Ok(record)) if record.finished => record.replay()?,
+            _ => return Err(format!("No finished game with id {}", id))
+        };
+        self.leave();
+        self.game = Some(Arc::new(Mutex::new(game)));
+        self.record = None;
+        self.unsaved = None;
+        Ok(self.full_board())
+    }
+
+    /* Takes a seat in a new room - which only shows up in the lobby if listed */
+    fn create(&mut self, rooms: &mut Rooms, options: GameOptions, listed: bool) -> Result<Response, String> {
+        let record = self.storage.as_ref().map(|storage| storage.new_id());
+        let (code, game) = rooms.create(self.player(), options, listed, record)?;
+        self.leave_in(rooms);
+        self.game = Some(game);
+        self.record = None;
+        self.unsaved = None;
+        self.seat = Some(Seat{ code: code.clone(), color: Color::Black });
+        Ok(Response::Room(code, Color::Black))
+    }
+
+    fn join(&mut self, rooms: &mut Rooms, code: &str) -> Result<Response, String> {
+        if self.in_room(code) { return Err(String::from("You're already in this game")); }
+        let (code, color, game) = rooms.join(code, self.player())?;
+        self.leave_in(rooms);
+        self.game = Some(game);
+        self.record = None;
+        self.unsaved = None;
+        self.seat = Some(Seat{ code: code.clone(), color });
+        Ok(Response::Room(code, color))
+    }
+
+    /* Starts watching a room - the board is left to the caller, to build once the rooms are let go */
+    fn watch(&mut self, rooms: &mut Rooms, code: &str) -> Result<(), String> {
+        if self.in_room(code) { return Err(String::from("You're already in this game")); }
+        let (code, id, game) = rooms.watch(code, self.outbox.clone())?;
+        self.leave_in(rooms);
+        self.game = Some(game);
+        self.record = None;
+        self.unsaved = None;
+        self.watching = Some(Spectator{ code, id });
+        Ok(())
+    }
+
+    /*
+    Handlers for the players of a game kept in storage, as if they had been playing it all along - to be parked right away,
+    so the players can resume it. A game played alone has a single one.
+    */
+    pub fn restored(rooms: Arc<Mutex<Rooms>>, sessions: Arc<Mutex<Sessions>>, storage: Option<Arc<Storage>>, record: &Record) -> Result<Vec<Handler>, String> {
+        let game = Arc::new(Mutex::new(record.replay()?));
+        let players = [Color::Black, Color::White].iter().filter_map(|color| record.players.get(color).clone().map(|token| (*color, token)));
+        Ok(players.map(|(color, token)| {
+            let mut handler = Handler::connected(Arc::clone(&rooms), Arc::clone(&sessions), storage.clone(), channel().0);
+            handler.token = token;
+            handler.game = Some(Arc::clone(&game));
+            match &record.code {
+                Some(code) => {
+                    handler.lock_rooms().restore(code, color, handler.player(), Arc::clone(&game), record.options, record.id);
+                    handler.seat = Some(Seat{ code: code.clone(), color });
+                },
+                None => handler.record = Some(record.clone())
+            }
+            handler
+        }).collect())
+    }
+
+    /* Hands over the game, with this connection's place in it, leaving the connection without one */
+    pub fn session(&mut self) -> Option<Session> {
+        let game = self.game.take()?;
+        Some(Session{ game, seat: self.seat.take(), watching: self.watching.take(), record: self.record.take(), unsaved: self.unsaved.take() })
+    }
+
+    /*
+    Parks the game of a dropped connection under its token, until it's resumed or its grace period is over.
+    Returns whether it was parked - not if there's no game, or too many are parked already, in which case the game is closed.
+    */
+    pub fn park(&mut self) -> bool {
+        let session = match self.session() { Some(session) => session, None => return false };
+        let mut sessions = lock_sessions(&self.sessions);
+        if sessions.full(&self.token) {
+            drop(sessions);
+            println!("Closing a dropped connection's game, too many are waiting to be resumed");
+            session.close(&mut self.lock_rooms());
+            return false;
+        }
+        sessions.park(&self.token, session, Instant::now());
+        true
+    }
+
+    /*
+    Takes over a parked game, pushing events about it to this connection from now on.
+    The current game is only given up once the parked one was found, so a mistyped token costs nothing
+    */
+    fn resume(&mut self, token: &str) -> Result<Response, String> {
+        let session = lock_sessions(&self.sessions).resume(token).ok_or_else(|| String::from("No game to resume with this token"))?;
+        let present = {
+            let mut rooms = self.lock_rooms();
+            let seated = session.seat.as_ref().is_none_or(|seat| rooms.rebind(&seat.code, seat.color, self.outbox.clone()));
+            let watching = session.watching.as_ref().is_none_or(|spectator| rooms.rewatch(&spectator.code, spectator.id, self.outbox.clone()));
+            seated && watching
+        };
+        /* Spectators of a game whose players both left have nothing to come back to */
+        if !present { return Err(String::from("The game was closed")); }
+        self.leave();
+        self.game = Some(session.game);
+        self.seat = session.seat;
+        self.watching = session.watching;
+        self.record = session.record;
+        self.unsaved = session.unsaved;
+        self.token = token.to_string();
+        self.token_told = true;
+        Ok(self.full_board())
+    }
+
+    /*
+    Enters another room in place of the current one. The task takes the new place before giving up the old one,
+    so a request that fails leaves the connection where it was. The rooms are held throughout, so two players can't take the same seat
+    */
+    fn enter<T>(&mut self, task: impl FnOnce(&mut Handler, &mut Rooms) -> Result<T, String>) -> Result<T, String> {
+        let rooms = Arc::clone(&self.rooms);
+        let mut rooms = rooms.lock().expect("Impossible event: a thread panicked while holding the rooms (Handler::enter)");
+        task(self, &mut rooms)
+    }
+
+    /* In a shared game, only the player whose turn it is may move - and only once the opponent has arrived */
+    fn do_turn(&mut self, position: Position) -> Result<Response, String> {
+        let color = self.seat.as_ref().map(|seat| seat.color);
+        if let (Some(seat), Some(game)) = (&self.seat, &self.game) {
+            let mut rooms = self.lock_rooms();
+            if !rooms.ready(&seat.code) { return Err(String::from("Waiting for an opponent")); }
+            let now = Instant::now();
+            let flagged = rooms.flag(&seat.code, now);
+            /* Whose turn it is comes first - until then, the clock running is the opponent's */
+            let on_time = match lock_game(game).current_player() {
+                player if player == seat.color => rooms.check_clock(&seat.code, seat.color, now),
+                _ => Err(String::from("It's not your turn"))
+            };
+            drop(rooms);
+            if flagged { self.save(); }
+            on_time?;
+        }
+        let change_set = self.result_of(|game| {
+            if color.is_some_and(|color| color != game.current_player()) { return Err(String::from("It's not your turn")); }
+            game.do_turn(position)
+        })?;
+        let change_set = match &self.seat {
+            Some(seat) => self.lock_rooms().moved(&seat.code, seat.color, change_set, Instant::now()),
+            None => change_set
+        };
+        self.save();
+        Ok(self.changed(change_set))
+    }
+
+    /*
+    Ends the shared game if the player to move ran out of time - checked whenever someone in the room asks about it,
+    so the opponent of a player who stopped moving can claim the game
+    */
+    fn flag(&mut self) {
+        let code = match (&self.seat, &self.watching) {
+            (Some(seat), _) => &seat.code,
+            (None, Some(spectator)) => &spectator.code,
+            (None, None) => return
+        };
+        let flagged = self.lock_rooms().flag(code, Instant::now());
+        if flagged { self.save(); }
+    }
+
+    /* The message telling this connection about the other player in its shared game, in the current encoding */
+    pub fn handle_event(&mut self, event: Event) -> Vec<u8> {
+        let response = match event {
+            Event::Joined if self.game.is_some() => self.full_board(),
+            Event::Changed(change_set) if self.game.is_some() => self.changed(change_set),
+            Event::Left if self.watching.is_some() => Response::Error(String::from("A player left the game")),
+            Event::Left => Response::Error(String::from("Your opponent left the game")),
+            Event::Spectators(count) => Response::Spectators(count),
+            Event::OutOfTime(_) if self.watching.is_some() => Response::Error(String::from("A player ran out of time")),
+            Event::OutOfTime(color) if self.seat.as_ref().is_some_and(|seat| seat.color == color) => Response::Error(String::from("You ran out of time")),
+            Event::OutOfTime(_) => Response::Error(String::from("Your opponent ran out of time")),
+            Event::Closed => {
+                self.watching = None;
+                self.game = None;
+                Response::Error(String::from("The game was closed"))
+            },
+            Event::Token => Response::Session(self.token.clone()),
+            Event::Joined | Event::Changed(_) => Response::Error(String::from("No game is running"))
+        };
+        self.encode(None, &response)
     }
 
     fn handle_parsed(&mut self, request: Request) -> Response {
+        let search_config = self.search_config;
         let result = match request {
             Request::Start => {
-                self.game = Some(Game::new());
-                Ok(self.game.as_ref().unwrap().summary())
+                self.leave();
+                self.game = Some(Arc::new(Mutex::new(Game::new())));
+                self.record = None;
+                self.unsaved = Some(GameOptions::default()).filter(|_| self.storage.is_some());
+                Ok(self.full_board())
+            },
+            Request::DoTurn(_) | Request::Cancel if self.watching.is_some() => Err(String::from("Spectators can't play")),
+            Request::DoTurn(position) => self.do_turn(position),
+            Request::Cancel if self.seat.is_some() => Err(String::from("Moves can't be taken back in a shared game")),
+            Request::Cancel => self.result_of(|game| game.cancel()).map(|change_set| {
+                self.save();
+                self.changed(change_set)
+            }),
+            /* Nobody in a shared game gets help from the engine - spectators could pass it on to a player */
+            Request::Hint | Request::Analyze if self.seat.is_some() || self.watching.is_some() => {
+                Err(String::from("The engine doesn't help in a shared game"))
+            },
+            /* The search runs on a copy of the board, so the game isn't held up meanwhile - in a shared game, the opponent may move */
+            Request::Hint => self.result_of(|game| Ok((game.board().clone(), game.current_player()))).map(|(board, player)| {
+                Response::Hint(search::best_move(&board, player, &search_config).map(|result| result.position))
+            }),
+            Request::Analyze => self.result_of(|game| Ok(Response::Analysis(Analysis::of(game.board())))),
+            Request::Set(setting) => {
+                self.apply(setting);
+                Ok(Response::Ok)
+            },
+            Request::Create(options) => self.enter(|handler, rooms| handler.create(rooms, options, true)),
+            Request::Join(code) => self.enter(|handler, rooms| handler.join(rooms, &code)),
+            Request::Lobby => Ok(Response::Lobby(self.lock_rooms().open())),
+            /* A player asking for a match again isn't paired with themselves */
+            Request::Match(options) => self.enter(|handler, rooms| match rooms.find_match(&options).filter(|code| !handler.in_room(code)) {
+                Some(code) => handler.join(rooms, &code),
+                None => handler.create(rooms, options, false)
+            }),
+            Request::Watch(code) => self.enter(|handler, rooms| handler.watch(rooms, &code)).map(|_| self.full_board()),
+            Request::Leave if self.seat.is_some() || self.watching.is_some() => {
+                self.leave();
+                Ok(Response::Ok)
+            },
+            Request::Leave => Err(String::from("You're not in a shared game")),
+            Request::Session => {
+                self.token_told = true;
+                Ok(Response::Session(self.token.clone()))
             },
-            Request::DoTurn(position) => self.result_of(|game| game.do_turn(position)),
-            Request::Cancel => self.result_of(|game| game.cancel())
+            Request::Resume(token) => self.resume(&token),
+            Request::Archive => Ok(Response::Archive(self.archive())),
+            Request::Load(id) => self.load(id),
+            Request::Ping => {
+                self.flag();
+                Ok(Response::Pong)
+            },
+            Request::Query(query) => {
+                self.flag();
+                self.query(query)
+            },
+            Request::Hello(version, capabilities) => self.hello(version, capabilities)
         };
-        
-        match result {
-            Ok(change_set) => Response::Update(change_set),
-            Err(error) => Response::Error(error)
+
+        /* Pushed rather than answered, so it follows the response - a game the connection could lose must come with a way to resume it */
+        if self.game.is_some() && !self.token_told { self.token_told = self.outbox.send(Event::Token).is_ok(); }
+        result.unwrap_or_else(Response::Error)
+    }
+
+    /* The encoding the next request is expected in - and the last response was written in */
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    fn decode(&self, request_raw: &[u8]) -> (Option<Json>, Result<Request, ParsingError>) {
+        match self.encoding {
+            Encoding::Text => {
+                let (id, request) = Request::parse_tagged(&String::from_utf8_lossy(request_raw));
+                (id.map(Json::String), request)
+            },
+            Encoding::Json => Request::parse_json_tagged(&String::from_utf8_lossy(request_raw)),
+            Encoding::Binary => {
+                let (id, request) = Request::decode_binary(request_raw);
+                (id.map(|id| Json::Number(id as f64)), request)
+            }
         }
     }
 
-    pub fn handle_raw(&mut self, request_raw: String) -> String {
-        let response = match Request::parse(&request_raw) {
+    fn encode(&self, id: Option<Json>, response: &Response) -> Vec<u8> {
+        match self.encoding {
+            Encoding::Text => {
+                let id_raw = id.map(|id| id.as_str().map_or_else(|| id.stringify(), String::from));
+                response.stringify_tagged(id_raw.as_deref(), &self.palette).into_bytes()
+            },
+            Encoding::Json => response.stringify_json_tagged(id.as_ref()).into_bytes(),
+            Encoding::Binary => {
+                /* Binary ids are numbers - ids which aren't can't be echoed */
+                let id = id.and_then(|id| id.as_usize().or_else(|| id.as_str().and_then(|id| id.parse().ok())))
+                           .and_then(|id| u16::try_from(id).ok());
+                response.encode_binary(id)
+            }
+        }
+    }
+
+    /* Requests may carry an id, echoed in the response - even if the encoding was switched by that very request */
+    pub fn handle_message(&mut self, request_raw: &[u8]) -> Vec<u8> {
+        let (id, request) = self.decode(request_raw);
+        let response = match request {
             Ok(request) => self.handle_parsed(request),
             Err(parsing_error) => Response::Error(parsing_error.to_string())
         };
-        response.stringify()
+        self.encode(id, &response)
+    }
+
+    /* The response to a message which couldn't even be read, in the current encoding */
+    pub fn reject(&self, reason: String) -> Vec<u8> {
+        self.encode(None, &Response::Error(reason))
+    }
+
+    /* handle_message for the text encodings */
+    pub fn handle_raw(&mut self, request_raw: String) -> String {
+        String::from_utf8_lossy(&self.handle_message(request_raw.as_bytes())).into_owned()
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Handler, Record, Request, Response, Setting, Encoding};
+    use super::super::rooms::{Event, Rooms};
+    use super::super::sessions::Sessions;
+    use super::super::storage::Storage;
+    use std::time::{Duration, Instant};
+    use std::fs;
+    use std::sync::mpsc::channel;
+    use std::sync::{Arc, Mutex};
+    use super::super::game::ChangeSet;
+    use super::super::board::{Tile, Color};
+    use super::super::position::Position;
+
+    fn game_after_move() -> ChangeSet {
+        let tiles = vec![(Position{x: 3, y: 4}, Tile(Some(Color::Black))), (Position{x: 4, y: 4}, Tile(Some(Color::Black)))];
+        ChangeSet{ tiles: tiles.into_iter().collect(), player: Color::White }
+    }
+
+    #[test]
+    fn handle_raw_test_switch_encoding() {
+        let mut handler = Handler::new();
+        assert_eq!(handler.handle_raw(String::from("Cancel")), "Error;No game is running");
+        assert_eq!(handler.handle_raw(String::from("Set;Encoding,Json")), "{\"type\":\"Ok\"}");
+        assert_eq!(handler.handle_raw(String::from("{\"type\": \"Cancel\"}")), "{\"type\":\"Error\",\"message\":\"No game is running\"}");
+        assert_eq!(handler.handle_raw(String::from("{\"type\": \"Set\", \"setting\": \"Encoding\", \"value\": \"Text\"}")), "Ok");
+        assert_eq!(handler.handle_raw(String::from("Set;Statistics,On")), "Ok");
+    }
+
+    #[test]
+    fn handle_raw_test_golden_game() {
+        let mut handler = Handler::new();
+        assert_eq!(handler.handle_raw(String::from("Set;Snapshots,On")), "Ok");
+        assert_eq!(handler.handle_raw(String::from("Start")),
+                   "Snapshot;0.0.0,10,10,............................................WB........BW............................................");
+        assert_eq!(handler.handle_raw(String::from("DoTurn;3,4")), "Update;255.255.255,3.4:0.0.0|4.4:0.0.0");
+        assert_eq!(handler.handle_raw(String::from("DoTurn;3,3")), "Update;0.0.0,3.3:255.255.255|4.4:255.255.255");
+        assert_eq!(handler.handle_raw(String::from("Cancel")), "Update;255.255.255,3.3:128.128.128|4.4:0.0.0");
+        assert_eq!(handler.handle_raw(String::from("Set;Encoding,Json")), "{\"type\":\"Ok\"}");
+        assert_eq!(handler.handle_raw(String::from("{\"type\": \"Cancel\"}")),
+                   "{\"type\":\"Update\",\"player\":\"Black\",\"tiles\":[\
+                    {\"position\":{\"x\":3,\"y\":4},\"tile\":null},{\"position\":{\"x\":4,\"y\":4},\"tile\":\"White\"}]}");
+    }
+
+    #[test]
+    fn handle_raw_test_hello() {
+        let mut handler = Handler::new();
+        assert_eq!(handler.handle_raw(String::from("Hello;2;Snapshots,Teleport,FullBoards")), "Hello;2;Snapshots,FullBoards");
+        assert_eq!(handler.handle_raw(String::from("Start")),
+                   "Snapshot;0.0.0,10,10,............................................WB........BW............................................");
+        assert_eq!(handler.handle_raw(String::from("DoTurn;3,4")),
+                   "Snapshot;255.255.255,10,10,...........................................BBB........BW............................................");
+        assert_eq!(handler.handle_raw(String::from("Hello;7;Json")), "{\"type\":\"Hello\",\"version\":2,\"capabilities\":[\"Json\"]}");
+        assert_eq!(handler.handle_raw(String::from("{\"type\": \"Hello\", \"version\": 0, \"capabilities\": []}")),
+                   "{\"type\":\"Error\",\"message\":\"Unsupported protocol version\"}");
+    }
+
+    #[test]
+    fn handle_raw_test_hello_again() {
+        let mut handler = Handler::new();
+        assert_eq!(handler.handle_raw(String::from("Hello;2;Snapshots,Hints,Analysis,Binary,Json,Snapshots")), "{\"type\":\"Hello\",\"version\":2,\"capabilities\":[\"Snapshots\",\"Json\"]}");
+        assert_eq!(handler.handle_raw(String::from("{\"type\": \"Hello\", \"version\": 2, \"capabilities\": [\"Statistics\"]}")), "Hello;2;Statistics");
+        assert_eq!(handler.handle_raw(String::from("Hello;1;Snapshots")), "Hello;1;");
+        /* Neither a snapshot nor statistics */
+        let board = handler.handle_raw(String::from("Start"));
+        assert!(board.starts_with("Update;0.0.0,0.0:128.128.128|"));
+        assert_eq!(board.split(';').count(), 2);
+    }
+
+    #[test]
+    fn handle_raw_test_request_ids() {
+        let mut handler = Handler::new();
+        assert_eq!(handler.handle_raw(String::from("#1;Cancel")), "#1;Error;No game is running");
+        assert_eq!(handler.handle_raw(String::from("#2;Teleport")),
+                   "#2;Error;ParsingError! Could not parse \"Teleport\". Could not recognize request type.");
+        assert_eq!(handler.handle_raw(String::from("#3;Set;Encoding,Json")), "{\"id\":\"3\",\"type\":\"Ok\"}");
+        assert_eq!(handler.handle_raw(String::from("{\"id\": 4, \"type\": \"Hint\"}")),
+                   "{\"id\":4,\"type\":\"Error\",\"message\":\"No game is running\"}");
+        assert_eq!(handler.handle_raw(String::from("{\"id\": 5, \"type\": \"Set\", \"setting\": \"Encoding\", \"value\": \"Text\"}")), "#5;Ok");
+        assert_eq!(handler.handle_raw(String::from("Cancel")), "Error;No game is running");
+    }
+
+    #[test]
+    fn handle_raw_test_palette() {
+        let mut handler = Handler::new();
+        assert_eq!(handler.handle_raw(String::from("Set;Palette,Symbolic")), "Ok");
+        assert!(handler.handle_raw(String::from("Start")).starts_with("Update;B,0.0:.|1.0:.|"));
+        assert_eq!(handler.handle_raw(String::from("DoTurn;3,4")), "Update;W,3.4:B|4.4:B");
+        assert_eq!(handler.handle_raw(String::from("Set;Palette,10.20.30,200.200.200,0.128.0")), "Ok");
+        assert_eq!(handler.handle_raw(String::from("Cancel")), "Update;10.20.30,3.4:0.128.0|4.4:200.200.200");
+        assert_eq!(handler.handle_raw(String::from("Set;Snapshots,On")), "Ok");
+        assert!(handler.handle_raw(String::from("Start")).starts_with("Snapshot;10.20.30,10,10,....."));
+    }
+
+    #[test]
+    fn handle_raw_test_queries() {
+        let mut handler = Handler::new();
+        assert_eq!(handler.handle_raw(String::from("Query;Score")), "Error;No game is running");
+        assert_eq!(handler.handle_raw(String::from("Ping")), "Pong");
+        handler.handle_raw(String::from("Start"));
+        assert_eq!(handler.handle_raw(String::from("Query;LastMove")), "Moves;");
+        assert_eq!(handler.handle_raw(String::from("DoTurn;3,4")), "Update;255.255.255,3.4:0.0.0|4.4:0.0.0");
+        assert_eq!(handler.handle_raw(String::from("Query;Player")), "Player;255.255.255");
+        assert_eq!(handler.handle_raw(String::from("Query;Score")), "Score;4,1");
+        assert_eq!(handler.handle_raw(String::from("Query;MoveNumber")), "MoveNumber;1");
+        assert_eq!(handler.handle_raw(String::from("DoTurn;3,3")), "Update;0.0.0,3.3:255.255.255|4.4:255.255.255");
+        assert_eq!(handler.handle_raw(String::from("Query;History")), "Moves;3.4:0.0.0|3.3:255.255.255");
+        assert_eq!(handler.handle_raw(String::from("Query;LastMove")), "Moves;3.3:255.255.255");
+        assert_eq!(handler.handle_raw(String::from("Set;Snapshots,On")), "Ok");
+        assert_eq!(handler.handle_raw(String::from("Query;Board")),
+                   "Snapshot;0.0.0,10,10,.................................W.........BWB........BW............................................");
+        assert_eq!(handler.handle_raw(String::from("Query;MoveNumber")), "MoveNumber;2");
+    }
+
+    #[test]
+    fn handle_raw_test_shared_game() {
+        let rooms = Arc::new(Mutex::new(Rooms::new()));
+        let sessions = Arc::new(Mutex::new(Sessions::new(Duration::from_secs(60))));
+        let (black_outbox, black_events) = channel();
+        let (white_outbox, white_events) = channel();
+        let mut black = Handler::connected(Arc::clone(&rooms), Arc::clone(&sessions), None, black_outbox);
+        let mut white = Handler::connected(Arc::clone(&rooms), Arc::clone(&sessions), None, white_outbox);
+
+        let room = black.handle_raw(String::from("Create"));
+        let code = room.strip_prefix("Room;").and_then(|room| room.strip_suffix(",0.0.0")).unwrap().to_string();
+        let token = black.handle_event(black_events.try_recv().unwrap());
+        assert!(token.starts_with(b"Session;"));
+        assert_eq!(black.handle_raw(String::from("DoTurn;4,3")), "Error;Waiting for an opponent");
+        assert_eq!(black.handle_raw(String::from("Session")).as_bytes(), token.as_slice());
+        assert_eq!(white.handle_raw(format!("Join;{}", code.to_ascii_lowercase())), format!("Room;{},255.255.255", code));
+        assert!(white.handle_event(white_events.try_recv().unwrap()).starts_with(b"Session;"));
+        assert!(black.handle_event(black_events.try_recv().unwrap()).starts_with(b"Update;0.0.0,0.0:128.128.128|"));
+
+        assert_eq!(white.handle_raw(String::from("DoTurn;3,4")), "Error;It's not your turn");
+        assert_eq!(white.handle_raw(String::from("Set;Palette,Symbolic")), "Ok");
+        assert_eq!(black.handle_raw(String::from("DoTurn;3,4")), "Update;255.255.255,3.4:0.0.0|4.4:0.0.0");
+        assert_eq!(white.handle_event(white_events.try_recv().unwrap()), b"Update;W,3.4:B|4.4:B");
+        assert_eq!(white.handle_raw(String::from("Query;LastMove")), "Moves;3.4:B");
+        assert_eq!(white.handle_raw(String::from("Cancel")), "Error;Moves can't be taken back in a shared game");
+        assert_eq!(white.handle_raw(String::from("DoTurn;3,3")), "Update;B,3.3:W|4.4:W");
+        assert_eq!(black.handle_event(black_events.try_recv().unwrap()), b"Update;0.0.0,3.3:255.255.255|4.4:255.255.255");
+
+        assert_eq!(white.handle_raw(String::from("Leave")), "Ok");
+        assert_eq!(black.handle_event(black_events.try_recv().unwrap()), b"Error;Your opponent left the game");
+        assert_eq!(white.handle_raw(String::from("Leave")), "Error;You're not in a shared game");
+        drop(black);
+        assert_eq!(white.handle_raw(format!("Join;{}", code)), format!("Error;No game with code {}", code));
+    }
+
+    #[test]
+    fn handle_raw_test_out_of_turn_when_timed() {
+        let rooms = Arc::new(Mutex::new(Rooms::new()));
+        let sessions = Arc::new(Mutex::new(Sessions::new(Duration::from_secs(60))));
+        let mut black = Handler::connected(Arc::clone(&rooms), Arc::clone(&sessions), None, channel().0);
+        let mut white = Handler::connected(Arc::clone(&rooms), Arc::clone(&sessions), None, channel().0);
+
+        let room = black.handle_raw(String::from("Create;8x8,Standard,1+0"));
+        let code = room.strip_prefix("Room;").and_then(|room| room.strip_suffix(",0.0.0")).unwrap().to_string();
+        white.handle_raw(format!("Join;{}", code));
+        std::thread::sleep(Duration::from_millis(1100));
+        /* Only Black's clock ran out - White is just early */
+        assert_eq!(white.handle_raw(String::from("DoTurn;2,3")), "Error;It's not your turn");
+        assert_eq!(black.handle_raw(String::from("DoTurn;2,3")), "Error;You ran out of time");
+    }
+
+    #[test]
+    fn handle_raw_test_failed_enter() {
+        let rooms = Arc::new(Mutex::new(Rooms::new()));
+        let sessions = Arc::new(Mutex::new(Sessions::new(Duration::from_secs(60))));
+        let (black_outbox, black_events) = channel();
+        let mut black = Handler::connected(Arc::clone(&rooms), Arc::clone(&sessions), None, black_outbox);
+        let mut white = Handler::connected(Arc::clone(&rooms), Arc::clone(&sessions), None, channel().0);
+
+        let room = black.handle_raw(String::from("Create"));
+        let code = room.strip_prefix("Room;").and_then(|room| room.strip_suffix(",0.0.0")).unwrap().to_string();
+        white.handle_raw(format!("Join;{}", code));
+        while black_events.try_recv().is_ok() {}
+
+        /* Whatever the request that failed, the seat is still taken */
+        assert_eq!(white.handle_raw(String::from("Join;NOPE")), "Error;No game with code NOPE");
+        assert_eq!(white.handle_raw(String::from("Watch;NOPE")), "Error;No game with code NOPE");
+        assert_eq!(white.handle_raw(String::from("Create;7x8,Standard")), "Error;The board's sides must be even and at least 4 long");
+        assert_eq!(white.handle_raw(format!("Join;{}", code.to_ascii_lowercase())), "Error;You're already in this game");
+        assert_eq!(white.handle_raw(format!("Watch;{}", code)), "Error;You're already in this game");
+        assert!(black_events.try_recv().is_err());
+        black.handle_raw(String::from("DoTurn;3,4"));
+        assert_eq!(white.handle_raw(String::from("DoTurn;3,3")), "Update;0.0.0,3.3:255.255.255|4.4:255.255.255");
+
+        /* Entering another room does give up the seat */
+        assert!(white.handle_raw(String::from("Create")).starts_with("Room;"));
+        black_events.try_recv().unwrap();
+        assert_eq!(black.handle_event(black_events.try_recv().unwrap()), b"Error;Your opponent left the game");
+    }
+
+    #[test]
+    fn handle_raw_test_lobby_and_match() {
+        let rooms = Arc::new(Mutex::new(Rooms::new()));
+        let sessions = Arc::new(Mutex::new(Sessions::new(Duration::from_secs(60))));
+        let (black_outbox, black_events) = channel();
+        let mut black = Handler::connected(Arc::clone(&rooms), Arc::clone(&sessions), None, black_outbox);
+        let mut white = Handler::connected(Arc::clone(&rooms), Arc::clone(&sessions), None, channel().0);
+        let mut other = Handler::connected(Arc::clone(&rooms), Arc::clone(&sessions), None, channel().0);
+
+        let room = other.handle_raw(String::from("Create;8x8,Parallel"));
+        let listed = room.strip_prefix("Room;").and_then(|room| room.strip_suffix(",0.0.0")).unwrap().to_string();
+        let room = black.handle_raw(String::from("Match;8x8,Standard,300+5"));
+        let queued = room.strip_prefix("Room;").and_then(|room| room.strip_suffix(",0.0.0")).unwrap().to_string();
+        assert_eq!(white.handle_raw(String::from("Lobby")), format!("Lobby;{}:8x8,Parallel", listed));
+
+        assert_eq!(white.handle_raw(String::from("Match;8x8,Standard,300+5")), format!("Room;{},255.255.255", queued));
+        assert!(black.handle_event(black_events.try_recv().unwrap()).starts_with(b"Session;"));
+        assert!(black.handle_event(black_events.try_recv().unwrap()).starts_with(b"Update;0.0.0,0.0:128.128.128|"));
+        assert_eq!(black.handle_raw(String::from("DoTurn;2,3")), "Update;255.255.255,2.3:0.0.0|3.3:0.0.0");
+        assert_eq!(white.handle_raw(String::from("Create;7x8,Standard")), "Error;The board's sides must be even and at least 4 long");
+    }
+
+    #[test]
+    fn handle_raw_test_spectators() {
+        let rooms = Arc::new(Mutex::new(Rooms::new()));
+        let sessions = Arc::new(Mutex::new(Sessions::new(Duration::from_secs(60))));
+        let (black_outbox, black_events) = channel();
+        let (spectator_outbox, spectator_events) = channel();
+        let mut black = Handler::connected(Arc::clone(&rooms), Arc::clone(&sessions), None, black_outbox);
+        let mut white = Handler::connected(Arc::clone(&rooms), Arc::clone(&sessions), None, channel().0);
+        let mut spectator = Handler::connected(Arc::clone(&rooms), Arc::clone(&sessions), None, spectator_outbox);
+
+        let room = black.handle_raw(String::from("Create"));
+        let code = room.strip_prefix("Room;").and_then(|room| room.strip_suffix(",0.0.0")).unwrap().to_string();
+        white.handle_raw(format!("Join;{}", code));
+        assert_eq!(spectator.handle_raw(String::from("Set;Palette,Symbolic")), "Ok");
+        assert!(spectator.handle_raw(format!("Watch;{}", code)).starts_with("Update;B,0.0:.|"));
+        assert_eq!(spectator.handle_event(spectator_events.try_recv().unwrap()), b"Spectators;1");
+        assert!(spectator.handle_event(spectator_events.try_recv().unwrap()).starts_with(b"Session;"));
+        assert!(black.handle_event(black_events.try_recv().unwrap()).starts_with(b"Session;"));
+        black_events.try_recv().unwrap();
+        assert_eq!(black.handle_event(black_events.try_recv().unwrap()), b"Spectators;1");
+
+        assert_eq!(spectator.handle_raw(String::from("DoTurn;3,4")), "Error;Spectators can't play");
+        assert_eq!(spectator.handle_raw(String::from("Cancel")), "Error;Spectators can't play");
+        assert_eq!(spectator.handle_raw(String::from("Hint")), "Error;The engine doesn't help in a shared game");
+        assert_eq!(black.handle_raw(String::from("Analyze")), "Error;The engine doesn't help in a shared game");
+        black.handle_raw(String::from("DoTurn;3,4"));
+        assert_eq!(spectator.handle_event(spectator_events.try_recv().unwrap()), b"Update;W,3.4:B|4.4:B");
+        assert_eq!(spectator.handle_raw(String::from("Query;Score")), "Score;4,1");
+
+        white.handle_raw(String::from("Leave"));
+        assert_eq!(spectator.handle_event(spectator_events.try_recv().unwrap()), b"Error;A player left the game");
+        assert_eq!(spectator.handle_raw(String::from("Leave")), "Ok");
+        assert_eq!(black.handle_event(black_events.try_recv().unwrap()), b"Error;Your opponent left the game");
+        assert_eq!(black.handle_event(black_events.try_recv().unwrap()), b"Spectators;0");
+        assert_eq!(spectator.handle_raw(String::from("Leave")), "Error;You're not in a shared game");
+    }
+
+    #[test]
+    fn handle_raw_test_resume() {
+        let rooms = Arc::new(Mutex::new(Rooms::new()));
+        let sessions = Arc::new(Mutex::new(Sessions::new(Duration::from_secs(60))));
+        let (white_outbox, white_events) = channel();
+        let mut black = Handler::connected(Arc::clone(&rooms), Arc::clone(&sessions), None, channel().0);
+        let mut white = Handler::connected(Arc::clone(&rooms), Arc::clone(&sessions), None, white_outbox);
+
+        let room = black.handle_raw(String::from("Create"));
+        let code = room.strip_prefix("Room;").and_then(|room| room.strip_suffix(",0.0.0")).unwrap().to_string();
+        white.handle_raw(format!("Join;{}", code));
+        black.handle_raw(String::from("DoTurn;3,4"));
+        let token = black.handle_raw(String::from("Session")).strip_prefix("Session;").unwrap().to_string();
+        assert!(black.park());
+        assert!(!black.park());
+        drop(black);
+        assert_eq!(white_events.try_recv(), Ok(Event::Token));
+        white_events.try_recv().unwrap();
+        assert!(white_events.try_recv().is_err());
+
+        let (outbox, events) = channel();
+        let mut black = Handler::connected(Arc::clone(&rooms), Arc::clone(&sessions), None, outbox);
+        assert_eq!(black.handle_raw(String::from("Resume;nope")), "Error;No game to resume with this token");
+        assert_eq!(white.handle_raw(String::from("Resume;nope")), "Error;No game to resume with this token");
+        assert!(white_events.try_recv().is_err());
+        assert_eq!(black.handle_raw(String::from("Set;Snapshots,On")), "Ok");
+        assert_eq!(black.handle_raw(format!("Resume;{}", token)),
+                   "Snapshot;255.255.255,10,10,...........................................BBB........BW............................................");
+        assert_eq!(black.handle_raw(String::from("Session")), format!("Session;{}", token));
+        white.handle_raw(String::from("DoTurn;3,3"));
+        assert_eq!(black.handle_event(events.try_recv().unwrap()), b"Update;0.0.0,3.3:255.255.255|4.4:255.255.255");
+
+        assert!(black.park());
+        drop(black);
+        let expired = sessions.lock().unwrap().expire(Instant::now() + Duration::from_secs(60));
+        for session in expired { session.close(&mut rooms.lock().unwrap()); }
+        assert_eq!(white.handle_event(white_events.try_recv().unwrap()), b"Error;Your opponent left the game");
+        let mut other = Handler::connected(Arc::clone(&rooms), Arc::clone(&sessions), None, channel().0);
+        assert_eq!(other.handle_raw(format!("Resume;{}", token)), "Error;No game to resume with this token");
+    }
+
+    #[test]
+    fn handle_raw_test_storage() {
+        let dir = std::env::temp_dir().join(format!("reversi_handler_storage_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let storage = Arc::new(Storage::open(&dir).unwrap());
+        let sessions = Arc::new(Mutex::new(Sessions::new(Duration::from_secs(60))));
+        let mut handler = Handler::connected(Arc::new(Mutex::new(Rooms::new())), Arc::clone(&sessions), Some(Arc::clone(&storage)), channel().0);
+        /* A game is only given an id once it has a move to store */
+        handler.handle_raw(String::from("Start"));
+        handler.handle_raw(String::from("Start"));
+        handler.handle_raw(String::from("DoTurn;3,4"));
+        let token = handler.handle_raw(String::from("Session")).strip_prefix("Session;").unwrap().to_string();
+
+        let mut record = storage.load(1).unwrap();
+        assert_eq!((record.moves.len(), record.finished, record.players.black.as_deref()), (1, false, Some(token.as_str())));
+        assert_eq!(handler.handle_raw(String::from("Archive")), "Archive;");
+        assert_eq!(handler.handle_raw(String::from("Load;1")), "Error;No finished game with id 1");
+        let finished = Record{ id: 2, finished: true, ..record.clone() };
+        storage.save(&finished, storage.version()).unwrap();
+        assert_eq!(handler.handle_raw(String::from("Archive")), "Archive;2:10x10,Standard");
+        assert!(handler.handle_raw(String::from("Load;2")).starts_with("Update;255.255.255,"));
+        assert_eq!(handler.handle_raw(String::from("Query;MoveNumber")), "MoveNumber;1");
+
+        /* After a restart, the unfinished game waits for its player */
+        let rooms = Arc::new(Mutex::new(Rooms::new()));
+        let sessions = Arc::new(Mutex::new(Sessions::new(Duration::from_secs(60))));
+        let storage = Arc::new(Storage::open(&dir).unwrap());
+        for mut restored in Handler::restored(Arc::clone(&rooms), Arc::clone(&sessions), Some(Arc::clone(&storage)), &record).unwrap() { restored.park(); }
+        let mut handler = Handler::connected(Arc::clone(&rooms), Arc::clone(&sessions), Some(Arc::clone(&storage)), channel().0);
+        assert!(handler.handle_raw(format!("Resume;{}", token)).starts_with("Update;255.255.255,"));
+        assert_eq!(handler.handle_raw(String::from("Query;MoveNumber")), "MoveNumber;1");
+
+        record.code = Some(String::from("K7Q2MZ"));
+        record.players.white = Some(String::from("white"));
+        for mut restored in Handler::restored(Arc::clone(&rooms), Arc::clone(&sessions), Some(Arc::clone(&storage)), &record).unwrap() { restored.park(); }
+        let mut white = Handler::connected(Arc::clone(&rooms), Arc::clone(&sessions), Some(Arc::clone(&storage)), channel().0);
+        assert!(white.handle_raw(String::from("Resume;white")).starts_with("Update;255.255.255,"));
+        assert_eq!(white.handle_raw(String::from("DoTurn;3,3")), "Update;0.0.0,3.3:255.255.255|4.4:255.255.255");
+        assert_eq!(storage.load(1).unwrap().moves.len(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn handle_message_test_binary() {
+        let mut handler = Handler::new();
+        assert_eq!(handler.handle_message(b"#3;Set;Encoding,Binary"), Response::Ok.encode_binary(Some(3)));
+        assert_eq!(handler.encoding(), Encoding::Binary);
+        assert_eq!(handler.handle_message(&[0x03, 0, 4, 0, 0]), Response::Error(String::from("No game is running")).encode_binary(Some(4)));
+        assert_eq!(handler.handle_message(&[0x01, 0, 0, 0, 0])[..8], [0x81, 0, 0, 0, 41, 1, 10, 10]);
+        assert_eq!(handler.handle_message(&[0x02, 0, 0, 0, 2, 3, 4]), Response::Update(game_after_move(), None).encode_binary(None));
+        assert_eq!(handler.handle_message(&Request::Set(Setting::Encoding(Encoding::Text)).encode_binary(Some(5))), b"#5;Ok");
+        assert_eq!(handler.reject(String::from("Too long")), b"Error;Too long");
+    }
+
+    #[test]
+    fn handle_raw_test_golden_summary() {
+        let mut handler = Handler::new();
+        let response = handler.handle_raw(String::from("Start"));
+        assert!(response.starts_with("Update;0.0.0,0.0:128.128.128|1.0:128.128.128|2.0:128.128.128|"));
+        assert!(response.contains("|3.4:128.128.128|4.4:255.255.255|5.4:0.0.0|6.4:128.128.128|"));
+        assert!(response.ends_with("|8.9:128.128.128|9.9:128.128.128"));
+    }
+
+    #[test]
+    fn handle_raw_test_full_board_with_statistics() {
+        let mut handler = Handler::new();
+        assert_eq!(handler.handle_raw(String::from("Set;Statistics,On")), "Ok");
+        let board = handler.handle_raw(String::from("Start"));
+        assert!(board.starts_with("Update;0.0.0,0.0:128.128.128|"));
+        assert!(board.ends_with(";Mobility:4,4;PotentialMobility:10,10;Frontier:2,2"));
+        assert_eq!(handler.handle_raw(String::from("Query;Board")), board);
+        assert_eq!(handler.handle_raw(String::from("Set;FullBoards,On")), "Ok");
+        assert!(handler.handle_raw(String::from("DoTurn;3,4")).starts_with("Update;255.255.255,0.0:128.128.128|"));
+    }
+}
@@ -0,0 +1,345 @@
+use super::game::Game;
+use super::json::Json;
+use super::position::Position;
+use super::server::{Incoming, Timeouts, MAX_FRAME_SIZE};
+use super::pool::{self, Pool};
+
+use std::collections::BTreeMap;
+use std::net::{TcpListener, TcpStream};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::sync::{Arc, Mutex};
+
+/*
+A plain HTTP/1.1 API for scripts and dashboards, serving every game over JSON:
+    GET    /games                  The ids of all games
+    POST   /games                  Creates a game
+    GET    /games/<id>             The board of a game
+    DELETE /games/<id>             Removes a game
+    POST   /games/<id>/moves       Plays {"x": 3, "y": 4} for the side to move
+    POST   /games/<id>/undo        Takes back the last move
+    GET    /games/<id>/history     The moves played so far
+Every connection carries a single request.
+*/
+
+const MAX_HEAD_SIZE: usize = 8 * 1024;
+const MAX_GAMES: usize = 1024;                      /* Games are kept until deleted, so creating more is refused past this */
+
+pub(crate) struct HttpRequest {
+    pub method: String,
+    pub path: String,                               /* Without the query string */
+    headers: Vec<(String, String)>,
+    pub body: String
+}
+
+impl HttpRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+}
+
+/* Reads the request line, the headers and - if there's a Content-Length - the body */
+pub(crate) fn read_request(reader: &mut impl BufRead) -> Result<HttpRequest, String> {
+    let mut lines: Vec<String> = Vec::new();
+    let mut total = 0;
+    loop {
+        let mut line = String::new();
+        /* A line is never read past the head's limit, even if it doesn't end */
+        let len = reader.by_ref().take((MAX_HEAD_SIZE - total + 1) as u64).read_line(&mut line).map_err(|e| e.to_string())?;
+        if len == 0 { return Err(String::from("End of stream in request head")); }
+        total += len;
+        if total > MAX_HEAD_SIZE { return Err(String::from("Request head too large")); }
+        let line = line.trim_end().to_string();
+        if line.is_empty() { break; }
+        lines.push(line);
+    }
+
+    let mut request_line = lines.first().ok_or_else(|| String::from("Missing request line"))?.split_whitespace();
+    let method = request_line.next().ok_or_else(|| String::from("Missing method"))?.to_string();
+    let target = request_line.next().ok_or_else(|| String::from("Missing path"))?;
+    let path = target.split('?').next().unwrap_or(target).to_string();
+
+    let headers = lines.iter().skip(1).filter_map(|line| {
+        let mut split = line.splitn(2, ':');
+        Some((split.next()?.trim().to_string(), split.next()?.trim().to_string()))
+    }).collect();
+    let mut request = HttpRequest{ method, path, headers, body: String::new() };
+
+    if let Some(raw) = request.header("Content-Length") {
+        let len: usize = raw.parse().map_err(|_| format!("Invalid Content-Length: {}", raw))?;
+        if len > MAX_FRAME_SIZE { return Err(format!("Request body is longer than {} bytes", MAX_FRAME_SIZE)); }
+        let mut body = vec![0u8; len];
+        reader.read_exact(&mut body).map_err(|e| e.to_string())?;
+        request.body = String::from_utf8(body).map_err(|_| String::from("Request body is not valid UTF-8"))?;
+    }
+    Ok(request)
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error"
+    }
+}
+
+pub(crate) fn write_response(writer: &mut impl Write, status: u16, body: Option<&Json>) -> std::io::Result<()> {
+    let mut response = format!("HTTP/1.1 {} {}\r\n", status, reason(status));
+    let body = body.map(Json::stringify).unwrap_or_default();
+    if !body.is_empty() { response.push_str("Content-Type: application/json\r\n"); }
+    response.push_str(&format!("Content-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body));
+    writer.write_all(response.as_bytes())?;
+    writer.flush()
+}
+
+/* All the games served over HTTP, by id */
+pub struct Games {
+    next_id: u64,
+    games: BTreeMap<u64, Game>
+}
+
+impl Default for Games {
+    fn default() -> Games {
+        Games::new()
+    }
+}
+
+impl Games {
+    pub fn new() -> Games {
+        Games{ next_id: 1, games: BTreeMap::new() }
+    }
+}
+
+struct HttpResponse {
+    status: u16,
+    body: Option<Json>
+}
+
+impl HttpResponse {
+    fn json(status: u16, body: Json) -> HttpResponse {
+        HttpResponse{ status, body: Some(body) }
+    }
+
+    fn error(status: u16, message: &str) -> HttpResponse {
+        HttpResponse::json(status, Json::object(vec![("error", Json::string(message))]))
+    }
+}
+
+fn stringify_game(id: u64, game: &Game) -> Json {
+    let snapshot = game.snapshot();
+    Json::object(vec![
+        ("id", Json::Number(id as f64)),
+        ("player", snapshot.player.stringify_json()),
+        ("width", Json::Number(snapshot.size.width as f64)),
+        ("height", Json::Number(snapshot.size.height as f64)),
+        ("board", Json::String(snapshot.symbols())),
+        ("moves", Json::Number(game.history().len() as f64))
+    ])
+}
+
+fn stringify_history(id: u64, game: &Game) -> Json {
+    let moves = game.history().iter().map(|mv| Json::object(vec![
+        ("position", mv.position.stringify_json()),
+        ("player", mv.player.stringify_json()),
+        ("flips", Json::Number(mv.flips.count() as f64))
+    ])).collect();
+    Json::object(vec![("id", Json::Number(id as f64)), ("moves", Json::Array(moves))])
+}
+
+fn play(id: u64, game: &mut Game, body: &str) -> HttpResponse {
+    let value = match Json::parse(body) {
+        Ok(value) => value,
+        Err(details) => return HttpResponse::error(400, &details)
+    };
+    let position = match Position::parse_json(&value) {
+        Ok(position) => position,
+        Err(parsing_error) => return HttpResponse::error(400, &parsing_error.to_string())
+    };
+    match game.make_move(position) {
+        Ok(_) => HttpResponse::json(200, stringify_game(id, game)),
+        Err(message) => HttpResponse::error(409, message)
+    }
+}
+
+fn route(games: &Mutex<Games>, request: &HttpRequest) -> HttpResponse {
+    let mut games = games.lock().expect("Impossible event: a thread panicked while holding the games (http::route)");
+    let segments: Vec<&str> = request.path.split('/').filter(|segment| !segment.is_empty()).collect();
+    let method = request.method.as_str();
+
+    match segments.as_slice() {
+        ["games"] => match method {
+            "GET" => HttpResponse::json(200, Json::object(vec![
+                ("games", Json::Array(games.games.keys().map(|id| Json::Number(*id as f64)).collect()))
+            ])),
+            "POST" if games.games.len() >= MAX_GAMES => HttpResponse::error(503, "Too many games - delete some first"),
+            "POST" => {
+                let id = games.next_id;
+                games.next_id += 1;
+                let game = Game::new();
+                let body = stringify_game(id, &game);
+                games.games.insert(id, game);
+                HttpResponse::json(201, body)
+            },
+            _ => HttpResponse::error(405, "Method not allowed")
+        },
+        ["games", id_raw, rest @ ..] => {
+            let id = match id_raw.parse::<u64>() {
+                Ok(id) if games.games.contains_key(&id) => id,
+                _ => return HttpResponse::error(404, "No such game")
+            };
+            if method == "DELETE" && rest.is_empty() {
+                games.games.remove(&id);
+                return HttpResponse{ status: 204, body: None };
+            }
+            let game = games.games.get_mut(&id).expect("Impossible event: game vanished while locked (http::route)");
+            match (method, rest) {
+                ("GET", []) => HttpResponse::json(200, stringify_game(id, game)),
+                ("GET", ["history"]) => HttpResponse::json(200, stringify_history(id, game)),
+                ("POST", ["moves"]) => play(id, game, &request.body),
+                ("POST", ["undo"]) => match game.unmake_move() {
+                    Some(_) => HttpResponse::json(200, stringify_game(id, game)),
+                    None => HttpResponse::error(409, "No more moves to cancel")
+                },
+                (_, []) | (_, ["history"]) | (_, ["moves"]) | (_, ["undo"]) => HttpResponse::error(405, "Method not allowed"),
+                _ => HttpResponse::error(404, "Not found")
+            }
+        },
+        _ => HttpResponse::error(404, "Not found")
+    }
+}
+
+fn serve_single(games: &Mutex<Games>, mut stream: TcpStream, timeouts: Timeouts) {
+    /* The single request has the idle timeout to arrive whole */
+    let mut reader = match timeouts.apply(&stream).and_then(|_| stream.try_clone()) {
+        Ok(read_half) => BufReader::new(Incoming::without_events(read_half, timeouts.idle)),
+        Err(e) => { println!("Error while setting up HTTP stream: {}", e); return; }
+    };
+    let response = match read_request(&mut reader) {
+        Ok(request) => route(games, &request),
+        Err(message) => HttpResponse::error(400, &message)
+    };
+    if let Err(e) = write_response(&mut stream, response.status, response.body.as_ref()) {
+        println!("Error while writing HTTP response: {}", e);
+    }
+}
+
+/* The response to a connection the pool can't serve */
+pub(crate) fn rejection() -> Vec<u8> {
+    let response = HttpResponse::error(503, pool::REJECTION);
+    let mut written = Vec::new();
+    write_response(&mut written, response.status, response.body.as_ref()).expect("Impossible event: writing to memory failed (http::rejection)");
+    written
+}
+
+pub fn serve_incoming(listener: TcpListener, pool: Arc<Pool>, timeouts: Timeouts) {
+    let games = Arc::new(Mutex::new(Games::new()));
+
+    for incoming_stream in listener.incoming() {
+        match incoming_stream {
+            Ok(valid_stream) => match pool.claim() {
+                Some(worker) => {
+                    let games = Arc::clone(&games);
+                    worker.run(move || serve_single(&games, valid_stream, timeouts));
+                },
+                None => {
+                    println!("Turning away HTTP stream, all {} workers are busy", pool.size());
+                    pool::turn_away(valid_stream, &rejection());
+                }
+            },
+            Err(e) => println!("Error while handling incoming HTTP stream: {}", e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_request, route, write_response, Games, HttpRequest, MAX_GAMES};
+    use super::super::json::Json;
+    use std::io::{BufReader, Cursor};
+    use std::sync::Mutex;
+
+    fn request(method: &str, path: &str, body: &str) -> HttpRequest {
+        let raw = format!("{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}", method, path, body.len(), body);
+        read_request(&mut Cursor::new(raw)).unwrap()
+    }
+
+    fn call(games: &Mutex<Games>, method: &str, path: &str, body: &str) -> (u16, String) {
+        let response = route(games, &request(method, path, body));
+        (response.status, response.body.map(|body| body.stringify()).unwrap_or_default())
+    }
+
+    #[test]
+    fn read_request_test() {
+        let raw = "POST /games/1/moves?verbose=1 HTTP/1.1\r\nhost: localhost\r\ncontent-length: 14\r\n\r\n{\"x\":3,\"y\":4}\nextra";
+        let request = read_request(&mut Cursor::new(raw)).unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/games/1/moves");
+        assert_eq!(request.header("Host"), Some("localhost"));
+        assert_eq!(request.body, "{\"x\":3,\"y\":4}\n");
+
+        assert!(read_request(&mut Cursor::new("GET / HTTP/1.1\r\n")).is_err());
+        assert!(read_request(&mut Cursor::new("POST / HTTP/1.1\r\nContent-Length: 999999\r\n\r\n")).is_err());
+        assert_eq!(read_request(&mut BufReader::new(std::io::repeat(b'a'))).map(|_| ()), Err(String::from("Request head too large")));
+    }
+
+    #[test]
+    fn write_response_test() {
+        let mut output: Vec<u8> = Vec::new();
+        write_response(&mut output, 201, Some(&Json::object(vec![("id", Json::Number(1.0))]))).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(),
+                   "HTTP/1.1 201 Created\r\nContent-Type: application/json\r\nContent-Length: 8\r\nConnection: close\r\n\r\n{\"id\":1}");
+    }
+
+    #[test]
+    fn route_test_game() {
+        let games = Mutex::new(Games::new());
+        let (status, body) = call(&games, "POST", "/games", "");
+        assert_eq!(status, 201);
+        assert_eq!(body, "{\"id\":1,\"player\":\"Black\",\"width\":10,\"height\":10,\
+                          \"board\":\"............................................WB........BW............................................\",\"moves\":0}");
+        assert_eq!(call(&games, "POST", "/games", "").0, 201);
+        assert_eq!(call(&games, "GET", "/games", ""), (200, String::from("{\"games\":[1,2]}")));
+
+        let (status, body) = call(&games, "POST", "/games/1/moves", "{\"x\": 3, \"y\": 4}");
+        assert_eq!(status, 200);
+        assert!(body.contains("\"player\":\"White\""));
+        assert!(body.contains("\"moves\":1"));
+        assert_eq!(call(&games, "GET", "/games/1/history", ""),
+                   (200, String::from("{\"id\":1,\"moves\":[{\"position\":{\"x\":3,\"y\":4},\"player\":\"Black\",\"flips\":1}]}")));
+
+        assert_eq!(call(&games, "POST", "/games/1/undo", "").0, 200);
+        assert_eq!(call(&games, "POST", "/games/1/undo", ""), (409, String::from("{\"error\":\"No more moves to cancel\"}")));
+        assert_eq!(call(&games, "DELETE", "/games/2", "").0, 204);
+        assert_eq!(call(&games, "GET", "/games/2", "").0, 404);
+    }
+
+    #[test]
+    fn route_test_errors() {
+        let games = Mutex::new(Games::new());
+        call(&games, "POST", "/games", "");
+        assert_eq!(call(&games, "POST", "/games/1/moves", "{\"x\": 0, \"y\": 0}"),
+                   (409, String::from("{\"error\":\"You must flip at least one tile\"}")));
+        assert_eq!(call(&games, "POST", "/games/1/moves", "{\"x\": 99, \"y\": 0}").0, 409);
+        assert_eq!(call(&games, "POST", "/games/1/moves", "{\"x\": 3}").0, 400);
+        assert_eq!(call(&games, "POST", "/games/1/moves", "not json").0, 400);
+        assert_eq!(call(&games, "GET", "/games/1/moves", "").0, 405);
+        assert_eq!(call(&games, "PUT", "/games", "").0, 405);
+        assert_eq!(call(&games, "GET", "/games/abc", "").0, 404);
+        assert_eq!(call(&games, "GET", "/games/1/teleport", "").0, 404);
+        assert_eq!(call(&games, "GET", "/players", "").0, 404);
+    }
+
+    #[test]
+    fn route_test_too_many_games() {
+        let games = Mutex::new(Games::new());
+        for _ in 0..MAX_GAMES { call(&games, "POST", "/games", ""); }
+        assert_eq!(call(&games, "POST", "/games", ""), (503, String::from("{\"error\":\"Too many games - delete some first\"}")));
+        assert_eq!(call(&games, "DELETE", "/games/1", "").0, 204);
+        assert_eq!(call(&games, "POST", "/games", "").0, 201);
+    }
+}
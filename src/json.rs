@@ -0,0 +1,312 @@
+/*
+A minimal JSON value, with its own reader and writer.
+Objects keep their keys in insertion order, so what's written is always the same for the same value.
+*/
+#[derive(PartialEq, Debug, Clone)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>)
+}
+
+const MAX_DEPTH: usize = 64;                        /* Deeper documents are rejected rather than risking the stack */
+
+impl Json {
+    pub fn object(entries: Vec<(&str, Json)>) -> Json {
+        Json::Object(entries.into_iter().map(|(key, value)| (key.to_string(), value)).collect())
+    }
+
+    pub fn string(value: &str) -> Json {
+        Json::String(value.to_string())
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(entries) => entries.iter().find(|(entry_key, _)| entry_key == key).map(|(_, value)| value),
+            _ => None
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(value) => Some(value),
+            _ => None
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Json::Bool(value) => Some(*value),
+            _ => None
+        }
+    }
+
+    pub fn as_usize(&self) -> Option<usize> {
+        match self {
+            Json::Number(value) if *value >= 0.0 && value.fract() == 0.0 && *value <= usize::MAX as f64 => Some(*value as usize),
+            _ => None
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&Vec<Json>> {
+        match self {
+            Json::Array(values) => Some(values),
+            _ => None
+        }
+    }
+
+    pub fn parse(source: &str) -> Result<Json, String> {
+        let mut reader = Reader{ chars: source.chars().collect(), idx: 0 };
+        let value = reader.value(0)?;
+        reader.skip_whitespace();
+        if reader.idx < reader.chars.len() { return Err(reader.error("Unexpected trailing characters")); }
+        Ok(value)
+    }
+
+    pub fn stringify(&self) -> String {
+        let mut output = String::new();
+        self.write(&mut output);
+        output
+    }
+
+    fn write(&self, output: &mut String) {
+        match self {
+            Json::Null => output.push_str("null"),
+            Json::Bool(value) => output.push_str(if *value { "true" } else { "false" }),
+            Json::Number(value) if !value.is_finite() => output.push_str("null"),
+            Json::Number(value) if value.fract() == 0.0 && value.abs() < 1e15 => output.push_str(&format!("{}", *value as i64)),
+            Json::Number(value) => output.push_str(&format!("{}", value)),
+            Json::String(value) => write_string(value, output),
+            Json::Array(values) => {
+                output.push('[');
+                for (idx, value) in values.iter().enumerate() {
+                    if idx > 0 { output.push(','); }
+                    value.write(output);
+                }
+                output.push(']');
+            },
+            Json::Object(entries) => {
+                output.push('{');
+                for (idx, (key, value)) in entries.iter().enumerate() {
+                    if idx > 0 { output.push(','); }
+                    write_string(key, output);
+                    output.push(':');
+                    value.write(output);
+                }
+                output.push('}');
+            }
+        }
+    }
+}
+
+fn write_string(value: &str, output: &mut String) {
+    output.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            c if (c as u32) < 0x20 => output.push_str(&format!("\\u{:04x}", c as u32)),
+            c => output.push(c)
+        }
+    }
+    output.push('"');
+}
+
+struct Reader {
+    chars: Vec<char>,
+    idx: usize
+}
+
+impl Reader {
+    fn error(&self, message: &str) -> String {
+        format!("{} at offset {}", message, self.idx)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.idx < self.chars.len() && self.chars[self.idx].is_whitespace() { self.idx += 1; }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.idx).copied()
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        if self.peek() != Some(expected) { return Err(self.error(&format!("Expected '{}'", expected))); }
+        self.idx += 1;
+        Ok(())
+    }
+
+    fn literal(&mut self, word: &str, value: Json) -> Result<Json, String> {
+        for expected in word.chars() { self.expect(expected)?; }
+        Ok(value)
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Json, String> {
+        if depth > MAX_DEPTH { return Err(self.error("Nested too deeply")); }
+        self.skip_whitespace();
+        match self.peek() {
+            Some('n') => self.literal("null", Json::Null),
+            Some('t') => self.literal("true", Json::Bool(true)),
+            Some('f') => self.literal("false", Json::Bool(false)),
+            Some('"') => Ok(Json::String(self.string()?)),
+            Some('[') => self.array(depth),
+            Some('{') => self.object(depth),
+            Some(c) if c == '-' || c.is_ascii_digit() => self.number(),
+            Some(_) => Err(self.error("Unexpected character")),
+            None => Err(self.error("Unexpected end"))
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.idx;
+        while let Some(c) = self.peek() {
+            if c.is_ascii_digit() || "+-.eE".contains(c) { self.idx += 1; } else { break; }
+        }
+        let raw: String = self.chars[start..self.idx].iter().collect();
+        /* Numbers beyond the range of f64 would parse to infinity, which JSON has no way to write back */
+        raw.parse::<f64>().ok().filter(|number| number.is_finite()).map(Json::Number).ok_or_else(|| self.error("Invalid number"))
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        if self.idx + 4 > self.chars.len() { return Err(self.error("Unexpected end")); }
+        let raw: String = self.chars[self.idx..self.idx + 4].iter().collect();
+        let code = u32::from_str_radix(&raw, 16).map_err(|_| self.error("Invalid unicode escape"))?;
+        self.idx += 4;
+        Ok(code)
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut value = String::new();
+        loop {
+            let c = self.peek().ok_or_else(|| self.error("Unterminated string"))?;
+            self.idx += 1;
+            match c {
+                '"' => return Ok(value),
+                '\\' => {
+                    let escaped = self.peek().ok_or_else(|| self.error("Unterminated string"))?;
+                    self.idx += 1;
+                    match escaped {
+                        '"' | '\\' | '/' => value.push(escaped),
+                        'b' => value.push('\u{8}'),
+                        'f' => value.push('\u{c}'),
+                        'n' => value.push('\n'),
+                        'r' => value.push('\r'),
+                        't' => value.push('\t'),
+                        'u' => {
+                            let mut code = self.hex4()?;
+                            if (0xD800..0xDC00).contains(&code) {
+                                self.expect('\\')?;
+                                self.expect('u')?;
+                                let low = self.hex4()?;
+                                if !(0xDC00..0xE000).contains(&low) { return Err(self.error("Invalid surrogate pair")); }
+                                code = 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00);
+                            }
+                            value.push(std::char::from_u32(code).ok_or_else(|| self.error("Invalid unicode escape"))?);
+                        },
+                        _ => return Err(self.error("Invalid escape"))
+                    }
+                },
+                c if (c as u32) < 0x20 => return Err(self.error("Control character in string")),
+                c => value.push(c)
+            }
+        }
+    }
+
+    fn array(&mut self, depth: usize) -> Result<Json, String> {
+        self.expect('[')?;
+        let mut values = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(']') { self.idx += 1; return Ok(Json::Array(values)); }
+        loop {
+            values.push(self.value(depth + 1)?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => self.idx += 1,
+                Some(']') => { self.idx += 1; return Ok(Json::Array(values)); },
+                _ => return Err(self.error("Expected ',' or ']'"))
+            }
+        }
+    }
+
+    fn object(&mut self, depth: usize) -> Result<Json, String> {
+        self.expect('{')?;
+        let mut entries = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some('}') { self.idx += 1; return Ok(Json::Object(entries)); }
+        loop {
+            self.skip_whitespace();
+            let key = self.string()?;
+            self.skip_whitespace();
+            self.expect(':')?;
+            entries.push((key, self.value(depth + 1)?));
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => self.idx += 1,
+                Some('}') => { self.idx += 1; return Ok(Json::Object(entries)); },
+                _ => return Err(self.error("Expected ',' or '}'"))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Json;
+
+    #[test]
+    fn parse_test_scalars() {
+        assert_eq!(Json::parse("null"), Ok(Json::Null));
+        assert_eq!(Json::parse(" true "), Ok(Json::Bool(true)));
+        assert_eq!(Json::parse("false"), Ok(Json::Bool(false)));
+        assert_eq!(Json::parse("-12.5e1"), Ok(Json::Number(-125.0)));
+        assert_eq!(Json::parse("\"a\\\"b\\n\\u00e9\\ud83d\\ude00\""), Ok(Json::string("a\"b\né\u{1F600}")));
+    }
+
+    #[test]
+    fn parse_test_nested() {
+        let parsed = Json::parse("{\"type\": \"DoTurn\", \"position\": {\"x\": 2, \"y\": 4}, \"list\": [1, [], {}]}").unwrap();
+        assert_eq!(parsed.get("type").and_then(Json::as_str), Some("DoTurn"));
+        assert_eq!(parsed.get("position").and_then(|pos| pos.get("y")).and_then(Json::as_usize), Some(4));
+        assert_eq!(parsed.get("list").and_then(Json::as_array).map(Vec::len), Some(3));
+        assert_eq!(parsed.get("missing"), None);
+    }
+
+    #[test]
+    fn parse_test_errors() {
+        assert!(Json::parse("").is_err());
+        assert!(Json::parse("{\"a\" 1}").is_err());
+        assert!(Json::parse("[1, 2").is_err());
+        assert!(Json::parse("\"unterminated").is_err());
+        assert!(Json::parse("nul").is_err());
+        assert!(Json::parse("{} {}").is_err());
+        assert!(Json::parse(&"[".repeat(100)).is_err());
+        assert_eq!(Json::parse("1e999"), Err(String::from("Invalid number at offset 5")));
+        assert!(Json::parse("[-1e400]").is_err());
+    }
+
+    #[test]
+    fn as_usize_test() {
+        assert_eq!(Json::Number(3.0).as_usize(), Some(3));
+        assert_eq!(Json::Number(3.5).as_usize(), None);
+        assert_eq!(Json::Number(-1.0).as_usize(), None);
+        assert_eq!(Json::string("3").as_usize(), None);
+    }
+
+    #[test]
+    fn stringify_test() {
+        let value = Json::object(vec![
+            ("type", Json::string("Error")),
+            ("message", Json::string("Say \"hi\"\n")),
+            ("values", Json::Array(vec![Json::Number(1.0), Json::Number(0.5), Json::Null, Json::Bool(true)]))
+        ]);
+        assert_eq!(value.stringify(), "{\"type\":\"Error\",\"message\":\"Say \\\"hi\\\"\\n\",\"values\":[1,0.5,null,true]}");
+        assert_eq!(Json::parse(&value.stringify()), Ok(value));
+    }
+}
@@ -0,0 +1,578 @@
+use super::handler::{Request, Response, Setting, Query, Encoding, Capability, Palette, Rgb};
+use super::parser::ParsingError;
+use super::json::Json;
+use super::board::{Tile, Color};
+use super::game::Variant;
+use super::rooms::{GameOptions, TimeControl};
+use super::storage::Record;
+use super::position::Size;
+use super::position::Position;
+use super::analysis::{PerColor, Statistics};
+use std::convert::TryFrom;
+
+/*
+The JSON counterpart of parser.rs. Every message is an object with a "type" field, named after the Request/Response variant:
+    {"type": "DoTurn", "position": {"x": 2, "y": 4}}
+    {"type": "Update", "player": "White", "tiles": [{"position": {"x": 2, "y": 4}, "tile": "Black"}]}
+*/
+
+fn field<'a>(value: &'a Json, key: &str, role: &'static str) -> Result<&'a Json, ParsingError> {
+    value.get(key).ok_or_else(|| ParsingError::missing(role, &value.stringify()))
+}
+
+impl Position {
+    pub(crate) fn parse_json(value: &Json) -> Result<Position, ParsingError> {
+        let coordinate = |key: &str, role: &'static str| {
+            let raw = field(value, key, role)?;
+            raw.as_usize().ok_or_else(|| ParsingError::invalid_number(role, &raw.stringify()))
+        };
+        Ok(Position{x: coordinate("x", "Position::x")?, y: coordinate("y", "Position::y")?})
+    }
+
+    pub(crate) fn stringify_json(&self) -> Json {
+        Json::object(vec![("x", Json::Number(self.x as f64)), ("y", Json::Number(self.y as f64))])
+    }
+}
+
+impl Rgb {
+    fn parse_json(value: &Json) -> Result<Rgb, ParsingError> {
+        let channels: Option<Vec<u8>> = value.as_array().and_then(|channels| {
+            channels.iter().map(|channel| channel.as_usize().and_then(|channel| u8::try_from(channel).ok())).collect()
+        });
+        match channels.as_deref() {
+            Some([r, g, b]) => Ok(Rgb(*r, *g, *b)),
+            _ => Err(ParsingError::invalid_value("Rgb", &value.stringify()))
+        }
+    }
+}
+
+impl Palette {
+    /* Either "Symbolic" or an object of [r, g, b] colors: {"black": [0, 0, 0], "white": [255, 255, 255], "empty": [0, 128, 0]} */
+    fn parse_json(value: &Json) -> Result<Palette, ParsingError> {
+        if value.as_str() == Some("Symbolic") { return Ok(Palette::Symbolic); }
+        let color = |key: &str, role: &'static str| Rgb::parse_json(field(value, key, role)?);
+        let (black, white, empty) = (color("black", "Palette::black")?, color("white", "Palette::white")?, color("empty", "Palette::empty")?);
+        Palette::rgb(black, white, empty).ok_or_else(|| ParsingError::invalid_value("Setting::Palette", &value.stringify()))
+    }
+}
+
+impl Setting {
+    fn parse_json(value: &Json) -> Result<Setting, ParsingError> {
+        let setting_raw = field(value, "setting", "Request::Set::Setting")?;
+        let setting_type = setting_raw.as_str().ok_or_else(|| ParsingError::invalid_value("Request::Set::Setting", &setting_raw.stringify()))?;
+        match setting_type {
+            "Statistics" => {
+                let raw = field(value, "value", "Setting::Statistics::value")?;
+                let include = raw.as_bool().ok_or_else(|| ParsingError::invalid_value("Setting::Statistics::value", &raw.stringify()))?;
+                Ok(Setting::Statistics(include))
+            },
+            "Encoding" => {
+                let raw = field(value, "value", "Setting::Encoding::value")?;
+                let encoding = raw.as_str().ok_or_else(|| ParsingError::invalid_value("Setting::Encoding::value", &raw.stringify()))?;
+                Ok(Setting::Encoding(Encoding::parse(encoding)?))
+            },
+            "Snapshots" => {
+                let raw = field(value, "value", "Setting::Snapshots::value")?;
+                let snapshots = raw.as_bool().ok_or_else(|| ParsingError::invalid_value("Setting::Snapshots::value", &raw.stringify()))?;
+                Ok(Setting::Snapshots(snapshots))
+            },
+            "FullBoards" => {
+                let raw = field(value, "value", "Setting::FullBoards::value")?;
+                let full_boards = raw.as_bool().ok_or_else(|| ParsingError::invalid_value("Setting::FullBoards::value", &raw.stringify()))?;
+                Ok(Setting::FullBoards(full_boards))
+            },
+            "Palette" => Ok(Setting::Palette(Palette::parse_json(field(value, "value", "Setting::Palette::value")?)?)),
+            _ => Err(ParsingError::unrecognized_setting(setting_type))
+        }
+    }
+}
+
+impl GameOptions {
+    /* {"width": 8, "height": 8, "variant": "Standard", "timeControl": {"initial": 300, "increment": 5}} - the time control may be left out */
+    fn parse_json(value: &Json) -> Result<GameOptions, ParsingError> {
+        let number = |value: &Json, key: &str, role: &'static str| {
+            let raw = field(value, key, role)?;
+            raw.as_usize().ok_or_else(|| ParsingError::invalid_number(role, &raw.stringify()))
+        };
+        let size = Size{ width: number(value, "width", "GameOptions::width")?, height: number(value, "height", "GameOptions::height")? };
+        let variant_raw = field(value, "variant", "GameOptions::variant")?;
+        let variant = variant_raw.as_str().ok_or_else(|| ParsingError::invalid_value("GameOptions::variant", &variant_raw.stringify()))?;
+        let time_control = match value.get("timeControl") {
+            None | Some(Json::Null) => None,
+            Some(raw) => {
+                let seconds = |key: &str, role: &'static str| number(raw, key, role).and_then(|seconds| {
+                    u32::try_from(seconds).map_err(|_| ParsingError::invalid_number(role, &seconds.to_string()))
+                });
+                Some(TimeControl{ initial: seconds("initial", "TimeControl::initial")?, increment: seconds("increment", "TimeControl::increment")? })
+            }
+        };
+        Ok(GameOptions{ size, variant: Variant::parse(variant)?, time_control })
+    }
+
+    /* The "options" field of a request, if there is one */
+    fn parse_json_field(value: &Json) -> Result<GameOptions, ParsingError> {
+        value.get("options").map_or(Ok(GameOptions::default()), GameOptions::parse_json)
+    }
+
+    fn stringify_json(&self) -> Json {
+        let time_control = self.time_control.map_or(Json::Null, |time_control| Json::object(vec![
+            ("initial", Json::Number(time_control.initial as f64)),
+            ("increment", Json::Number(time_control.increment as f64))
+        ]));
+        Json::object(vec![
+            ("width", Json::Number(self.size.width as f64)),
+            ("height", Json::Number(self.size.height as f64)),
+            ("variant", Json::string(self.variant.stringify())),
+            ("timeControl", time_control)
+        ])
+    }
+}
+
+impl Query {
+    fn parse_json(value: &Json) -> Result<Query, ParsingError> {
+        let raw = field(value, "query", "Request::Query::Query")?;
+        let query = raw.as_str().ok_or_else(|| ParsingError::invalid_value("Request::Query::Query", &raw.stringify()))?;
+        Query::parse(query)
+    }
+}
+
+impl Request {
+    /*
+    Like parse_json, but also returns the request's "id" field - a string or a number, echoed as is in the response.
+    The id is known even when the request is invalid, as long as it's valid JSON.
+    */
+    pub fn parse_json_tagged(source: &str) -> (Option<Json>, Result<Request, ParsingError>) {
+        match Json::parse(source) {
+            Ok(value) => {
+                let id = value.get("id").filter(|id| matches!(id, Json::String(_) | Json::Number(_))).cloned();
+                (id, Request::from_json(&value))
+            },
+            Err(details) => (None, Err(ParsingError::invalid_json(&details, source)))
+        }
+    }
+
+    pub fn parse_json(source: &str) -> Result<Request, ParsingError> {
+        Request::parse_json_tagged(source).1
+    }
+
+    fn from_json(value: &Json) -> Result<Request, ParsingError> {
+        let type_raw = field(value, "type", "Request::type")?;
+        let request_type = type_raw.as_str().ok_or_else(|| ParsingError::invalid_value("Request::type", &type_raw.stringify()))?;
+        match request_type {
+            "Start" => Ok(Request::Start),
+            "DoTurn" => Ok(Request::DoTurn(Position::parse_json(field(value, "position", "Request::DoTurn::Position")?)?)),
+            "Cancel" => Ok(Request::Cancel),
+            "Hint" => Ok(Request::Hint),
+            "Analyze" => Ok(Request::Analyze),
+            "Set" => Ok(Request::Set(Setting::parse_json(value)?)),
+            "Create" => Ok(Request::Create(GameOptions::parse_json_field(value)?)),
+            "Lobby" => Ok(Request::Lobby),
+            "Match" => Ok(Request::Match(GameOptions::parse_json_field(value)?)),
+            "Join" => {
+                let raw = field(value, "code", "Request::Join::code")?;
+                let code = raw.as_str().ok_or_else(|| ParsingError::invalid_value("Request::Join::code", &raw.stringify()))?;
+                Ok(Request::Join(code.to_string()))
+            },
+            "Watch" => {
+                let raw = field(value, "code", "Request::Watch::code")?;
+                let code = raw.as_str().ok_or_else(|| ParsingError::invalid_value("Request::Watch::code", &raw.stringify()))?;
+                Ok(Request::Watch(code.to_string()))
+            },
+            "Leave" => Ok(Request::Leave),
+            "Session" => Ok(Request::Session),
+            "Archive" => Ok(Request::Archive),
+            "Ping" => Ok(Request::Ping),
+            "Load" => {
+                let raw = field(value, "id", "Request::Load::id")?;
+                Ok(Request::Load(raw.as_usize().ok_or_else(|| ParsingError::invalid_number("Request::Load::id", &raw.stringify()))? as u64))
+            },
+            "Resume" => {
+                let raw = field(value, "token", "Request::Resume::token")?;
+                let token = raw.as_str().ok_or_else(|| ParsingError::invalid_value("Request::Resume::token", &raw.stringify()))?;
+                Ok(Request::Resume(token.to_string()))
+            },
+            "Query" => Ok(Request::Query(Query::parse_json(value)?)),
+            "Hello" => {
+                let version_raw = field(value, "version", "Request::Hello::version")?;
+                let version = version_raw.as_usize()
+                                         .and_then(|version| u32::try_from(version).ok())
+                                         .ok_or_else(|| ParsingError::invalid_number("Request::Hello::version", &version_raw.stringify()))?;
+                let capabilities = match value.get("capabilities") {
+                    Some(raw) => raw.as_array()
+                                    .ok_or_else(|| ParsingError::invalid_value("Request::Hello::capabilities", &raw.stringify()))?
+                                    .iter()
+                                    .filter_map(|capability| capability.as_str().and_then(Capability::parse))
+                                    .collect(),
+                    None => Vec::new()
+                };
+                Ok(Request::Hello(version, capabilities))
+            },
+            _ => Err(ParsingError::unrecognized_request_type(request_type))
+        }
+    }
+}
+
+impl Color {
+    fn parse_json(value: &Json, role: &'static str) -> Result<Color, ParsingError> {
+        match value.as_str() {
+            Some("Black") => Ok(Color::Black),
+            Some("White") => Ok(Color::White),
+            _ => Err(ParsingError::invalid_value(role, &value.stringify()))
+        }
+    }
+
+    pub(crate) fn stringify_json(&self) -> Json {
+        Json::string(match self {
+            Color::White => "White",
+            Color::Black => "Black"
+        })
+    }
+}
+
+impl Tile {
+    pub(crate) fn stringify_json(&self) -> Json {
+        self.0.map_or(Json::Null, |color| color.stringify_json())
+    }
+}
+
+fn stringify_positions_json(positions: &[Position]) -> Json {
+    Json::Array(positions.iter().map(Position::stringify_json).collect())
+}
+
+fn stringify_statistics_json(statistics: &PerColor<Statistics>) -> Json {
+    let single = |statistics: &Statistics| Json::object(vec![
+        ("mobility", Json::Number(statistics.mobility as f64)),
+        ("potentialMobility", Json::Number(statistics.potential_mobility as f64)),
+        ("frontier", Json::Number(statistics.frontier as f64))
+    ]);
+    Json::object(vec![("black", single(&statistics.black)), ("white", single(&statistics.white))])
+}
+
+impl Response {
+    pub fn stringify_json(&self) -> String {
+        self.to_json().stringify()
+    }
+
+    /* The response to a request with the given id, which is echoed as the "id" field */
+    pub fn stringify_json_tagged(&self, id: Option<&Json>) -> String {
+        let mut value = self.to_json();
+        if let (Some(id), Json::Object(entries)) = (id, &mut value) {
+            entries.insert(0, (String::from("id"), id.clone()));
+        }
+        value.stringify()
+    }
+
+    fn to_json(&self) -> Json {
+        match self {
+            Response::Update(change_set, statistics) => {
+                let tiles = change_set.tiles.iter()
+                                      .map(|(pos, tile)| Json::object(vec![("position", pos.stringify_json()), ("tile", tile.stringify_json())]))
+                                      .collect();
+                let mut entries = vec![
+                    ("type", Json::string("Update")),
+                    ("player", change_set.player.stringify_json()),
+                    ("tiles", Json::Array(tiles))
+                ];
+                if let Some(statistics) = statistics { entries.push(("statistics", stringify_statistics_json(statistics))); }
+                Json::object(entries)
+            },
+            Response::Snapshot(snapshot) => {
+                Json::object(vec![
+                    ("type", Json::string("Snapshot")),
+                    ("player", snapshot.player.stringify_json()),
+                    ("width", Json::Number(snapshot.size.width as f64)),
+                    ("height", Json::Number(snapshot.size.height as f64)),
+                    ("board", Json::String(snapshot.symbols()))
+                ])
+            },
+            Response::Hint(position) => {
+                Json::object(vec![("type", Json::string("Hint")), ("position", position.map_or(Json::Null, |pos| pos.stringify_json()))])
+            },
+            Response::Analysis(analysis) => {
+                Json::object(vec![
+                    ("type", Json::string("Analysis")),
+                    ("stable", Json::object(vec![
+                        ("black", stringify_positions_json(&analysis.stable.black)),
+                        ("white", stringify_positions_json(&analysis.stable.white))
+                    ])),
+                    ("statistics", stringify_statistics_json(&analysis.statistics))
+                ])
+            },
+            Response::Ok => Json::object(vec![("type", Json::string("Ok"))]),
+            Response::Pong => Json::object(vec![("type", Json::string("Pong"))]),
+            Response::Hello(version, capabilities) => {
+                Json::object(vec![
+                    ("type", Json::string("Hello")),
+                    ("version", Json::Number(*version as f64)),
+                    ("capabilities", Json::Array(capabilities.iter().map(|capability| Json::string(capability.stringify())).collect()))
+                ])
+            },
+            Response::Lobby(games) => {
+                let games = games.iter()
+                                 .map(|(code, options)| Json::object(vec![("code", Json::string(code)), ("options", options.stringify_json())]))
+                                 .collect();
+                Json::object(vec![("type", Json::string("Lobby")), ("games", Json::Array(games))])
+            },
+            Response::Room(code, color) => {
+                Json::object(vec![("type", Json::string("Room")), ("code", Json::string(code)), ("color", color.stringify_json())])
+            },
+            Response::Spectators(count) => Json::object(vec![("type", Json::string("Spectators")), ("count", Json::Number(*count as f64))]),
+            Response::Session(token) => Json::object(vec![("type", Json::string("Session")), ("token", Json::string(token))]),
+            Response::Archive(games) => {
+                let games = games.iter()
+                                 .map(|(id, options)| Json::object(vec![("id", Json::Number(*id as f64)), ("options", options.stringify_json())]))
+                                 .collect();
+                Json::object(vec![("type", Json::string("Archive")), ("games", Json::Array(games))])
+            },
+            Response::Player(player) => Json::object(vec![("type", Json::string("Player")), ("player", player.stringify_json())]),
+            Response::Score(discs) => {
+                Json::object(vec![
+                    ("type", Json::string("Score")),
+                    ("black", Json::Number(discs.black as f64)),
+                    ("white", Json::Number(discs.white as f64))
+                ])
+            },
+            Response::MoveNumber(number) => Json::object(vec![("type", Json::string("MoveNumber")), ("number", Json::Number(*number as f64))]),
+            Response::Moves(moves) => {
+                let moves = moves.iter()
+                                 .map(|(pos, player)| Json::object(vec![("position", pos.stringify_json()), ("player", player.stringify_json())]))
+                                 .collect();
+                Json::object(vec![("type", Json::string("Moves")), ("moves", Json::Array(moves))])
+            },
+            Response::Error(details) => Json::object(vec![("type", Json::string("Error")), ("message", Json::string(details))])
+        }
+    }
+}
+
+impl Record {
+    /* The layout described in storage.rs - ids are kept as numbers, which holds them exactly up to 2^53 */
+    pub(crate) fn parse_json(value: &Json) -> Result<Record, ParsingError> {
+        let id_raw = field(value, "id", "Record::id")?;
+        let id = id_raw.as_usize().ok_or_else(|| ParsingError::invalid_number("Record::id", &id_raw.stringify()))? as u64;
+        let options = GameOptions::parse_json(field(value, "options", "Record::options")?)?;
+        let text = |raw: &Json, role: &'static str| match raw {
+            Json::Null => Ok(None),
+            _ => raw.as_str().map(|text| Some(text.to_string())).ok_or_else(|| ParsingError::invalid_value(role, &raw.stringify()))
+        };
+        let code = text(field(value, "code", "Record::code")?, "Record::code")?;
+        let players_raw = field(value, "players", "Record::players")?;
+        let players = PerColor{
+            black: text(field(players_raw, "black", "Record::players::black")?, "Record::players::black")?,
+            white: text(field(players_raw, "white", "Record::players::white")?, "Record::players::white")?
+        };
+        let moves_raw = field(value, "moves", "Record::moves")?;
+        let moves = moves_raw.as_array().ok_or_else(|| ParsingError::invalid_value("Record::moves", &moves_raw.stringify()))?.iter().map(|move_raw| {
+            Ok((Position::parse_json(field(move_raw, "position", "Record::moves::position")?)?,
+                Color::parse_json(field(move_raw, "player", "Record::moves::player")?, "Record::moves::player")?))
+        }).collect::<Result<Vec<(Position, Color)>, ParsingError>>()?;
+        let finished_raw = field(value, "finished", "Record::finished")?;
+        let finished = finished_raw.as_bool().ok_or_else(|| ParsingError::invalid_value("Record::finished", &finished_raw.stringify()))?;
+        Ok(Record{ id, options, code, players, moves, finished })
+    }
+
+    pub(crate) fn stringify_json(&self) -> Json {
+        let text = |text: &Option<String>| text.as_deref().map_or(Json::Null, Json::string);
+        let moves = self.moves.iter()
+                              .map(|(pos, player)| Json::object(vec![("position", pos.stringify_json()), ("player", player.stringify_json())]))
+                              .collect();
+        Json::object(vec![
+            ("id", Json::Number(self.id as f64)),
+            ("options", self.options.stringify_json()),
+            ("code", text(&self.code)),
+            ("players", Json::object(vec![("black", text(&self.players.black)), ("white", text(&self.players.white))])),
+            ("moves", Json::Array(moves)),
+            ("finished", Json::Bool(self.finished))
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::parser::ParsingError;
+    use super::super::json::Json;
+    use super::super::handler::{Request, Response, Setting, Query, Encoding, Capability, Palette, Rgb};
+    use super::super::game::{ChangeSet, Snapshot};
+    use super::super::board::{Tile, Color};
+    use super::super::position::{Position, Size};
+    use super::super::analysis::{Analysis, PerColor, Statistics};
+    use super::super::game::Variant;
+    use super::super::rooms::{GameOptions, TimeControl};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn request_test_simple() {
+        assert_eq!(Request::parse_json("{\"type\": \"Start\"}"), Ok(Request::Start));
+        assert_eq!(Request::parse_json("{\"type\": \"Cancel\"}"), Ok(Request::Cancel));
+        assert_eq!(Request::parse_json("{\"type\": \"Hint\"}"), Ok(Request::Hint));
+        assert_eq!(Request::parse_json("{\"type\": \"Analyze\"}"), Ok(Request::Analyze));
+    }
+
+    #[test]
+    fn request_test_query() {
+        assert_eq!(Request::parse_json("{\"type\": \"Query\", \"query\": \"MoveNumber\"}"), Ok(Request::Query(Query::MoveNumber)));
+        assert_eq!(Request::parse_json("{\"type\": \"Query\"}"), Err(ParsingError::missing("Request::Query::Query", "{\"type\":\"Query\"}")));
+        assert_eq!(Request::parse_json("{\"type\": \"Query\", \"query\": 3}"), Err(ParsingError::invalid_value("Request::Query::Query", "3")));
+    }
+
+    #[test]
+    fn request_test_rooms() {
+        assert_eq!(Request::parse_json("{\"type\": \"Create\"}"), Ok(Request::Create(GameOptions::default())));
+        let options = GameOptions{ size: Size{ width: 8, height: 8 }, variant: Variant::Standard, time_control: Some(TimeControl{ initial: 300, increment: 5 }) };
+        let options_raw = "{\"width\":8,\"height\":8,\"variant\":\"Standard\",\"timeControl\":{\"initial\":300,\"increment\":5}}";
+        assert_eq!(Request::parse_json(&format!("{{\"type\": \"Match\", \"options\": {}}}", options_raw)), Ok(Request::Match(options)));
+        assert_eq!(Request::parse_json("{\"type\": \"Create\", \"options\": {\"width\": 8, \"height\": 8, \"variant\": \"Parallel\"}}"),
+                   Ok(Request::Create(GameOptions{ size: Size{ width: 8, height: 8 }, variant: Variant::Parallel, time_control: None })));
+        assert_eq!(Request::parse_json("{\"type\": \"Lobby\"}"), Ok(Request::Lobby));
+        assert_eq!(Response::Lobby(vec![(String::from("K7Q2MZ"), options)]).stringify_json(),
+                   format!("{{\"type\":\"Lobby\",\"games\":[{{\"code\":\"K7Q2MZ\",\"options\":{}}}]}}", options_raw));
+        assert_eq!(Request::parse_json("{\"type\": \"Join\", \"code\": \"K7Q2MZ\"}"), Ok(Request::Join(String::from("K7Q2MZ"))));
+        assert_eq!(Request::parse_json("{\"type\": \"Join\", \"code\": 7}"), Err(ParsingError::invalid_value("Request::Join::code", "7")));
+        assert_eq!(Request::parse_json("{\"type\": \"Leave\"}"), Ok(Request::Leave));
+        assert_eq!(Request::parse_json("{\"type\": \"Watch\", \"code\": \"K7Q2MZ\"}"), Ok(Request::Watch(String::from("K7Q2MZ"))));
+        assert_eq!(Response::Spectators(2).stringify_json(), "{\"type\":\"Spectators\",\"count\":2}");
+        assert_eq!(Response::Room(String::from("K7Q2MZ"), Color::White).stringify_json(), "{\"type\":\"Room\",\"code\":\"K7Q2MZ\",\"color\":\"White\"}");
+    }
+
+    #[test]
+    fn request_test_session() {
+        assert_eq!(Request::parse_json("{\"type\": \"Session\"}"), Ok(Request::Session));
+        assert_eq!(Request::parse_json("{\"type\": \"Resume\", \"token\": \"0123abcd\"}"), Ok(Request::Resume(String::from("0123abcd"))));
+        assert_eq!(Request::parse_json("{\"type\": \"Resume\"}"), Err(ParsingError::missing("Request::Resume::token", "{\"type\":\"Resume\"}")));
+        assert_eq!(Response::Session(String::from("0123abcd")).stringify_json(), "{\"type\":\"Session\",\"token\":\"0123abcd\"}");
+    }
+
+    #[test]
+    fn request_test_archive() {
+        assert_eq!(Request::parse_json("{\"type\": \"Archive\"}"), Ok(Request::Archive));
+        assert_eq!(Request::parse_json("{\"type\": \"Load\", \"id\": 12}"), Ok(Request::Load(12)));
+        assert_eq!(Request::parse_json("{\"type\": \"Load\", \"id\": \"12\"}"), Err(ParsingError::invalid_number("Request::Load::id", "\"12\"")));
+        assert_eq!(Response::Archive(vec![(3, GameOptions{ size: Size{ width: 4, height: 4 }, variant: Variant::Standard, time_control: None })]).stringify_json(),
+                   "{\"type\":\"Archive\",\"games\":[{\"id\":3,\"options\":{\"width\":4,\"height\":4,\"variant\":\"Standard\",\"timeControl\":null}}]}");
+    }
+
+    #[test]
+    fn request_test_ping() {
+        assert_eq!(Request::parse_json("{\"type\": \"Ping\"}"), Ok(Request::Ping));
+        assert_eq!(Response::Pong.stringify_json(), "{\"type\":\"Pong\"}");
+    }
+
+    #[test]
+    fn response_test_queries() {
+        assert_eq!(Response::Player(Color::White).stringify_json(), "{\"type\":\"Player\",\"player\":\"White\"}");
+        assert_eq!(Response::Score(PerColor{ black: 3, white: 2 }).stringify_json(), "{\"type\":\"Score\",\"black\":3,\"white\":2}");
+        assert_eq!(Response::MoveNumber(1).stringify_json(), "{\"type\":\"MoveNumber\",\"number\":1}");
+        assert_eq!(Response::Moves(vec![(Position{x: 3, y: 4}, Color::Black)]).stringify_json(),
+                   "{\"type\":\"Moves\",\"moves\":[{\"position\":{\"x\":3,\"y\":4},\"player\":\"Black\"}]}");
+    }
+
+    #[test]
+    fn request_test_do_turn() {
+        assert_eq!(Request::parse_json("{\"type\": \"DoTurn\", \"position\": {\"x\": 2, \"y\": 4}}"), Ok(Request::DoTurn(Position{x: 2, y: 4})));
+    }
+
+    #[test]
+    fn request_test_do_turn_invalid() {
+        assert_eq!(Request::parse_json("{\"type\": \"DoTurn\"}"), Err(ParsingError::missing("Request::DoTurn::Position", "{\"type\":\"DoTurn\"}")));
+        assert_eq!(Request::parse_json("{\"type\": \"DoTurn\", \"position\": {\"x\": 2}}"), Err(ParsingError::missing("Position::y", "{\"x\":2}")));
+        assert_eq!(Request::parse_json("{\"type\": \"DoTurn\", \"position\": {\"x\": -2, \"y\": 1}}"), Err(ParsingError::invalid_number("Position::x", "-2")));
+    }
+
+    #[test]
+    fn request_test_set() {
+        assert_eq!(Request::parse_json("{\"type\": \"Set\", \"setting\": \"Statistics\", \"value\": true}"), Ok(Request::Set(Setting::Statistics(true))));
+        assert_eq!(Request::parse_json("{\"type\": \"Set\", \"setting\": \"Encoding\", \"value\": \"Text\"}"), Ok(Request::Set(Setting::Encoding(Encoding::Text))));
+        assert_eq!(Request::parse_json("{\"type\": \"Set\", \"setting\": \"Statistics\", \"value\": \"On\"}"),
+                   Err(ParsingError::invalid_value("Setting::Statistics::value", "\"On\"")));
+    }
+
+    #[test]
+    fn request_test_hello() {
+        assert_eq!(Request::parse_json("{\"type\": \"Hello\", \"version\": 2, \"capabilities\": [\"Statistics\", \"Teleport\", 3]}"),
+                   Ok(Request::Hello(2, vec![Capability::Statistics])));
+        assert_eq!(Request::parse_json("{\"type\": \"Hello\", \"version\": 1}"), Ok(Request::Hello(1, Vec::new())));
+        assert_eq!(Request::parse_json("{\"type\": \"Hello\", \"version\": 1, \"capabilities\": \"Json\"}"),
+                   Err(ParsingError::invalid_value("Request::Hello::capabilities", "\"Json\"")));
+    }
+
+    #[test]
+    fn request_test_invalid() {
+        assert_eq!(Request::parse_json("Start"), Err(ParsingError::invalid_json("Unexpected character at offset 0", "Start")));
+        assert_eq!(Request::parse_json("{}"), Err(ParsingError::missing("Request::type", "{}")));
+        assert_eq!(Request::parse_json("{\"type\": 3}"), Err(ParsingError::invalid_value("Request::type", "3")));
+        assert_eq!(Request::parse_json("{\"type\": \"StartIt\"}"), Err(ParsingError::unrecognized_request_type("StartIt")));
+    }
+
+    #[test]
+    fn response_test_update() {
+        let mut tiles: BTreeMap<Position, Tile> = BTreeMap::new();
+        tiles.insert(Position{x: 4, y: 5}, Tile(Some(Color::White)));
+        assert_eq!(Response::Update(ChangeSet{tiles, player: Color::Black}, None).stringify_json(),
+                   "{\"type\":\"Update\",\"player\":\"Black\",\"tiles\":[{\"position\":{\"x\":4,\"y\":5},\"tile\":\"White\"}]}");
+    }
+
+    #[test]
+    fn response_test_update_row_major() {
+        let mut tiles: BTreeMap<Position, Tile> = BTreeMap::new();
+        tiles.insert(Position{x: 2, y: 1}, Tile(Some(Color::Black)));
+        tiles.insert(Position{x: 5, y: 0}, Tile(None));
+        assert_eq!(Response::Update(ChangeSet{tiles, player: Color::Black}, None).stringify_json(),
+                   "{\"type\":\"Update\",\"player\":\"Black\",\"tiles\":[{\"position\":{\"x\":5,\"y\":0},\"tile\":null},\
+                    {\"position\":{\"x\":2,\"y\":1},\"tile\":\"Black\"}]}");
+    }
+
+    #[test]
+    fn response_test_snapshot() {
+        let snapshot = Snapshot{ size: Size{width: 2, height: 1}, tiles: vec![Tile(Some(Color::White)), Tile(None)], player: Color::Black };
+        assert_eq!(Response::Snapshot(snapshot).stringify_json(), "{\"type\":\"Snapshot\",\"player\":\"Black\",\"width\":2,\"height\":1,\"board\":\"W.\"}");
+    }
+
+    #[test]
+    fn response_test_update_with_statistics() {
+        let mut tiles: BTreeMap<Position, Tile> = BTreeMap::new();
+        tiles.insert(Position{x: 1, y: 0}, Tile(None));
+        let statistics = PerColor{ black: Statistics{ mobility: 1, potential_mobility: 2, frontier: 3 }, white: Statistics::default() };
+        assert_eq!(Response::Update(ChangeSet{tiles, player: Color::White}, Some(statistics)).stringify_json(),
+                   "{\"type\":\"Update\",\"player\":\"White\",\"tiles\":[{\"position\":{\"x\":1,\"y\":0},\"tile\":null}],\
+                    \"statistics\":{\"black\":{\"mobility\":1,\"potentialMobility\":2,\"frontier\":3},\
+                    \"white\":{\"mobility\":0,\"potentialMobility\":0,\"frontier\":0}}}");
+    }
+
+    #[test]
+    fn response_test_hint_analysis_ok_error() {
+        assert_eq!(Response::Hint(Some(Position{x: 3, y: 7})).stringify_json(), "{\"type\":\"Hint\",\"position\":{\"x\":3,\"y\":7}}");
+        assert_eq!(Response::Hint(None).stringify_json(), "{\"type\":\"Hint\",\"position\":null}");
+        let analysis = Analysis{ stable: PerColor{ black: vec![Position{x: 0, y: 0}], white: Vec::new() }, statistics: PerColor::default() };
+        assert!(Response::Analysis(analysis).stringify_json().starts_with("{\"type\":\"Analysis\",\"stable\":{\"black\":[{\"x\":0,\"y\":0}],\"white\":[]},"));
+        assert_eq!(Response::Ok.stringify_json(), "{\"type\":\"Ok\"}");
+        assert_eq!(Response::Hello(2, vec![Capability::Hints]).stringify_json(), "{\"type\":\"Hello\",\"version\":2,\"capabilities\":[\"Hints\"]}");
+        assert_eq!(Response::Error("Say \"no\"".to_string()).stringify_json(), "{\"type\":\"Error\",\"message\":\"Say \\\"no\\\"\"}");
+    }
+
+    #[test]
+    fn request_test_set_palette() {
+        assert_eq!(Request::parse_json("{\"type\": \"Set\", \"setting\": \"Palette\", \"value\": \"Symbolic\"}"),
+                   Ok(Request::Set(Setting::Palette(Palette::Symbolic))));
+        assert_eq!(Request::parse_json("{\"type\": \"Set\", \"setting\": \"Palette\", \"value\": \
+                                        {\"black\": [1, 2, 3], \"white\": [250, 250, 250], \"empty\": [0, 128, 0]}}"),
+                   Ok(Request::Set(Setting::Palette(Palette::Rgb{black: Rgb(1, 2, 3), white: Rgb(250, 250, 250), empty: Rgb(0, 128, 0)}))));
+        assert!(Request::parse_json("{\"type\": \"Set\", \"setting\": \"Palette\", \"value\": \
+                                     {\"black\": [1, 2, 300], \"white\": [250, 250, 250], \"empty\": [0, 128, 0]}}").is_err());
+        assert!(Request::parse_json("{\"type\": \"Set\", \"setting\": \"Palette\", \"value\": \
+                                     {\"black\": [0, 0, 0], \"white\": [0, 0, 0], \"empty\": [0, 128, 0]}}").is_err());
+    }
+
+    #[test]
+    fn request_test_tagged() {
+        assert_eq!(Request::parse_json_tagged("{\"id\": 5, \"type\": \"Start\"}"), (Some(Json::Number(5.0)), Ok(Request::Start)));
+        assert_eq!(Request::parse_json_tagged("{\"id\": \"a\", \"type\": \"Teleport\"}"),
+                   (Some(Json::string("a")), Err(ParsingError::unrecognized_request_type("Teleport"))));
+        assert_eq!(Request::parse_json_tagged("{\"id\": [], \"type\": \"Start\"}").0, None);
+        assert_eq!(Request::parse_json_tagged("{\"id\": 5").0, None);
+    }
+
+    #[test]
+    fn response_test_tagged() {
+        assert_eq!(Response::Ok.stringify_json_tagged(Some(&Json::Number(5.0))), "{\"id\":5,\"type\":\"Ok\"}");
+        assert_eq!(Response::Ok.stringify_json_tagged(None), "{\"type\":\"Ok\"}");
+    }
+}
@@ -0,0 +1,20 @@
+pub mod config;
+pub mod server;
+pub mod websocket;
+pub mod http;
+pub mod nboard;
+pub mod pool;
+pub mod handler;
+pub mod parser;
+pub mod json;
+pub mod json_parser;
+pub mod binary_parser;
+pub mod game;
+pub mod rooms;
+pub mod sessions;
+pub mod storage;
+pub mod search;
+pub mod analysis;
+pub mod board;
+pub mod symmetry;
+pub mod position;
@@ -1,10 +1,62 @@
-mod server;
-mod handler;
-mod parser;
-mod game;
-mod board;
-mod position;
+use reversi_backend::{server, websocket, http, nboard};
+use reversi_backend::config::{Config, USAGE};
+use reversi_backend::pool::Pool;
+use reversi_backend::rooms::Rooms;
+use reversi_backend::sessions::Sessions;
+use reversi_backend::storage::Storage;
+use std::net::TcpListener;
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/* Binds one of the listeners, printing the address it actually got - which tells the port when 0 was asked for */
+fn bind(config: &Config, port: u16, name: &str) -> Result<TcpListener, String> {
+    let address = config.address(port);
+    let listener = TcpListener::bind(&address).map_err(|e| format!("Error while binding the {} listener to {}: {}", name, address, e))?;
+    let local_address = listener.local_addr().map_err(|e| format!("Error while reading the {} listener's address: {}", name, e))?;
+    println!("Serving {} on {}", name, local_address);
+    Ok(listener)
+}
+
+fn run(config: Config) -> Result<(), String> {
+    if config.nboard {
+        nboard::run(config.search_config());
+        return Ok(());
+    }
+
+    let listener = bind(&config, config.port, "requests")?;
+    let websocket_listener = bind(&config, config.websocket_port, "WebSocket")?;
+    let http_listener = bind(&config, config.http_port, "HTTP")?;
+    let storage = Storage::open(&config.data_dir).map_err(|e| format!("Error while opening the game storage in {}: {}", config.data_dir, e))?;
+    let storage = Arc::new(storage);
+    /* Shared games can be joined, and dropped games resumed, from either protocol */
+    let rooms = Arc::new(Mutex::new(Rooms::new()));
+    let sessions = Arc::new(Mutex::new(Sessions::new(Duration::from_secs(config.session_grace))));
+    println!("Restored {} unfinished games from {}", server::restore(&rooms, &sessions, &storage), config.data_dir);
+    let (expiry_rooms, expiry_sessions) = (Arc::clone(&rooms), Arc::clone(&sessions));
+    thread::spawn(move || server::expire_sessions(expiry_rooms, expiry_sessions));
+    /* One cap on the connections of all three listeners */
+    let pool = Arc::new(Pool::new(config.max_connections));
+    let timeouts = config.timeouts();
+    let search_config = config.search_config();
+    let (websocket_rooms, websocket_sessions, websocket_storage, websocket_pool) = (Arc::clone(&rooms), Arc::clone(&sessions), Arc::clone(&storage), Arc::clone(&pool));
+    thread::spawn(move || websocket::serve_incoming(websocket_listener, websocket_rooms, websocket_sessions, Some(websocket_storage), search_config, websocket_pool, timeouts));
+    let http_pool = Arc::clone(&pool);
+    thread::spawn(move || http::serve_incoming(http_listener, http_pool, timeouts));
+    server::serve_incoming(listener, rooms, sessions, Some(storage), search_config, pool, timeouts);
+    Ok(())
+}
 
 fn main() {
-    server::serve_incoming();
+    if std::env::args().skip(1).any(|arg| arg == "--help" || arg == "-h") {
+        println!("{}", USAGE);
+        return;
+    }
+
+    let result = Config::from_env().map_err(|message| format!("{}\n{}", message, USAGE)).and_then(run);
+    if let Err(message) = result {
+        eprintln!("{}", message);
+        process::exit(1);
+    }
 }
@@ -0,0 +1,367 @@
+use super::analysis::PerColor;
+use super::board::{Board, Color};
+use super::game::Game;
+use super::position::Position;
+use super::search;
+use super::search::SearchConfig;
+
+use std::io::{BufRead, Write};
+use std::time::{Duration, Instant};
+
+/*
+The NBoard engine protocol, spoken line by line over stdin/stdout, so the engine can be loaded into NBoard and similar tools.
+Games are set up from GGF, where the board is "BO[8 <cells> <side>]" with "*" for Black, "O" for White and "-" for empty tiles,
+followed by the moves as "B[d3]" and "W[c5]" ("PA" being a pass).
+When the game has a clock ("TI[5:00]"), "go" budgets its search by the time left instead of searching to a fixed depth.
+*/
+
+const NAME: &str = "Reversi";
+
+/* The least time "go" searches for, however little is left on the clock */
+const MIN_THINKING_TIME: f64 = 0.05;
+
+pub struct Engine {
+    game: Game,
+    search_config: SearchConfig,
+    clock: Option<PerColor<f64>>                    /* Seconds left for each side - "None" when the game is untimed */
+}
+
+impl Default for Engine {
+    fn default() -> Engine {
+        Engine::new()
+    }
+}
+
+/* The standard 8x8 starting position */
+fn standard_game() -> Game {
+    let mut board = Board::new(8, 8);
+    board.set(&Position{x: 3, y: 3}, &Color::White);
+    board.set(&Position{x: 4, y: 3}, &Color::Black);
+    board.set(&Position{x: 3, y: 4}, &Color::Black);
+    board.set(&Position{x: 4, y: 4}, &Color::White);
+    Game::with_board(board, Color::Black)
+}
+
+/* A move such as "d3" or "D3/1.5/0.2" - the GUI's evaluation and the seconds taken follow the move. "None" is a pass */
+fn parse_move(source: &str) -> Result<Option<Position>, String> {
+    let token = source.split('/').next().unwrap_or(source).trim().to_ascii_lowercase();
+    if token == "pa" { return Ok(None); }
+
+    let mut chars = token.chars();
+    let x = match chars.next() {
+        Some(c) if c.is_ascii_lowercase() => c as usize - 'a' as usize,
+        _ => return Err(format!("Invalid move: {}", source))
+    };
+    let y: usize = chars.as_str().parse().map_err(|_| format!("Invalid move: {}", source))?;
+    if y == 0 { return Err(format!("Invalid move: {}", source)); }
+    Ok(Some(Position{x, y: y - 1}))
+}
+
+/* The seconds taken by a move such as "D3/1.5/0.2" - 0 when not given */
+fn move_time(source: &str) -> f64 {
+    source.split('/').nth(2).and_then(|time| time.trim().parse::<f64>().ok()).filter(|time| time.is_finite()).unwrap_or(0.0)
+}
+
+/* A GGF time such as "5:00", "1:05:00" or "90" in seconds - anything after a "/" is the increment, which isn't supported */
+fn parse_time(source: &str) -> Result<f64, String> {
+    source.split('/').next().unwrap_or(source).split(':')
+          .map(|part| part.trim().parse::<f64>().ok().filter(|value| value.is_finite() && *value >= 0.0))
+          .try_fold(0.0, |total, part| part.map(|value| total * 60.0 + value))
+          .ok_or_else(|| format!("Invalid time: {}", source))
+}
+
+fn stringify_move(position: Option<Position>) -> String {
+    match position {
+        Some(pos) => format!("{}{}", (b'A' + pos.x as u8) as char, pos.y + 1),
+        None => String::from("PA")
+    }
+}
+
+/* Plays a move - or a pass - for the side to move */
+fn play(game: &mut Game, position: Option<Position>) -> Result<(), String> {
+    match position {
+        Some(position) => game.make_move(position).map(|_| ()),
+        None => game.pass()
+    }.map_err(String::from)
+}
+
+/* The "TAG[value]" pairs of a GGF game, in order */
+fn ggf_tags(source: &str) -> Vec<(String, String)> {
+    let mut tags = Vec::new();
+    let mut name = String::new();
+    let mut chars = source.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '[' => {
+                let value: String = chars.by_ref().take_while(|c| *c != ']').collect();
+                tags.push((name.clone(), value));
+                name.clear();
+            },
+            c if c.is_ascii_alphabetic() => name.push(c),
+            _ => name.clear()
+        }
+    }
+    tags
+}
+
+fn parse_ggf_board(source: &str) -> Result<Game, String> {
+    let tokens: Vec<&str> = source.split_whitespace().collect();
+    if tokens.len() < 3 { return Err(format!("Invalid board: {}", source)); }
+    let size: usize = tokens[0].parse().map_err(|_| format!("Invalid board size: {}", tokens[0]))?;
+    if size == 0 || size * size > 128 { return Err(format!("Unsupported board size: {}", size)); }
+
+    let cells: String = tokens[1..tokens.len() - 1].concat();
+    if cells.chars().count() != size * size { return Err(format!("Invalid board: {}", source)); }
+    let mut board = Board::new(size, size);
+    for (idx, cell) in cells.chars().enumerate() {
+        match cell {
+            '*' => board.set(&board.position(idx), &Color::Black),
+            'O' | 'o' => board.set(&board.position(idx), &Color::White),
+            '-' => {},
+            _ => return Err(format!("Invalid tile: {}", cell))
+        }
+    }
+
+    let player = match tokens[tokens.len() - 1] {
+        "*" => Color::Black,
+        "O" | "o" => Color::White,
+        side => return Err(format!("Invalid side to move: {}", side))
+    };
+    Ok(Game::with_board(board, player))
+}
+
+/* The seconds each side has left once the moves of a GGF game are played - "None" when the game has no clock */
+fn parse_ggf_clock(source: &str) -> Result<Option<PerColor<f64>>, String> {
+    let tags = ggf_tags(source);
+    let time = match tags.iter().find(|(name, _)| name == "TI") {
+        Some((_, time)) => parse_time(time)?,
+        None => return Ok(None)
+    };
+    let mut clock = PerColor{ black: time, white: time };
+    for (name, value) in tags.iter() {
+        match name.as_str() {
+            "B" => clock.black -= move_time(value),
+            "W" => clock.white -= move_time(value),
+            _ => {}
+        }
+    }
+    Ok(Some(clock))
+}
+
+pub fn parse_ggf(source: &str) -> Result<Game, String> {
+    let tags = ggf_tags(source);
+    let board = tags.iter().find(|(name, _)| name == "BO").ok_or_else(|| String::from("Missing BO tag"))?;
+    let mut game = parse_ggf_board(&board.1)?;
+
+    for (name, value) in tags.iter() {
+        let color = match name.as_str() {
+            "B" => Color::Black,
+            "W" => Color::White,
+            _ => continue
+        };
+        /* Some writers leave passes out - the other side must have passed then */
+        if color != game.current_player() { game.pass()?; }
+        play(&mut game, parse_move(value)?)?;
+    }
+    Ok(game)
+}
+
+impl Engine {
+    pub fn new() -> Engine {
+        Engine{ game: standard_game(), search_config: SearchConfig::default(), clock: None }
+    }
+
+    /* How long "go" may think: an even share of the clock over the moves the side to move has left to play */
+    fn thinking_time(&self) -> Option<Duration> {
+        let remaining = *self.clock.as_ref()?.get(&self.game.current_player());
+        let board = self.game.board();
+        let empties = board.iter_all_positions().filter(|pos| !board.taken(pos)).count();
+        Some(Duration::from_secs_f64((remaining / (empties / 2).max(1) as f64).max(MIN_THINKING_TIME)))
+    }
+
+    fn search(&self) -> (Option<Position>, i32) {
+        let (board, player) = (self.game.board(), self.game.current_player());
+        let result = match self.thinking_time() {
+            Some(time) => search::best_move_until(board, player, &self.search_config, Instant::now() + time),
+            None => search::best_move(board, player, &self.search_config)
+        };
+        match result {
+            Some(result) => (Some(result.position), result.discs()),
+            None => (None, 0)
+        }
+    }
+
+    /* The "search" lines answering "hint <n>": the n best moves, best first */
+    fn hint(&self, count: &str) -> Result<Vec<String>, String> {
+        let count: usize = count.trim().parse().ok().filter(|count| *count > 0).ok_or_else(|| format!("Invalid hint count: {}", count.trim()))?;
+        let depth = self.search_config.depth;
+        let mut lines: Vec<String> = search::ranked_moves(self.game.board(), self.game.current_player(), &self.search_config)
+            .into_iter()
+            .take(count)
+            .map(|result| format!("search {} {} 0 {}", stringify_move(Some(result.position)), result.discs(), depth))
+            .collect();
+        if lines.is_empty() { lines.push(format!("search {} 0 0 {}", stringify_move(None), depth)); }
+        lines.push(String::from("status"));
+        Ok(lines)
+    }
+
+    /* Plays a move given as "d3/eval/time", charging the time to the mover's clock */
+    fn play(&mut self, source: &str) -> Result<(), String> {
+        let player = self.game.current_player();
+        play(&mut self.game, parse_move(source)?)?;
+        if let Some(clock) = self.clock.as_mut() { *clock.get_mut(&player) -= move_time(source); }
+        Ok(())
+    }
+
+    fn set(&mut self, command: &str) -> Result<(), String> {
+        let mut split = command.trim().splitn(2, ' ');
+        let (name, value) = (split.next().unwrap_or(""), split.next().unwrap_or("").trim());
+        match name {
+            "depth" => {
+                let depth: u32 = value.parse().map_err(|_| format!("Invalid depth: {}", value))?;
+                self.search_config.depth = depth.max(1);
+            },
+            "game" => {
+                let game = parse_ggf(value)?;
+                self.clock = parse_ggf_clock(value)?;
+                self.game = game;
+            },
+            _ => {}                                                         /* e.g. "contempt", which doesn't apply to this engine */
+        }
+        Ok(())
+    }
+
+    /* Handles a single command, returning the lines to answer with */
+    pub fn handle(&mut self, line: &str) -> Vec<String> {
+        let mut split = line.trim().splitn(2, ' ');
+        let (command, argument) = (split.next().unwrap_or(""), split.next().unwrap_or(""));
+        let result = match command {
+            "nboard" => Ok(vec![format!("set myname {}", NAME)]),
+            "set" => self.set(argument).map(|_| Vec::new()),
+            "move" => self.play(argument).map(|_| Vec::new()),
+            "ping" => Ok(vec![format!("pong {}", argument.trim())]),
+            "go" => {
+                let start = Instant::now();
+                let (position, discs) = self.search();
+                Ok(vec![format!("=== {}/{}/{:.1}", stringify_move(position), discs, start.elapsed().as_secs_f64())])
+            },
+            "hint" => self.hint(argument),
+            "learn" => Ok(vec![String::from("learned")]),
+            "" | "analyze" => Ok(Vec::new()),
+            _ => Err(format!("Unknown command: {}", command))
+        };
+        result.unwrap_or_else(|message| vec![format!("status {}", message)])
+    }
+}
+
+pub fn run(search_config: SearchConfig) {
+    let stdin = std::io::stdin();
+    let mut stdout = std::io::stdout();
+    let mut engine = Engine{ search_config, ..Engine::new() };
+
+    for line in stdin.lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break
+        };
+        for output in engine.handle(&line) {
+            if writeln!(stdout, "{}", output).is_err() { return; }
+        }
+        if stdout.flush().is_err() { return; }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::board::{Tile, Color};
+    use super::super::position::Position;
+    use super::{parse_ggf, parse_ggf_clock, parse_move, parse_time, stringify_move, Engine};
+
+    const START: &str = "(;GM[Othello]PC[NBoard]DT[2024-01-01]PB[a]PW[b]RE[?]TI[5:00]TY[8]\
+                         BO[8 ---------------------------O*------*O--------------------------- *];)";
+
+    #[test]
+    fn parse_move_test() {
+        assert_eq!(parse_move("d3"), Ok(Some(Position{x: 3, y: 2})));
+        assert_eq!(parse_move("F5/1.50/0.2"), Ok(Some(Position{x: 5, y: 4})));
+        assert_eq!(parse_move("PA"), Ok(None));
+        assert!(parse_move("d0").is_err());
+        assert!(parse_move("3d").is_err());
+        assert_eq!(stringify_move(Some(Position{x: 3, y: 2})), "D3");
+        assert_eq!(stringify_move(None), "PA");
+    }
+
+    #[test]
+    fn parse_ggf_test() {
+        let game = parse_ggf(START).unwrap();
+        assert_eq!(game.board().size.width, 8);
+        assert_eq!(game.current_player(), Color::Black);
+        assert_eq!(game.board().get(&Position{x: 3, y: 3}), Tile(Some(Color::White)));
+        assert_eq!(game.board().get(&Position{x: 4, y: 3}), Tile(Some(Color::Black)));
+
+        let game = parse_ggf(&START.replace(";)", "B[d3//1.2]W[c5];)")).unwrap();
+        assert_eq!(game.history().len(), 2);
+        assert_eq!(game.current_player(), Color::Black);
+        assert_eq!(game.board().get(&Position{x: 2, y: 4}), Tile(Some(Color::White)));
+
+        assert!(parse_ggf(&START.replace(";)", "B[a1];)")).is_err());
+        assert!(parse_ggf("(;GM[Othello];)").is_err());
+    }
+
+    #[test]
+    fn parse_ggf_clock_test() {
+        assert_eq!(parse_time("5:00"), Ok(300.0));
+        assert_eq!(parse_time("1:02:03"), Ok(3723.0));
+        assert_eq!(parse_time("90//2:00"), Ok(90.0));
+        assert!(parse_time("5:xx").is_err());
+
+        let clock = parse_ggf_clock(&START.replace(";)", "B[d3//1.5]W[c5//2];)")).unwrap().unwrap();
+        assert_eq!((clock.black, clock.white), (298.5, 298.0));
+        assert!(parse_ggf_clock(&START.replace("TI[5:00]", "")).unwrap().is_none());
+    }
+
+    #[test]
+    fn handle_test() {
+        let mut engine = Engine::new();
+        assert_eq!(engine.handle("nboard 2"), vec!["set myname Reversi"]);
+        assert!(engine.handle("set depth 2").is_empty());
+        /* Untimed, so "go" searches to the depth set */
+        assert!(engine.handle(&format!("set game {}", START.replace("TI[5:00]", ""))).is_empty());
+        assert!(engine.handle("move f5/0.5/1.2").is_empty());
+        assert_eq!(engine.handle("move f5"), vec!["status Position already taken"]);
+        assert_eq!(engine.handle("ping 7"), vec!["pong 7"]);
+
+        let answer = engine.handle("go");
+        assert_eq!(answer.len(), 1);
+        let played = parse_move(&answer[0]["=== ".len()..]).unwrap().unwrap();
+        let mut game = parse_ggf(&START.replace(";)", "B[f5];)")).unwrap();
+        assert!(game.make_move(played).is_ok());
+
+        let hint = engine.handle("hint 1");
+        assert_eq!(hint.len(), 2);
+        assert!(hint[0].starts_with("search "));
+        assert!(hint[0].ends_with(" 0 2"));
+        assert_eq!(hint[1], "status");
+        let hints = engine.handle("hint 3");
+        assert_eq!(hints.len(), 4);
+        assert!(hints[..3].iter().all(|line| line.starts_with("search ")));
+        assert_eq!(engine.handle("hint 0"), vec!["status Invalid hint count: 0"]);
+        assert_eq!(engine.handle("bogus"), vec!["status Unknown command: bogus"]);
+    }
+
+    #[test]
+    fn handle_test_clock() {
+        let mut engine = Engine::new();
+        assert!(engine.handle("set depth 60").is_empty());
+        assert!(engine.handle(&format!("set game {}", START.replace("TI[5:00]", "TI[0:03]"))).is_empty());
+        assert!(engine.handle("move f5//1.5").is_empty());
+        assert_eq!(engine.clock.as_ref().map(|clock| clock.black), Some(1.5));
+
+        /* Without the clock, a 60 ply search would take far longer */
+        let start = std::time::Instant::now();
+        let answer = engine.handle("go");
+        assert!(start.elapsed() < std::time::Duration::from_secs(2));
+        assert!(answer[0].starts_with("=== "));
+        assert_eq!(engine.handle("bogus"), vec!["status Unknown command: bogus"]);
+    }
+}
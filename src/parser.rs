@@ -1,31 +1,78 @@
-use super::handler::{Request, Response};
+use super::handler::{Request, Response, Setting, Query, Encoding, Capability, Palette, Rgb};
 use super::board::{Tile, Color};
 use super::position::Position;
+use super::analysis::{Analysis, PerColor, Statistics};
+use super::game::{ChangeSet, Snapshot, Variant};
+use super::rooms::{GameOptions, TimeControl};
+use super::position::Size;
+use std::collections::BTreeMap;
 
 #[derive(Debug, PartialEq ,Eq)]
 pub struct ParsingError { message: String, token: String}
 
 impl ParsingError {
-    fn invalid_number(role: &'static str, token: &str) -> ParsingError {
+    pub(crate) fn invalid_number(role: &'static str, token: &str) -> ParsingError {
         ParsingError{
             message: format!("Could not be parsed into a number ({})", role),
             token: token.to_string()
         }
     }
 
-    fn missing(role: &'static str, token: &str) -> ParsingError {
+    pub(crate) fn missing(role: &'static str, token: &str) -> ParsingError {
         ParsingError{
             message: format!("Could not find {}", role),
             token: token.to_string()
         }
     }
 
-    fn unrecognized_request_type(token: &str) -> ParsingError {
+    pub(crate) fn invalid_switch(role: &'static str, token: &str) -> ParsingError {
+        ParsingError{
+            message: format!("Must be either On or Off ({})", role),
+            token: token.to_string()
+        }
+    }
+
+    pub(crate) fn invalid_value(role: &'static str, token: &str) -> ParsingError {
+        ParsingError{
+            message: format!("Has an unexpected type or value ({})", role),
+            token: token.to_string()
+        }
+    }
+
+    pub(crate) fn invalid_json(details: &str, token: &str) -> ParsingError {
+        ParsingError{
+            message: format!("Invalid JSON: {}", details),
+            token: token.to_string()
+        }
+    }
+
+    pub(crate) fn unrecognized_encoding(token: &str) -> ParsingError {
+        ParsingError{
+            message: "Could not recognize encoding".to_string(),
+            token: token.to_string()
+        }
+    }
+
+    pub(crate) fn unrecognized_setting(token: &str) -> ParsingError {
+        ParsingError{
+            message: "Could not recognize setting".to_string(),
+            token: token.to_string()
+        }
+    }
+
+    pub(crate) fn unrecognized_request_type(token: &str) -> ParsingError {
         ParsingError{
             message: format!("Could not recognize request type"),
             token: token.to_string()
         }
     }
+
+    pub(crate) fn unrecognized_response_type(token: &str) -> ParsingError {
+        ParsingError{
+            message: format!("Could not recognize response type"),
+            token: token.to_string()
+        }
+    }
 }
 
 impl std::fmt::Display for ParsingError {
@@ -35,8 +82,9 @@ impl std::fmt::Display for ParsingError {
 }
 
 impl Position {
+    /* Positions are written "x.y", but requests have always been accepted as "x,y" as well */
     fn parse(source: &str) -> Result<Position, ParsingError> {
-        let mut split = source.split(",");
+        let mut split = source.split(&[',', '.'][..]);
         let x_str = split.next().expect("Impossible event: First next() of source.split() returned None (Position::parse)");
         let x = x_str.parse::<usize>().or_else(|_| Err(ParsingError::invalid_number("Position::x", x_str)))?;
         let y_str = split.next().ok_or(ParsingError::missing("Position::y", source))?;
@@ -45,8 +93,219 @@ impl Position {
     }
 }
 
+fn parse_switch(role: &'static str, source: &str) -> Result<bool, ParsingError> {
+    match source {
+        "On" => Ok(true),
+        "Off" => Ok(false),
+        _ => Err(ParsingError::invalid_switch(role, source))
+    }
+}
+
+impl Encoding {
+    pub(crate) fn parse(source: &str) -> Result<Encoding, ParsingError> {
+        match source {
+            "Text" => Ok(Encoding::Text),
+            "Json" => Ok(Encoding::Json),
+            "Binary" => Ok(Encoding::Binary),
+            _ => Err(ParsingError::unrecognized_encoding(source))
+        }
+    }
+
+    fn stringify(&self) -> &'static str {
+        match self {
+            Encoding::Text => "Text",
+            Encoding::Json => "Json",
+            Encoding::Binary => "Binary"
+        }
+    }
+}
+
+impl Capability {
+    /* "None" for capabilities this server doesn't know - they are simply not accepted */
+    pub(crate) fn parse(source: &str) -> Option<Capability> {
+        match source {
+            "Json" => Some(Capability::Json),
+            "Snapshots" => Some(Capability::Snapshots),
+            "FullBoards" => Some(Capability::FullBoards),
+            "Statistics" => Some(Capability::Statistics),
+            "Hints" => Some(Capability::Hints),
+            "Analysis" => Some(Capability::Analysis),
+            "Binary" => Some(Capability::Binary),
+            _ => None
+        }
+    }
+
+    pub(crate) fn stringify(&self) -> &'static str {
+        match self {
+            Capability::Json => "Json",
+            Capability::Snapshots => "Snapshots",
+            Capability::FullBoards => "FullBoards",
+            Capability::Statistics => "Statistics",
+            Capability::Hints => "Hints",
+            Capability::Analysis => "Analysis",
+            Capability::Binary => "Binary"
+        }
+    }
+}
+
+impl Rgb {
+    fn parse(source: &str) -> Result<Rgb, ParsingError> {
+        let channels: Vec<u8> = source.split(".").map(|channel| channel.parse::<u8>()).collect::<Result<_, _>>()
+                                      .map_err(|_| ParsingError::invalid_value("Rgb", source))?;
+        match channels.as_slice() {
+            [r, g, b] => Ok(Rgb(*r, *g, *b)),
+            _ => Err(ParsingError::invalid_value("Rgb", source))
+        }
+    }
+
+    fn stringify(&self) -> String {
+        format!("{}.{}.{}", self.0, self.1, self.2)
+    }
+}
+
+impl Palette {
+    /* Either "Symbolic" or the "r.g.b" colors of black, white and empty tiles */
+    fn parse(values: &[&str], source: &str) -> Result<Palette, ParsingError> {
+        match values {
+            ["Symbolic"] => Ok(Palette::Symbolic),
+            [black, white, empty] => Palette::rgb(Rgb::parse(black)?, Rgb::parse(white)?, Rgb::parse(empty)?)
+                                             .ok_or_else(|| ParsingError::invalid_value("Setting::Palette", source)),
+            _ => Err(ParsingError::missing("Setting::Palette::value", source))
+        }
+    }
+
+    fn stringify(&self) -> String {
+        match self {
+            Palette::Rgb{ black, white, empty } => format!("{},{},{}", black.stringify(), white.stringify(), empty.stringify()),
+            Palette::Symbolic => String::from("Symbolic")
+        }
+    }
+}
+
+impl Setting {
+    fn parse(source: &str) -> Result<Setting, ParsingError> {
+        let mut split = source.split(",");
+        let setting_type = split.next().expect("Impossible event: First next() of source.split() returned None (Setting::parse)");
+        match setting_type {
+            "Statistics" => {
+                let value = split.next().ok_or(ParsingError::missing("Setting::Statistics::value", source))?;
+                Ok(Setting::Statistics(parse_switch("Setting::Statistics::value", value)?))
+            },
+            "Encoding" => {
+                let value = split.next().ok_or(ParsingError::missing("Setting::Encoding::value", source))?;
+                Ok(Setting::Encoding(Encoding::parse(value)?))
+            },
+            "Snapshots" => {
+                let value = split.next().ok_or(ParsingError::missing("Setting::Snapshots::value", source))?;
+                Ok(Setting::Snapshots(parse_switch("Setting::Snapshots::value", value)?))
+            },
+            "FullBoards" => {
+                let value = split.next().ok_or(ParsingError::missing("Setting::FullBoards::value", source))?;
+                Ok(Setting::FullBoards(parse_switch("Setting::FullBoards::value", value)?))
+            },
+            "Palette" => {
+                let values: Vec<&str> = split.collect();
+                Ok(Setting::Palette(Palette::parse(&values, source)?))
+            },
+            _ => Err(ParsingError::unrecognized_setting(setting_type))
+        }
+    }
+}
+
+impl Query {
+    pub(crate) fn parse(source: &str) -> Result<Query, ParsingError> {
+        match source {
+            "Board" => Ok(Query::Board),
+            "Player" => Ok(Query::Player),
+            "Score" => Ok(Query::Score),
+            "MoveNumber" => Ok(Query::MoveNumber),
+            "History" => Ok(Query::History),
+            "LastMove" => Ok(Query::LastMove),
+            _ => Err(ParsingError::invalid_value("Request::Query::Query", source))
+        }
+    }
+
+    pub(crate) fn stringify(&self) -> &'static str {
+        match self {
+            Query::Board => "Board",
+            Query::Player => "Player",
+            Query::Score => "Score",
+            Query::MoveNumber => "MoveNumber",
+            Query::History => "History",
+            Query::LastMove => "LastMove"
+        }
+    }
+}
+
+impl Variant {
+    pub(crate) fn parse(source: &str) -> Result<Variant, ParsingError> {
+        match source {
+            "Standard" => Ok(Variant::Standard),
+            "Parallel" => Ok(Variant::Parallel),
+            _ => Err(ParsingError::invalid_value("GameOptions::variant", source))
+        }
+    }
+
+    pub(crate) fn stringify(&self) -> &'static str {
+        match self {
+            Variant::Standard => "Standard",
+            Variant::Parallel => "Parallel"
+        }
+    }
+}
+
+impl GameOptions {
+    /* "<width>x<height>,<variant>" followed by ",<initial>+<increment>" in seconds for timed games, e.g. "8x8,Standard,300+5" */
+    fn parse(source: &str) -> Result<GameOptions, ParsingError> {
+        let mut split = source.split(",");
+        let size_raw = split.next().unwrap_or("");
+        let (width_raw, height_raw) = size_raw.split_once('x').ok_or_else(|| ParsingError::missing("GameOptions::height", size_raw))?;
+        let size = Size{ width: parse_number("GameOptions::width", width_raw)?, height: parse_number("GameOptions::height", height_raw)? };
+        let variant = Variant::parse(split.next().ok_or_else(|| ParsingError::missing("GameOptions::variant", source))?)?;
+        let time_control = match split.next() {
+            Some(time_control_raw) => {
+                let (initial_raw, increment_raw) = time_control_raw.split_once('+').ok_or_else(|| ParsingError::missing("TimeControl::increment", time_control_raw))?;
+                let seconds = |role: &'static str, raw: &str| raw.parse::<u32>().map_err(|_| ParsingError::invalid_number(role, raw));
+                Some(TimeControl{ initial: seconds("TimeControl::initial", initial_raw)?, increment: seconds("TimeControl::increment", increment_raw)? })
+            },
+            None => None
+        };
+        if split.next().is_some() { return Err(ParsingError::invalid_value("GameOptions", source)); }
+        Ok(GameOptions{ size, variant, time_control })
+    }
+
+    fn stringify(&self) -> String {
+        let time_control_raw = self.time_control.map_or(String::new(), |time_control| format!(",{}+{}", time_control.initial, time_control.increment));
+        format!("{}x{},{}{}", self.size.width, self.size.height, self.variant.stringify(), time_control_raw)
+    }
+}
+
+/* Splits off the optional "#<id>;" prefix of a request */
+fn split_request_id(source: &str) -> (Option<&str>, &str) {
+    if let Some(tagged) = source.strip_prefix('#') {
+        let mut split = tagged.splitn(2, ';');
+        if let (Some(id), Some(rest)) = (split.next(), split.next()) {
+            if !id.is_empty() { return (Some(id), rest); }
+        }
+    }
+    (None, source)
+}
+
 impl Request {
+    /*
+    Like parse, but also returns the id of a request starting with "#<id>;".
+    The id is known even when the rest of the request is invalid, so that errors can be told apart too.
+    */
+    pub fn parse_tagged(source: &str) -> (Option<String>, Result<Request, ParsingError>) {
+        let (id, request_raw) = split_request_id(source);
+        (id.map(String::from), Request::parse_untagged(request_raw))
+    }
+
     pub fn parse(source: &str) -> Result<Request, ParsingError> {
+        Request::parse_tagged(source).1
+    }
+
+    fn parse_untagged(source: &str) -> Result<Request, ParsingError> {
         let mut split = source.split(";");
         let request_type: &str = split.next().expect("Impossible event: First next() of source.split() returned None (Request::parse)");
         match request_type {
@@ -56,65 +315,410 @@ impl Request {
                 Ok(Request::DoTurn(Position::parse(details)?))
             },
             "Cancel" => Ok(Request::Cancel),
+            "Hint" => Ok(Request::Hint),
+            "Analyze" => Ok(Request::Analyze),
+            "Set" => {
+                let details = split.next().ok_or(ParsingError::missing("Request::Set::Setting", source))?;
+                Ok(Request::Set(Setting::parse(details)?))
+            },
+            "Create" => Ok(Request::Create(split.next().map_or(Ok(GameOptions::default()), GameOptions::parse)?)),
+            "Lobby" => Ok(Request::Lobby),
+            "Match" => Ok(Request::Match(split.next().map_or(Ok(GameOptions::default()), GameOptions::parse)?)),
+            "Join" => {
+                let code = split.next().filter(|code| !code.is_empty()).ok_or(ParsingError::missing("Request::Join::code", source))?;
+                Ok(Request::Join(code.to_string()))
+            },
+            "Watch" => {
+                let code = split.next().filter(|code| !code.is_empty()).ok_or(ParsingError::missing("Request::Watch::code", source))?;
+                Ok(Request::Watch(code.to_string()))
+            },
+            "Leave" => Ok(Request::Leave),
+            "Session" => Ok(Request::Session),
+            "Archive" => Ok(Request::Archive),
+            "Ping" => Ok(Request::Ping),
+            "Load" => {
+                let id_raw = split.next().ok_or(ParsingError::missing("Request::Load::id", source))?;
+                Ok(Request::Load(id_raw.parse::<u64>().map_err(|_| ParsingError::invalid_number("Request::Load::id", id_raw))?))
+            },
+            "Resume" => {
+                let token = split.next().filter(|token| !token.is_empty()).ok_or(ParsingError::missing("Request::Resume::token", source))?;
+                Ok(Request::Resume(token.to_string()))
+            },
+            "Query" => {
+                let details = split.next().ok_or(ParsingError::missing("Request::Query::Query", source))?;
+                Ok(Request::Query(Query::parse(details)?))
+            },
+            "Hello" => {
+                let version_str = split.next().ok_or(ParsingError::missing("Request::Hello::version", source))?;
+                let version = version_str.parse::<u32>().map_err(|_| ParsingError::invalid_number("Request::Hello::version", version_str))?;
+                let capabilities = split.next().map_or(Vec::new(), |raw| raw.split(",").filter_map(Capability::parse).collect());
+                Ok(Request::Hello(version, capabilities))
+            },
             _ => Err(ParsingError::unrecognized_request_type(request_type))
         }
     }
 }
 
+fn stringify_switch(value: bool) -> &'static str {
+    if value { "On" } else { "Off" }
+}
+
+impl Setting {
+    fn stringify(&self) -> String {
+        match self {
+            Setting::Statistics(include) => format!("Statistics,{}", stringify_switch(*include)),
+            Setting::Encoding(encoding) => format!("Encoding,{}", encoding.stringify()),
+            Setting::Snapshots(snapshots) => format!("Snapshots,{}", stringify_switch(*snapshots)),
+            Setting::FullBoards(full_boards) => format!("FullBoards,{}", stringify_switch(*full_boards)),
+            Setting::Palette(palette) => format!("Palette,{}", palette.stringify())
+        }
+    }
+}
+
+impl Request {
+    pub fn stringify(&self) -> String {
+        match self {
+            Request::Start => String::from("Start"),
+            Request::DoTurn(position) => format!("DoTurn;{}", position.stringify()),
+            Request::Cancel => String::from("Cancel"),
+            Request::Hint => String::from("Hint"),
+            Request::Analyze => String::from("Analyze"),
+            Request::Set(setting) => format!("Set;{}", setting.stringify()),
+            Request::Create(options) => format!("Create;{}", options.stringify()),
+            Request::Lobby => String::from("Lobby"),
+            Request::Match(options) => format!("Match;{}", options.stringify()),
+            Request::Join(code) => format!("Join;{}", code),
+            Request::Watch(code) => format!("Watch;{}", code),
+            Request::Leave => String::from("Leave"),
+            Request::Session => String::from("Session"),
+            Request::Resume(token) => format!("Resume;{}", token),
+            Request::Archive => String::from("Archive"),
+            Request::Load(id) => format!("Load;{}", id),
+            Request::Ping => String::from("Ping"),
+            Request::Query(query) => format!("Query;{}", query.stringify()),
+            Request::Hello(version, capabilities) => format!("Hello;{};{}", version, stringify_capabilities(capabilities))
+        }
+    }
+
+    pub fn stringify_tagged(&self, id: Option<&str>) -> String {
+        match id {
+            Some(id) => format!("#{};{}", id, self.stringify()),
+            None => self.stringify()
+        }
+    }
+}
+
+impl Color {
+    fn parse(source: &str, palette: &Palette) -> Result<Color, ParsingError> {
+        match Tile::parse(source, palette)? {
+            Tile(Some(color)) => Ok(color),
+            Tile(None) => Err(ParsingError::invalid_value("Color", source))
+        }
+    }
+}
+
+impl Tile {
+    fn parse(source: &str, palette: &Palette) -> Result<Tile, ParsingError> {
+        let tile = match palette {
+            Palette::Rgb{ black, white, empty } => {
+                let rgb = Rgb::parse(source).map_err(|_| ParsingError::invalid_value("Tile", source))?;
+                [(black, Tile(Some(Color::Black))), (white, Tile(Some(Color::White))), (empty, Tile(None))].iter()
+                    .find(|(color, _)| **color == rgb)
+                    .map(|(_, tile)| tile.clone())
+            },
+            Palette::Symbolic => {
+                let mut chars = source.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(symbol), None) => Tile::from_symbol(symbol),
+                    _ => None
+                }
+            }
+        };
+        tile.ok_or_else(|| ParsingError::invalid_value("Tile", source))
+    }
+
+    fn from_symbol(symbol: char) -> Option<Tile> {
+        match symbol {
+            'B' => Some(Tile(Some(Color::Black))),
+            'W' => Some(Tile(Some(Color::White))),
+            '.' => Some(Tile(None)),
+            _ => None
+        }
+    }
+}
+
+fn parse_number(role: &'static str, source: &str) -> Result<usize, ParsingError> {
+    source.parse::<usize>().map_err(|_| ParsingError::invalid_number(role, source))
+}
+
+fn parse_positions(source: &str) -> Result<Vec<Position>, ParsingError> {
+    if source.is_empty() { return Ok(Vec::new()); }
+    source.split("|").map(Position::parse).collect()
+}
+
+/* The "Mobility:b,w", "PotentialMobility:b,w" and "Frontier:b,w" sections, in this order */
+fn parse_statistics(sections: &[&str]) -> Result<PerColor<Statistics>, ParsingError> {
+    let section = |idx: usize, name: &'static str| -> Result<(usize, usize), ParsingError> {
+        let raw = sections.get(idx).ok_or_else(|| ParsingError::missing(name, &sections.join(";")))?;
+        let values = raw.strip_prefix(name).and_then(|rest| rest.strip_prefix(':')).ok_or_else(|| ParsingError::missing(name, raw))?;
+        let mut split = values.split(",");
+        let black = parse_number(name, split.next().unwrap_or(""))?;
+        let white = parse_number(name, split.next().ok_or_else(|| ParsingError::missing(name, raw))?)?;
+        Ok((black, white))
+    };
+    let (mobility, potential_mobility, frontier) = (section(0, "Mobility")?, section(1, "PotentialMobility")?, section(2, "Frontier")?);
+    Ok(PerColor{
+        black: Statistics{ mobility: mobility.0, potential_mobility: potential_mobility.0, frontier: frontier.0 },
+        white: Statistics{ mobility: mobility.1, potential_mobility: potential_mobility.1, frontier: frontier.1 }
+    })
+}
+
+impl Response {
+    /* The counterpart of Response::stringify, for clients written in Rust */
+    pub fn parse(source: &str) -> Result<Response, ParsingError> {
+        Response::parse_with(source, &Palette::default())
+    }
+
+    pub fn parse_with(source: &str, palette: &Palette) -> Result<Response, ParsingError> {
+        Response::parse_tagged(source, palette).1
+    }
+
+    /* Like parse_with, but also returns the id echoed in front of the response, if any */
+    pub fn parse_tagged(source: &str, palette: &Palette) -> (Option<String>, Result<Response, ParsingError>) {
+        let (id, response_raw) = split_request_id(source);
+        (id.map(String::from), Response::parse_untagged(response_raw, palette))
+    }
+
+    fn parse_untagged(source: &str, palette: &Palette) -> Result<Response, ParsingError> {
+        let mut split = source.splitn(2, ";");
+        let response_type = split.next().expect("Impossible event: First next() of source.splitn() returned None (Response::parse)");
+        let details = split.next();
+        let details_of = |role: &'static str| details.ok_or_else(|| ParsingError::missing(role, source));
+        match response_type {
+            "Update" => {
+                let sections: Vec<&str> = details_of("Response::Update::player")?.split(";").collect();
+                let (player_raw, tiles_raw) = sections[0].split_once(',').ok_or_else(|| ParsingError::missing("Response::Update::tiles", sections[0]))?;
+                let mut tiles = BTreeMap::new();
+                for tile_raw in tiles_raw.split("|").filter(|tile_raw| !tile_raw.is_empty()) {
+                    let (position_raw, color_raw) = tile_raw.split_once(':').ok_or_else(|| ParsingError::missing("Response::Update::tile", tile_raw))?;
+                    tiles.insert(Position::parse(position_raw)?, Tile::parse(color_raw, palette)?);
+                }
+                let statistics = if sections.len() > 1 { Some(parse_statistics(&sections[1..])?) } else { None };
+                Ok(Response::Update(ChangeSet{ tiles, player: Color::parse(player_raw, palette)? }, statistics))
+            },
+            "Snapshot" => {
+                let details = details_of("Response::Snapshot::player")?;
+                let mut split = details.splitn(4, ",");
+                let player = Color::parse(split.next().unwrap_or(""), palette)?;
+                let width = parse_number("Response::Snapshot::width", split.next().ok_or_else(|| ParsingError::missing("Response::Snapshot::width", details))?)?;
+                let height_raw = split.next().ok_or_else(|| ParsingError::missing("Response::Snapshot::height", details))?;
+                let height = parse_number("Response::Snapshot::height", height_raw)?;
+                let area = width.checked_mul(height).ok_or_else(|| ParsingError::invalid_value("Response::Snapshot::height", height_raw))?;
+                let symbols = split.next().ok_or_else(|| ParsingError::missing("Response::Snapshot::tiles", details))?;
+                let tiles: Vec<Tile> = symbols.chars().map(Tile::from_symbol).collect::<Option<Vec<Tile>>>()
+                                              .ok_or_else(|| ParsingError::invalid_value("Response::Snapshot::tiles", symbols))?;
+                if tiles.len() != area { return Err(ParsingError::invalid_value("Response::Snapshot::tiles", symbols)); }
+                Ok(Response::Snapshot(Snapshot{ size: Size{ width, height }, tiles, player }))
+            },
+            "Hint" => {
+                let position_raw = details_of("Response::Hint::position")?;
+                Ok(Response::Hint(if position_raw.is_empty() { None } else { Some(Position::parse(position_raw)?) }))
+            },
+            "Analysis" => {
+                let sections: Vec<&str> = details_of("Response::Analysis::stable")?.split(";").collect();
+                let stable_raw = sections[0].strip_prefix("Stable:").ok_or_else(|| ParsingError::missing("Response::Analysis::stable", sections[0]))?;
+                let (black_raw, white_raw) = stable_raw.split_once(',').ok_or_else(|| ParsingError::missing("Response::Analysis::stable", stable_raw))?;
+                Ok(Response::Analysis(Analysis{
+                    stable: PerColor{ black: parse_positions(black_raw)?, white: parse_positions(white_raw)? },
+                    statistics: parse_statistics(&sections[1..])?
+                }))
+            },
+            "Ok" => Ok(Response::Ok),
+            "Pong" => Ok(Response::Pong),
+            "Hello" => {
+                let details = details_of("Response::Hello::version")?;
+                let mut split = details.splitn(2, ";");
+                let version_str = split.next().unwrap_or("");
+                let version = version_str.parse::<u32>().map_err(|_| ParsingError::invalid_number("Response::Hello::version", version_str))?;
+                let capabilities = split.next().map_or(Vec::new(), |raw| raw.split(",").filter_map(Capability::parse).collect());
+                Ok(Response::Hello(version, capabilities))
+            },
+            "Lobby" => {
+                let games_raw = details_of("Response::Lobby::games")?;
+                let games = games_raw.split("|").filter(|game_raw| !game_raw.is_empty()).map(|game_raw| {
+                    let (code, options_raw) = game_raw.split_once(':').ok_or_else(|| ParsingError::missing("Response::Lobby::options", game_raw))?;
+                    Ok((code.to_string(), GameOptions::parse(options_raw)?))
+                }).collect::<Result<Vec<(String, GameOptions)>, ParsingError>>()?;
+                Ok(Response::Lobby(games))
+            },
+            "Archive" => {
+                let games_raw = details_of("Response::Archive::games")?;
+                let games = games_raw.split("|").filter(|game_raw| !game_raw.is_empty()).map(|game_raw| {
+                    let (id_raw, options_raw) = game_raw.split_once(':').ok_or_else(|| ParsingError::missing("Response::Archive::options", game_raw))?;
+                    let id = id_raw.parse::<u64>().map_err(|_| ParsingError::invalid_number("Response::Archive::id", id_raw))?;
+                    Ok((id, GameOptions::parse(options_raw)?))
+                }).collect::<Result<Vec<(u64, GameOptions)>, ParsingError>>()?;
+                Ok(Response::Archive(games))
+            },
+            "Room" => {
+                let details = details_of("Response::Room::code")?;
+                let (code, color_raw) = details.split_once(',').ok_or_else(|| ParsingError::missing("Response::Room::color", details))?;
+                Ok(Response::Room(code.to_string(), Color::parse(color_raw, palette)?))
+            },
+            "Session" => Ok(Response::Session(details_of("Response::Session::token")?.to_string())),
+            "Spectators" => Ok(Response::Spectators(parse_number("Response::Spectators::count", details_of("Response::Spectators::count")?)?)),
+            "Player" => Ok(Response::Player(Color::parse(details_of("Response::Player::player")?, palette)?)),
+            "Score" => {
+                let details = details_of("Response::Score::black")?;
+                let (black_raw, white_raw) = details.split_once(',').ok_or_else(|| ParsingError::missing("Response::Score::white", details))?;
+                Ok(Response::Score(PerColor{ black: parse_number("Response::Score::black", black_raw)?, white: parse_number("Response::Score::white", white_raw)? }))
+            },
+            "MoveNumber" => Ok(Response::MoveNumber(parse_number("Response::MoveNumber::number", details_of("Response::MoveNumber::number")?)?)),
+            "Moves" => {
+                let moves_raw = details_of("Response::Moves::moves")?;
+                let moves = moves_raw.split("|").filter(|move_raw| !move_raw.is_empty()).map(|move_raw| {
+                    let (position_raw, color_raw) = move_raw.split_once(':').ok_or_else(|| ParsingError::missing("Response::Moves::player", move_raw))?;
+                    Ok((Position::parse(position_raw)?, Color::parse(color_raw, palette)?))
+                }).collect::<Result<Vec<(Position, Color)>, ParsingError>>()?;
+                Ok(Response::Moves(moves))
+            },
+            "Error" => Ok(Response::Error(details_of("Response::Error::message")?.to_string())),
+            _ => Err(ParsingError::unrecognized_response_type(response_type))
+        }
+    }
+}
+
 impl Position {
     fn stringify(&self) -> String {
         format!("{}.{}", self.x, self.y)
     }
 }
 
+fn stringify_capabilities(capabilities: &[Capability]) -> String {
+    capabilities.iter().map(Capability::stringify).collect::<Vec<&str>>().join(",")
+}
+
+fn stringify_positions(positions: &[Position]) -> String {
+    positions.iter().map(|pos| pos.stringify()).collect::<Vec<String>>().join("|")
+}
+
+fn stringify_statistics(statistics: &PerColor<Statistics>) -> String {
+    let (black, white) = (&statistics.black, &statistics.white);
+    format!("Mobility:{},{};PotentialMobility:{},{};Frontier:{},{}",
+            black.mobility, white.mobility, black.potential_mobility, white.potential_mobility, black.frontier, white.frontier)
+}
+
 impl Color {
-    fn stringify(&self) -> &'static str {
-        match self {
-            Color::White => "255.255.255",
-            Color::Black => "0.0.0"
-        }
+    fn stringify(&self, palette: &Palette) -> String {
+        Tile(Some(*self)).stringify(palette)
     }
 }
 
 impl Tile {
-    fn stringify(&self) -> &'static str {
+    fn stringify(&self, palette: &Palette) -> String {
+        match palette {
+            Palette::Rgb{ black, white, empty } => match &self.0 {
+                Some(Color::Black) => black.stringify(),
+                Some(Color::White) => white.stringify(),
+                None => empty.stringify()
+            },
+            Palette::Symbolic => self.symbol().to_string()
+        }
+    }
+
+    /* A single character per tile, for compact snapshots */
+    pub(crate) fn symbol(&self) -> char {
         match &self.0 {
-            Some(color) => color.stringify(),
-            None => "128.128.128"
+            Some(Color::Black) => 'B',
+            Some(Color::White) => 'W',
+            None => '.'
         }
     }
 }
 
+impl Snapshot {
+    pub(crate) fn symbols(&self) -> String {
+        self.tiles.iter().map(Tile::symbol).collect()
+    }
+}
+
 impl Response {
     pub fn stringify(&self) -> String {
+        self.stringify_with(&Palette::default())
+    }
+
+    pub fn stringify_with(&self, palette: &Palette) -> String {
         match self {
-            Response::Update(change_set) => {
+            Response::Update(change_set, statistics) => {
                 let mut tiles_raw = change_set.tiles.iter()
                                          .map(
                                              |(pos, tile)| {
-                                                 format!("{}:{}", pos.stringify(), tile.stringify())
+                                                 format!("{}:{}", pos.stringify(), tile.stringify(palette))
                                              })
                                          .fold(String::from(""), |acc, val| {
                                              acc + &val + "|"
                                          });
                 if tiles_raw.len() > 0 { tiles_raw.pop(); } // Remove last comma if needed
-                format!("Update;{},{}", change_set.player.stringify(), tiles_raw)
+                let statistics_raw = statistics.as_ref().map_or(String::new(), |statistics| format!(";{}", stringify_statistics(statistics)));
+                format!("Update;{},{}{}", change_set.player.stringify(palette), tiles_raw, statistics_raw)
+            },
+            Response::Snapshot(snapshot) => {
+                format!("Snapshot;{},{},{},{}", snapshot.player.stringify(palette), snapshot.size.width, snapshot.size.height, snapshot.symbols())
+            },
+            Response::Hint(position) => {
+                format!("Hint;{}", position.map_or(String::new(), |pos| pos.stringify()))
+            },
+            Response::Analysis(analysis) => {
+                format!("Analysis;Stable:{},{};{}", stringify_positions(&analysis.stable.black), stringify_positions(&analysis.stable.white),
+                                                     stringify_statistics(&analysis.statistics))
+            },
+            Response::Ok => String::from("Ok"),
+            Response::Pong => String::from("Pong"),
+            Response::Hello(version, capabilities) => format!("Hello;{};{}", version, stringify_capabilities(capabilities)),
+            Response::Room(code, color) => format!("Room;{},{}", code, color.stringify(palette)),
+            Response::Lobby(games) => {
+                let games_raw: Vec<String> = games.iter().map(|(code, options)| format!("{}:{}", code, options.stringify())).collect();
+                format!("Lobby;{}", games_raw.join("|"))
+            },
+            Response::Spectators(count) => format!("Spectators;{}", count),
+            Response::Session(token) => format!("Session;{}", token),
+            Response::Archive(games) => {
+                let games_raw: Vec<String> = games.iter().map(|(id, options)| format!("{}:{}", id, options.stringify())).collect();
+                format!("Archive;{}", games_raw.join("|"))
+            },
+            Response::Player(player) => format!("Player;{}", player.stringify(palette)),
+            Response::Score(discs) => format!("Score;{},{}", discs.black, discs.white),
+            Response::MoveNumber(number) => format!("MoveNumber;{}", number),
+            Response::Moves(moves) => {
+                let moves_raw: Vec<String> = moves.iter().map(|(pos, player)| format!("{}:{}", pos.stringify(), player.stringify(palette))).collect();
+                format!("Moves;{}", moves_raw.join("|"))
             },
             Response::Error(details) => {
                 format!("Error;{}", details)
             }
         }
     }
+
+    /* The response to a request with the given id, which is echoed as a "#<id>;" prefix */
+    pub fn stringify_tagged(&self, id: Option<&str>, palette: &Palette) -> String {
+        match id {
+            Some(id) => format!("#{};{}", id, self.stringify_with(palette)),
+            None => self.stringify_with(palette)
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::ParsingError;
-    use super::super::handler::{Request, Response};
-    use super::super::game::ChangeSet;
+    use super::super::handler::{Request, Response, Setting, Query, Encoding, Capability, Palette, Rgb};
+    use super::super::game::{ChangeSet, Snapshot};
     use super::super::board::{Tile, Color};
-    use super::super::position::Position;
-    use std::collections::HashMap;
+    use super::super::position::{Position, Size};
+    use super::super::analysis::{Analysis, PerColor, Statistics};
+    use super::super::game::Variant;
+    use super::super::rooms::{GameOptions, TimeControl};
+    use std::collections::BTreeMap;
 
     #[test]
     fn request_test_empty() {
@@ -133,6 +737,135 @@ mod tests {
         assert_eq!(Request::parse("Cancel"), Ok(Request::Cancel));
     }
 
+    #[test]
+    fn request_test_hint() {
+        assert_eq!(Request::parse("Hint"), Ok(Request::Hint));
+    }
+
+    #[test]
+    fn request_test_analyze() {
+        assert_eq!(Request::parse("Analyze"), Ok(Request::Analyze));
+    }
+
+    #[test]
+    fn request_test_query() {
+        assert_eq!(Request::parse("Query;Board"), Ok(Request::Query(Query::Board)));
+        assert_eq!(Request::parse("Query;LastMove"), Ok(Request::Query(Query::LastMove)));
+        assert_eq!(Request::parse("Query"), Err(ParsingError::missing("Request::Query::Query", "Query")));
+        assert_eq!(Request::parse("Query;Weather"), Err(ParsingError::invalid_value("Request::Query::Query", "Weather")));
+    }
+
+    #[test]
+    fn request_test_rooms() {
+        assert_eq!(Request::parse("Create"), Ok(Request::Create(GameOptions::default())));
+        let options = GameOptions{ size: Size{width: 8, height: 6}, variant: Variant::Parallel, time_control: Some(TimeControl{initial: 300, increment: 5}) };
+        assert_eq!(Request::parse("Match;8x6,Parallel,300+5"), Ok(Request::Match(options)));
+        assert_eq!(Request::parse("Create;8x8,Standard"), Ok(Request::Create(GameOptions{ size: Size{width: 8, height: 8}, ..GameOptions::default() })));
+        assert_eq!(Request::parse("Create;8x8,Chess"), Err(ParsingError::invalid_value("GameOptions::variant", "Chess")));
+        assert_eq!(Request::parse("Create;8x8,Standard,300"), Err(ParsingError::missing("TimeControl::increment", "300")));
+        assert_eq!(Request::parse("Lobby"), Ok(Request::Lobby));
+        assert_eq!(Response::Lobby(vec![(String::from("K7Q2MZ"), options), (String::from("AAAAAA"), GameOptions::default())]).stringify(),
+                   "Lobby;K7Q2MZ:8x6,Parallel,300+5|AAAAAA:10x10,Standard");
+        assert_eq!(Request::parse("Join;K7Q2MZ"), Ok(Request::Join(String::from("K7Q2MZ"))));
+        assert_eq!(Request::parse("Join;"), Err(ParsingError::missing("Request::Join::code", "Join;")));
+        assert_eq!(Request::parse("Leave"), Ok(Request::Leave));
+        assert_eq!(Request::parse("Watch;K7Q2MZ"), Ok(Request::Watch(String::from("K7Q2MZ"))));
+        assert_eq!(Request::parse("Watch"), Err(ParsingError::missing("Request::Watch::code", "Watch")));
+        assert_eq!(Response::Spectators(3).stringify(), "Spectators;3");
+        assert_eq!(Response::Room(String::from("K7Q2MZ"), Color::Black).stringify(), "Room;K7Q2MZ,0.0.0");
+    }
+
+    #[test]
+    fn request_test_session() {
+        assert_eq!(Request::parse("Session"), Ok(Request::Session));
+        assert_eq!(Request::parse("Resume;0123abcd"), Ok(Request::Resume(String::from("0123abcd"))));
+        assert_eq!(Request::parse("Resume;"), Err(ParsingError::missing("Request::Resume::token", "Resume;")));
+        assert_eq!(Response::Session(String::from("0123abcd")).stringify(), "Session;0123abcd");
+    }
+
+    #[test]
+    fn request_test_archive() {
+        assert_eq!(Request::parse("Archive"), Ok(Request::Archive));
+        assert_eq!(Request::parse("Load;12"), Ok(Request::Load(12)));
+        assert_eq!(Request::parse("Load;x"), Err(ParsingError::invalid_number("Request::Load::id", "x")));
+        assert_eq!(Response::Archive(vec![(3, GameOptions::default()), (12, GameOptions{ variant: Variant::Parallel, ..GameOptions::default() })]).stringify(),
+                   "Archive;3:10x10,Standard|12:10x10,Parallel");
+    }
+
+    #[test]
+    fn request_test_ping() {
+        assert_eq!(Request::parse("Ping"), Ok(Request::Ping));
+        assert_eq!(Response::Pong.stringify(), "Pong");
+    }
+
+    #[test]
+    fn request_test_set() {
+        assert_eq!(Request::parse("Set;Statistics,On"), Ok(Request::Set(Setting::Statistics(true))));
+        assert_eq!(Request::parse("Set;Statistics,Off"), Ok(Request::Set(Setting::Statistics(false))));
+    }
+
+    #[test]
+    fn request_test_set_encoding() {
+        assert_eq!(Request::parse("Set;Encoding,Json"), Ok(Request::Set(Setting::Encoding(Encoding::Json))));
+        assert_eq!(Request::parse("Set;Encoding,Text"), Ok(Request::Set(Setting::Encoding(Encoding::Text))));
+        assert_eq!(Request::parse("Set;Encoding,Xml"), Err(ParsingError::unrecognized_encoding("Xml")));
+    }
+
+    #[test]
+    fn request_test_tagged() {
+        assert_eq!(Request::parse_tagged("#7;DoTurn;3,4"), (Some(String::from("7")), Ok(Request::DoTurn(Position{x: 3, y: 4}))));
+        assert_eq!(Request::parse_tagged("#a-1;Teleport"), (Some(String::from("a-1")), Err(ParsingError::unrecognized_request_type("Teleport"))));
+        assert_eq!(Request::parse_tagged("Start"), (None, Ok(Request::Start)));
+        assert_eq!(Request::parse_tagged("#;Start"), (None, Err(ParsingError::unrecognized_request_type("#"))));
+        assert_eq!(Request::parse("#12;Cancel"), Ok(Request::Cancel));
+    }
+
+    #[test]
+    fn request_test_set_palette() {
+        assert_eq!(Request::parse("Set;Palette,Symbolic"), Ok(Request::Set(Setting::Palette(Palette::Symbolic))));
+        assert_eq!(Request::parse("Set;Palette,1.2.3,250.250.250,0.128.0"),
+                   Ok(Request::Set(Setting::Palette(Palette::Rgb{black: Rgb(1, 2, 3), white: Rgb(250, 250, 250), empty: Rgb(0, 128, 0)}))));
+        assert_eq!(Request::parse("Set;Palette,1.2.3,1.2.3,0.128.0"),
+                   Err(ParsingError::invalid_value("Setting::Palette", "Palette,1.2.3,1.2.3,0.128.0")));
+        assert_eq!(Request::parse("Set;Palette,1.2.300,4.5.6,7.8.9"), Err(ParsingError::invalid_value("Rgb", "1.2.300")));
+        assert_eq!(Request::parse("Set;Palette,Rainbow"), Err(ParsingError::missing("Setting::Palette::value", "Palette,Rainbow")));
+    }
+
+    #[test]
+    fn response_test_symbolic() {
+        let mut tiles = BTreeMap::new();
+        tiles.insert(Position{x: 4, y: 5}, Tile(Some(Color::White)));
+        tiles.insert(Position{x: 8, y: 5}, Tile(None));
+        assert_eq!(Response::Update(ChangeSet{tiles, player: Color::Black}, None).stringify_with(&Palette::Symbolic), "Update;B,4.5:W|8.5:.");
+    }
+
+    #[test]
+    fn request_test_hello() {
+        assert_eq!(Request::parse("Hello;2;Json,Hints"), Ok(Request::Hello(2, vec![Capability::Json, Capability::Hints])));
+        assert_eq!(Request::parse("Hello;3;Telepathy,FullBoards"), Ok(Request::Hello(3, vec![Capability::FullBoards])));
+        assert_eq!(Request::parse("Hello;1"), Ok(Request::Hello(1, Vec::new())));
+        assert_eq!(Request::parse("Hello;1;"), Ok(Request::Hello(1, Vec::new())));
+        assert_eq!(Request::parse("Hello"), Err(ParsingError::missing("Request::Hello::version", "Hello")));
+        assert_eq!(Request::parse("Hello;v2"), Err(ParsingError::invalid_number("Request::Hello::version", "v2")));
+    }
+
+    #[test]
+    fn request_test_set_full_boards() {
+        assert_eq!(Request::parse("Set;FullBoards,Off"), Ok(Request::Set(Setting::FullBoards(false))));
+    }
+
+    #[test]
+    fn request_test_set_snapshots() {
+        assert_eq!(Request::parse("Set;Snapshots,On"), Ok(Request::Set(Setting::Snapshots(true))));
+    }
+
+    #[test]
+    fn request_test_set_invalid() {
+        assert_eq!(Request::parse("Set"), Err(ParsingError::missing("Request::Set::Setting", "Set")));
+        assert_eq!(Request::parse("Set;Colors,On"), Err(ParsingError::unrecognized_setting("Colors")));
+        assert_eq!(Request::parse("Set;Statistics,Yes"), Err(ParsingError::invalid_switch("Setting::Statistics::value", "Yes")));
+    }
+
     #[test]
     fn request_test_do_turn() {
         assert_eq!(Request::parse("DoTurn;2,4"), Ok(Request::DoTurn(Position{x: 2, y: 4})));
@@ -160,32 +893,202 @@ mod tests {
     
     #[test]
     fn request_test_do_turn_position_wrong_delimiter() {
-        assert_eq!(Request::parse("DoTurn;2.4"), Err(ParsingError::invalid_number("Position::x", "2.4")));
+        assert_eq!(Request::parse("DoTurn;2:4"), Err(ParsingError::invalid_number("Position::x", "2:4")));
+    }
+
+    #[test]
+    fn request_test_do_turn_position_dot_delimiter() {
+        assert_eq!(Request::parse("DoTurn;2.4"), Ok(Request::DoTurn(Position{x: 2, y: 4})));
     }
 
     #[test]
     fn response_test_update_no_pairs() {
-        let tiles: HashMap<Position, Tile> = HashMap::new();
-        assert_eq!(Response::Update(ChangeSet{tiles: tiles, player: Color::Black}).stringify(), "Update;0.0.0,")
+        let tiles: BTreeMap<Position, Tile> = BTreeMap::new();
+        assert_eq!(Response::Update(ChangeSet{tiles: tiles, player: Color::Black}, None).stringify(), "Update;0.0.0,")
     }
     #[test]
     fn response_test_update_one_pair() {
-        let mut tiles: HashMap<Position, Tile> = HashMap::new();
+        let mut tiles: BTreeMap<Position, Tile> = BTreeMap::new();
         tiles.insert(Position{x: 4, y: 5}, Tile(Some(Color::White)));
-        assert_eq!(Response::Update(ChangeSet{tiles: tiles, player: Color::White}).stringify(), "Update;255.255.255,4.5:255.255.255")
+        assert_eq!(Response::Update(ChangeSet{tiles: tiles, player: Color::White}, None).stringify(), "Update;255.255.255,4.5:255.255.255")
     }
     #[test]
     fn response_test_update_two_pairs() {
-        let mut tiles: HashMap<Position, Tile> = HashMap::new();
+        let mut tiles: BTreeMap<Position, Tile> = BTreeMap::new();
         tiles.insert(Position{x: 8, y: 5}, Tile(None));
         tiles.insert(Position{x: 4, y: 5}, Tile(Some(Color::White)));
-        let actual = Response::Update(ChangeSet{tiles: tiles, player: Color::Black}).stringify();
-        let expected1 = "Update;0.0.0,4.5:255.255.255|8.5:128.128.128";
-        let expected2 = "Update;0.0.0,8.5:128.128.128|4.5:255.255.255";
-        assert!(actual == expected1 || actual == expected2, "{} isn't equeal to {} nor {}", actual, expected1, expected2);
+        assert_eq!(Response::Update(ChangeSet{tiles: tiles, player: Color::Black}, None).stringify(), "Update;0.0.0,4.5:255.255.255|8.5:128.128.128");
+    }
+    #[test]
+    fn response_test_update_row_major() {
+        let mut tiles: BTreeMap<Position, Tile> = BTreeMap::new();
+        tiles.insert(Position{x: 0, y: 2}, Tile(Some(Color::Black)));
+        tiles.insert(Position{x: 9, y: 0}, Tile(Some(Color::White)));
+        tiles.insert(Position{x: 1, y: 2}, Tile(None));
+        tiles.insert(Position{x: 2, y: 1}, Tile(Some(Color::Black)));
+        assert_eq!(Response::Update(ChangeSet{tiles, player: Color::White}, None).stringify(),
+                   "Update;255.255.255,9.0:255.255.255|2.1:0.0.0|0.2:0.0.0|1.2:128.128.128");
+    }
+    #[test]
+    fn response_test_snapshot() {
+        let snapshot = Snapshot{
+            size: Size{width: 3, height: 2},
+            tiles: vec![Tile(None), Tile(Some(Color::Black)), Tile(None), Tile(Some(Color::White)), Tile(None), Tile(None)],
+            player: Color::White
+        };
+        assert_eq!(Response::Snapshot(snapshot).stringify(), "Snapshot;255.255.255,3,2,.B.W..");
+    }
+    #[test]
+    fn response_test_hint() {
+        assert_eq!(Response::Hint(Some(Position{x: 3, y: 7})).stringify(), "Hint;3.7");
+        assert_eq!(Response::Hint(None).stringify(), "Hint;");
+    }
+    #[test]
+    fn response_test_analysis() {
+        let stable = PerColor{ black: vec![Position{x: 0, y: 0}, Position{x: 1, y: 0}], white: Vec::new() };
+        let statistics = PerColor{
+            black: Statistics{ mobility: 1, potential_mobility: 2, frontier: 3 },
+            white: Statistics{ mobility: 4, potential_mobility: 5, frontier: 6 }
+        };
+        assert_eq!(Response::Analysis(Analysis{ stable, statistics }).stringify(),
+                   "Analysis;Stable:0.0|1.0,;Mobility:1,4;PotentialMobility:2,5;Frontier:3,6");
+    }
+    #[test]
+    fn response_test_update_with_statistics() {
+        let statistics = PerColor{ black: Statistics::default(), white: Statistics{ mobility: 2, potential_mobility: 0, frontier: 1 } };
+        assert_eq!(Response::Update(ChangeSet{tiles: BTreeMap::new(), player: Color::White}, Some(statistics)).stringify(),
+                   "Update;255.255.255,;Mobility:0,2;PotentialMobility:0,0;Frontier:0,1");
+    }
+    #[test]
+    fn response_test_hello() {
+        assert_eq!(Response::Hello(2, vec![Capability::Snapshots, Capability::Analysis]).stringify(), "Hello;2;Snapshots,Analysis");
+        assert_eq!(Response::Hello(2, Vec::new()).stringify(), "Hello;2;");
+    }
+
+    #[test]
+    fn response_test_queries() {
+        assert_eq!(Response::Player(Color::Black).stringify(), "Player;0.0.0");
+        assert_eq!(Response::Score(PerColor{black: 4, white: 1}).stringify(), "Score;4,1");
+        assert_eq!(Response::MoveNumber(2).stringify(), "MoveNumber;2");
+        let moves = Response::Moves(vec![(Position{x: 3, y: 4}, Color::Black), (Position{x: 3, y: 3}, Color::White)]);
+        assert_eq!(moves.stringify_with(&Palette::Symbolic), "Moves;3.4:B|3.3:W");
+        assert_eq!(Response::Moves(Vec::new()).stringify(), "Moves;");
+    }
+
+    #[test]
+    fn response_test_ok() {
+        assert_eq!(Response::Ok.stringify(), "Ok");
     }
     #[test]
     fn response_test_error() {
         assert_eq!(Response::Error("Custom error message".to_string()).stringify(), "Error;Custom error message");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn response_test_tagged() {
+        assert_eq!(Response::Ok.stringify_tagged(Some("12"), &Palette::default()), "#12;Ok");
+        assert_eq!(Response::Error(String::from("Oops")).stringify_tagged(None, &Palette::Symbolic), "Error;Oops");
+    }
+
+    #[test]
+    fn request_test_round_trip() {
+        let requests = vec![
+            Request::Start,
+            Request::DoTurn(Position{x: 2, y: 4}),
+            Request::Cancel,
+            Request::Hint,
+            Request::Analyze,
+            Request::Set(Setting::Statistics(true)),
+            Request::Set(Setting::Encoding(Encoding::Json)),
+            Request::Set(Setting::Snapshots(false)),
+            Request::Set(Setting::FullBoards(true)),
+            Request::Set(Setting::Palette(Palette::Symbolic)),
+            Request::Set(Setting::Palette(Palette::Rgb{black: Rgb(1, 2, 3), white: Rgb(4, 5, 6), empty: Rgb(7, 8, 9)})),
+            Request::Create(GameOptions::default()),
+            Request::Match(GameOptions{ size: Size{width: 4, height: 6}, variant: Variant::Parallel, time_control: Some(TimeControl{initial: 60, increment: 0}) }),
+            Request::Lobby,
+            Request::Join(String::from("K7Q2MZ")),
+            Request::Watch(String::from("K7Q2MZ")),
+            Request::Leave,
+            Request::Session,
+            Request::Archive,
+            Request::Load(42),
+            Request::Ping,
+            Request::Resume(String::from("0123456789abcdef0123456789abcdef")),
+            Request::Query(Query::Score),
+            Request::Query(Query::History),
+            Request::Hello(2, vec![Capability::Json, Capability::Hints]),
+            Request::Hello(1, Vec::new())
+        ];
+        for request in requests {
+            assert_eq!(Request::parse(&request.stringify()), Ok(request));
+        }
+        assert_eq!(Request::DoTurn(Position{x: 2, y: 4}).stringify_tagged(Some("9")), "#9;DoTurn;2.4");
+    }
+
+    #[test]
+    fn response_test_round_trip() {
+        let mut tiles = BTreeMap::new();
+        tiles.insert(Position{x: 4, y: 5}, Tile(Some(Color::White)));
+        tiles.insert(Position{x: 8, y: 5}, Tile(None));
+        let statistics = PerColor{
+            black: Statistics{mobility: 1, potential_mobility: 2, frontier: 3},
+            white: Statistics{mobility: 4, potential_mobility: 5, frontier: 6}
+        };
+        let responses = vec![
+            Response::Update(ChangeSet{tiles: tiles.clone(), player: Color::Black}, None),
+            Response::Update(ChangeSet{tiles, player: Color::White}, Some(statistics)),
+            Response::Update(ChangeSet{tiles: BTreeMap::new(), player: Color::White}, None),
+            Response::Snapshot(Snapshot{
+                size: Size{width: 2, height: 2},
+                tiles: vec![Tile(Some(Color::Black)), Tile(None), Tile(None), Tile(Some(Color::White))],
+                player: Color::White
+            }),
+            Response::Hint(Some(Position{x: 3, y: 4})),
+            Response::Hint(None),
+            Response::Analysis(Analysis{
+                stable: PerColor{black: vec![Position{x: 0, y: 0}, Position{x: 1, y: 0}], white: Vec::new()},
+                statistics
+            }),
+            Response::Ok,
+            Response::Pong,
+            Response::Hello(2, vec![Capability::Snapshots, Capability::Analysis]),
+            Response::Room(String::from("K7Q2MZ"), Color::White),
+            Response::Spectators(0),
+            Response::Session(String::from("0123456789abcdef0123456789abcdef")),
+            Response::Archive(vec![(1, GameOptions::default()), (7, GameOptions{ time_control: Some(TimeControl{initial: 60, increment: 1}), ..GameOptions::default() })]),
+            Response::Archive(Vec::new()),
+            Response::Lobby(vec![(String::from("K7Q2MZ"), GameOptions::default())]),
+            Response::Lobby(Vec::new()),
+            Response::Player(Color::White),
+            Response::Score(PerColor{black: 12, white: 3}),
+            Response::MoveNumber(7),
+            Response::Moves(vec![(Position{x: 3, y: 4}, Color::Black), (Position{x: 3, y: 3}, Color::White)]),
+            Response::Moves(Vec::new()),
+            Response::Error(String::from("Something; went wrong"))
+        ];
+        let palettes = [Palette::default(), Palette::Symbolic, Palette::Rgb{black: Rgb(1, 2, 3), white: Rgb(4, 5, 6), empty: Rgb(7, 8, 9)}];
+        for response in responses {
+            for palette in palettes.iter() {
+                assert_eq!(Response::parse_with(&response.stringify_with(palette), palette), Ok(response.clone()));
+            }
+        }
+    }
+
+    #[test]
+    fn response_test_parse_tagged() {
+        assert_eq!(Response::parse_tagged("#3;Ok", &Palette::default()), (Some(String::from("3")), Ok(Response::Ok)));
+        assert_eq!(Response::parse_tagged("Error;No game is running", &Palette::default()), (None, Ok(Response::Error(String::from("No game is running")))));
+    }
+
+    #[test]
+    fn response_test_parse_invalid() {
+        assert_eq!(Response::parse("Teleport"), Err(ParsingError::unrecognized_response_type("Teleport")));
+        assert_eq!(Response::parse("Update;1.2.3,"), Err(ParsingError::invalid_value("Tile", "1.2.3")));
+        assert_eq!(Response::parse("Update;128.128.128,"), Err(ParsingError::invalid_value("Color", "128.128.128")));
+        assert_eq!(Response::parse("Snapshot;0.0.0,2,2,B.."), Err(ParsingError::invalid_value("Response::Snapshot::tiles", "B..")));
+        assert_eq!(Response::parse(&format!("Snapshot;0.0.0,{},{},B", usize::MAX, usize::MAX)),
+                   Err(ParsingError::invalid_value("Response::Snapshot::height", &usize::MAX.to_string())));
+        assert_eq!(Response::parse("Hint"), Err(ParsingError::missing("Response::Hint::position", "Hint")));
+    }
+}
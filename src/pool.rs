@@ -0,0 +1,171 @@
+use std::net::TcpStream;
+use std::io::{Read, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+
+/*
+The threads serving connections. A connection holds its worker until it's gone, so the pool's size caps how many are served at once -
+a connection coming in while every worker is busy is turned away instead of queued.
+Workers are spawned as they're first needed, and kept afterwards for the connections to come.
+*/
+
+/* What a connection turned away is told */
+pub const REJECTION: &str = "Too many connections - try again later";
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+struct Workers {
+    spawned: usize,
+    idle: usize                                     /* Spawned workers with no job, and none claimed */
+}
+
+pub struct Pool {
+    size: usize,
+    workers: Arc<Mutex<Workers>>,
+    jobs: Sender<Job>,
+    queue: Arc<Mutex<Receiver<Job>>>
+}
+
+/* A worker set aside for a job. Dropped unused, the worker is free again */
+pub struct Claim<'a> {
+    pool: &'a Pool,
+    used: bool
+}
+
+fn lock_workers(workers: &Mutex<Workers>) -> MutexGuard<'_, Workers> {
+    workers.lock().expect("Impossible event: a thread panicked while holding the workers (pool::lock_workers)")
+}
+
+/* Runs jobs until the pool is gone. A job which panics is reported, and the worker goes on with the next one */
+fn work(workers: Arc<Mutex<Workers>>, queue: Arc<Mutex<Receiver<Job>>>) {
+    loop {
+        let job = match queue.lock().expect("Impossible event: a thread panicked while holding the job queue (pool::work)").recv() {
+            Ok(job) => job,
+            Err(_) => return
+        };
+        if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+            println!("A connection's thread panicked");
+        }
+        lock_workers(&workers).idle += 1;
+    }
+}
+
+impl Pool {
+    pub fn new(size: usize) -> Pool {
+        let (jobs, queue) = mpsc::channel();
+        Pool{ size, workers: Arc::new(Mutex::new(Workers{ spawned: 0, idle: 0 })), jobs, queue: Arc::new(Mutex::new(queue)) }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /* How many workers are running a job, or claimed for one */
+    pub fn busy(&self) -> usize {
+        let workers = lock_workers(&self.workers);
+        workers.spawned - workers.idle
+    }
+
+    /* Sets a worker aside, spawning one if none is idle - "None" when all of them are busy */
+    pub fn claim(&self) -> Option<Claim<'_>> {
+        let mut workers = lock_workers(&self.workers);
+        if workers.idle > 0 {
+            workers.idle -= 1;
+        } else if workers.spawned < self.size {
+            let (pool_workers, queue) = (Arc::clone(&self.workers), Arc::clone(&self.queue));
+            thread::spawn(move || work(pool_workers, queue));
+            workers.spawned += 1;
+        } else {
+            return None;
+        }
+        Some(Claim{ pool: self, used: false })
+    }
+}
+
+impl Claim<'_> {
+    pub fn run(mut self, job: impl FnOnce() + Send + 'static) {
+        self.used = true;
+        self.pool.jobs.send(Box::new(job)).expect("Impossible event: every worker is gone while the pool is alive (pool::Claim::run)");
+    }
+}
+
+impl Drop for Claim<'_> {
+    fn drop(&mut self) {
+        if !self.used { lock_workers(&self.pool.workers).idle += 1; }
+    }
+}
+
+/*
+Answers a connection which won't be served, and closes it - without ever blocking, as this runs on the thread accepting connections.
+What the client already sent is read first - closing a socket with unread data resets the connection, which could lose the answer.
+The answer is written in a single attempt: it fits in a fresh socket's send buffer, and whatever doesn't is dropped.
+*/
+pub(crate) fn turn_away(mut stream: TcpStream, response: &[u8]) {
+    if let Err(e) = stream.set_nonblocking(true) {
+        println!("Error while turning a connection away: {}", e);
+        return;
+    }
+    let mut discarded = [0u8; 1024];
+    while matches!(stream.read(&mut discarded), Ok(len) if len > 0) {}
+    match stream.write(response) {
+        Ok(len) if len < response.len() => println!("Error while turning a connection away: wrote {} of {} bytes", len, response.len()),
+        Err(e) => println!("Error while turning a connection away: {}", e),
+        _ => {}
+    }
+    let _ = stream.shutdown(std::net::Shutdown::Both);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{turn_away, Pool};
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::mpsc;
+    use std::thread;
+
+    #[test]
+    fn claim_test() {
+        let pool = Pool::new(2);
+        let first = pool.claim().unwrap();
+        let second = pool.claim().unwrap();
+        assert!(pool.claim().is_none());
+        assert_eq!(pool.busy(), 2);
+        drop(second);
+        assert_eq!(pool.busy(), 1);
+
+        let (done, finished) = mpsc::channel();
+        first.run(move || done.send(()).unwrap());
+        finished.recv().unwrap();
+        while pool.busy() > 0 { thread::yield_now(); }
+        let mut claims = Vec::new();
+        while let Some(claim) = pool.claim() { claims.push(claim); }
+        assert_eq!(claims.len(), pool.size());
+    }
+
+    #[test]
+    fn claim_test_panicking_job() {
+        let pool = Pool::new(1);
+        pool.claim().unwrap().run(|| panic!("Expected by the test"));
+        let (done, finished) = mpsc::channel();
+        let claim = loop {
+            if let Some(claim) = pool.claim() { break claim; }
+            thread::yield_now();
+        };
+        claim.run(move || done.send(()).unwrap());
+        finished.recv().unwrap();
+    }
+
+    #[test]
+    fn turn_away_test() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.write_all(b"Start\n").unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        turn_away(stream, b"Error;Busy\n");
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert_eq!(response, "Error;Busy\n");
+    }
+}
@@ -1,13 +1,32 @@
-#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
 pub struct Size {
     pub width: usize, pub height: usize
 }
 
+impl Size {
+    pub fn contains(&self, pos: &Position) -> bool {
+        pos.x < self.width && pos.y < self.height
+    }
+}
+
 #[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
 pub struct Position {
     pub x: usize, pub y: usize
 }
 
+/* Positions are ordered row-major - the order of the board's tiles */
+impl Ord for Position {
+    fn cmp(&self, other: &Position) -> std::cmp::Ordering {
+        (self.y, self.x).cmp(&(other.y, other.x))
+    }
+}
+
+impl PartialOrd for Position {
+    fn partial_cmp(&self, other: &Position) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
 pub enum Direction {
     UpLeft, Up, UpRight, Right, DownRight, Down, DownLeft, Left
 }
@@ -78,6 +97,13 @@ mod tests {
         assert_eq!(Position{x: 0, y: 1}.advance(&Direction::Up, &Size{width: 6, height: 6}), Some(Position{x: 0, y: 0}));
     }
 
+    #[test]
+    fn position_order_test() {
+        assert!(Position{x: 3, y: 0} < Position{x: 0, y: 1});
+        assert!(Position{x: 1, y: 2} < Position{x: 2, y: 2});
+        assert_eq!(Position{x: 2, y: 2}.cmp(&Position{x: 2, y: 2}), std::cmp::Ordering::Equal);
+    }
+
     #[test]
     fn position_add_test_underflow() {
         assert_eq!(Position{x: 0, y: 1}.advance(&Direction::Left, &Size{width: 4, height: 6}), None);
@@ -0,0 +1,553 @@
+use super::game::{ChangeSet, Game, Variant};
+use super::board::Color;
+use super::position::Size;
+use super::analysis::PerColor;
+use super::storage::Record;
+
+use std::collections::BTreeMap;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/*
+Shared games, played by two connections. One player creates a room and is handed a code to pass on, the other joins with it.
+Each player is seated at one color, and whatever one of them does is pushed to the other as an Event.
+Rooms can be listed in the lobby for anyone to join. Players asking for a match are paired with the longest waiting room
+of the same options - or get a room of their own to wait in, which isn't listed.
+Any number of other connections may watch a room with its code. Spectators are pushed the moves but can't play.
+*/
+
+const CODE_LENGTH: usize = 6;
+const CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";  /* No "0", "O", "1" or "I", which are easily mixed up when read out */
+
+/* What happens in a room, as told to the other player */
+#[derive(Debug, PartialEq, Clone)]
+pub enum Event {
+    Joined,                                         /* The opponent took their seat - the game is on */
+    Changed(ChangeSet),                             /* The opponent moved */
+    Left,                                           /* The opponent left the room - or, for spectators, one of the players */
+    Spectators(usize),                              /* The number of spectators changed */
+    OutOfTime(Color),                               /* The player at this color ran out of time, and lost */
+    Closed,                                         /* Both players left, so there is nothing left to watch */
+    Token                                           /* Not about a room: the connection's first game began, so it's told its session token */
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct TimeControl {
+    pub initial: u32,                               /* Seconds on each player's clock at the start */
+    pub increment: u32                              /* Seconds added to a player's clock after each of their moves */
+}
+
+/* A player's connection - where to push events for it, and the session token to resume it with */
+pub struct Player {
+    pub outbox: Sender<Event>,
+    pub token: String
+}
+
+/* What a shared game is played with */
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct GameOptions {
+    pub size: Size,
+    pub variant: Variant,
+    pub time_control: Option<TimeControl>           /* "None" for untimed games */
+}
+
+impl Default for GameOptions {
+    fn default() -> GameOptions {
+        GameOptions{ size: Size{ width: 10, height: 10 }, variant: Variant::Standard, time_control: None }
+    }
+}
+
+/* The time left to each player, running for the player to move once the game is on */
+struct Clocks {
+    left: PerColor<Duration>,
+    increment: Duration,
+    since: Option<Instant>,                         /* When the player to move got the turn - "None" until both players arrived */
+    flagged: Option<Color>                          /* The player who ran out of time - the game is over then */
+}
+
+struct Room {
+    game: Arc<Mutex<Game>>,
+    seats: PerColor<Option<Player>>,                /* The player seated at each color */
+    options: GameOptions,
+    listed: bool,                                   /* Whether the room shows up in the lobby */
+    started: bool,                                  /* Whether both players arrived at some point */
+    opened: u64,                                    /* Rooms are matched oldest first */
+    clocks: Option<Clocks>,
+    spectators: BTreeMap<u64, Sender<Event>>,       /* Where to push events for each spectator, by the number it was given */
+    watched: u64,                                   /* The number of spectators so far, numbering the next one */
+    record: Option<u64>                             /* The id the game is stored under - "None" if it isn't */
+}
+
+impl Room {
+    fn send(&self, color: Color, event: Event) {
+        /* A player whose connection is going away can't be told anything anymore - it leaves the room soon enough */
+        if let Some(player) = self.seats.get(&color) { let _ = player.outbox.send(event); }
+    }
+
+    fn send_spectators(&self, event: Event) {
+        for outbox in self.spectators.values() { let _ = outbox.send(event.clone()); }
+    }
+
+    /* Tells everyone in the room how many are watching */
+    fn count_spectators(&self) {
+        let event = Event::Spectators(self.spectators.len());
+        self.send(Color::Black, event.clone());
+        self.send(Color::White, event.clone());
+        self.send_spectators(event);
+    }
+}
+
+pub struct Rooms {
+    rooms: BTreeMap<String, Room>,
+    keys: RandomState,                              /* Randomly seeded, so codes can't be guessed */
+    created: u64
+}
+
+impl Default for Rooms {
+    fn default() -> Rooms {
+        Rooms::new()
+    }
+}
+
+impl Rooms {
+    pub fn new() -> Rooms {
+        Rooms{ rooms: BTreeMap::new(), keys: RandomState::new(), created: 0 }
+    }
+
+    fn new_code(&mut self) -> String {
+        loop {
+            let mut hasher = self.keys.build_hasher();
+            hasher.write_u64(self.created);
+            self.created += 1;
+            let mut value = hasher.finish();
+            let code: String = (0..CODE_LENGTH).map(|_| {
+                let symbol = CODE_ALPHABET[(value % CODE_ALPHABET.len() as u64) as usize] as char;
+                value /= CODE_ALPHABET.len() as u64;
+                symbol
+            }).collect();
+            if !self.rooms.contains_key(&code) { return code; }
+        }
+    }
+
+    /* Opens a room with a new game, seating its creator at Black. Returns the room's code and its game */
+    pub fn create(&mut self, player: Player, options: GameOptions, listed: bool, record: Option<u64>) -> Result<(String, Arc<Mutex<Game>>), String> {
+        if options.time_control.is_some_and(|time_control| time_control.initial == 0) { return Err(String::from("Timed games need some time on the clocks")); }
+        let game = Arc::new(Mutex::new(Game::start(options.size, options.variant)?));
+        let code = self.new_code();
+        self.open_room(code.clone(), Arc::clone(&game), options, listed, record);
+        self.seat(&code, Color::Black, player);
+        Ok((code, game))
+    }
+
+    fn open_room(&mut self, code: String, game: Arc<Mutex<Game>>, options: GameOptions, listed: bool, record: Option<u64>) {
+        let clocks = options.time_control.map(|time_control| {
+            let initial = Duration::from_secs(time_control.initial as u64);
+            Clocks{ left: PerColor{ black: initial, white: initial }, increment: Duration::from_secs(time_control.increment as u64), since: None, flagged: None }
+        });
+        let room = Room{ game, seats: PerColor::default(), options, listed, started: false, opened: self.created, clocks,
+                         spectators: BTreeMap::new(), watched: 0, record };
+        self.created += 1;
+        self.rooms.insert(code, room);
+    }
+
+    /* Seats a player, starting the game - and its clocks - once both seats were taken */
+    fn seat(&mut self, code: &str, color: Color, player: Player) {
+        if let Some(room) = self.rooms.get_mut(code) {
+            *room.seats.get_mut(&color) = Some(player);
+            if !room.started && room.seats.black.is_some() && room.seats.white.is_some() {
+                room.started = true;
+                if let Some(clocks) = &mut room.clocks { clocks.since = Some(Instant::now()); }
+            }
+        }
+    }
+
+    /*
+    Brings back a room kept in storage, seating one of its players - the first one reopens it.
+    The clocks start over, and the room isn't listed in the lobby anymore.
+    */
+    pub fn restore(&mut self, code: &str, color: Color, player: Player, game: Arc<Mutex<Game>>, options: GameOptions, record: u64) {
+        if !self.rooms.contains_key(code) { self.open_room(code.to_string(), game, options, false, Some(record)); }
+        self.seat(code, color, player);
+    }
+
+    /* The rooms listed in the lobby which are still waiting for their second player, oldest first */
+    pub fn open(&self) -> Vec<(String, GameOptions)> {
+        let mut open: Vec<(&String, &Room)> = self.rooms.iter().filter(|(_, room)| room.listed && !room.started).collect();
+        open.sort_by_key(|(_, room)| room.opened);
+        open.into_iter().map(|(code, room)| (code.clone(), room.options)).collect()
+    }
+
+    /* The code of the longest waiting room with the given options, listed or not */
+    pub fn find_match(&self, options: &GameOptions) -> Option<String> {
+        self.rooms.iter()
+                  .filter(|(_, room)| !room.started && room.options == *options)
+                  .min_by_key(|(_, room)| room.opened)
+                  .map(|(code, _)| code.clone())
+    }
+
+    /*
+    Takes the free seat of a room, telling the player already there. Codes aren't case-sensitive.
+    A seat given up once the game is on stays free - nobody may take over a game halfway through.
+    */
+    pub fn join(&mut self, code: &str, player: Player) -> Result<(String, Color, Arc<Mutex<Game>>), String> {
+        let code = code.to_ascii_uppercase();
+        let room = self.rooms.get_mut(&code).ok_or_else(|| format!("No game with code {}", code))?;
+        let color = [Color::White, Color::Black].iter().copied()
+                                                .find(|color| room.seats.get(color).is_none())
+                                                .ok_or_else(|| String::from("The game is full"))?;
+        if room.started { return Err(String::from("The game already started")); }
+        let game = Arc::clone(&room.game);
+        room.send(color.opposite(), Event::Joined);
+        self.seat(&code, color, player);
+        Ok((code, color, game))
+    }
+
+    /* Frees a seat, telling the other player and the spectators. Rooms nobody is seated in anymore are closed */
+    pub fn leave(&mut self, code: &str, color: Color) {
+        let room = match self.rooms.get_mut(code) { Some(room) => room, None => return };
+        *room.seats.get_mut(&color) = None;
+        if room.seats.get(&color.opposite()).is_none() {
+            room.send_spectators(Event::Closed);
+            self.rooms.remove(code);
+        } else {
+            room.send(color.opposite(), Event::Left);
+            room.send_spectators(Event::Left);
+        }
+    }
+
+    /* Adds a spectator to a room, telling everyone there. Returns the room's code, the spectator's number and the game */
+    pub fn watch(&mut self, code: &str, outbox: Sender<Event>) -> Result<(String, u64, Arc<Mutex<Game>>), String> {
+        let code = code.to_ascii_uppercase();
+        let room = self.rooms.get_mut(&code).ok_or_else(|| format!("No game with code {}", code))?;
+        let id = room.watched;
+        room.watched += 1;
+        room.spectators.insert(id, outbox);
+        room.count_spectators();
+        Ok((code, id, Arc::clone(&room.game)))
+    }
+
+    /* Removes a spectator from a room, telling everyone left there */
+    pub fn unwatch(&mut self, code: &str, id: u64) {
+        if let Some(room) = self.rooms.get_mut(code) {
+            if room.spectators.remove(&id).is_some() { room.count_spectators(); }
+        }
+    }
+
+    /* Pushes events for a seat somewhere else from now on, once its player came back on another connection */
+    pub fn rebind(&mut self, code: &str, color: Color, outbox: Sender<Event>) -> bool {
+        match self.rooms.get_mut(code).and_then(|room| room.seats.get_mut(&color).as_mut()) {
+            Some(player) => { player.outbox = outbox; true },
+            None => false
+        }
+    }
+
+    /* The counterpart of rebind for spectators */
+    pub fn rewatch(&mut self, code: &str, id: u64, outbox: Sender<Event>) -> bool {
+        match self.rooms.get_mut(code).and_then(|room| room.spectators.get_mut(&id)) {
+            Some(spectator) => { *spectator = outbox; true },
+            None => false
+        }
+    }
+
+    /* How the room's game is stored, without its moves - "None" if it isn't */
+    pub fn record(&self, code: &str) -> Option<Record> {
+        let room = self.rooms.get(code)?;
+        let token = |player: &Option<Player>| player.as_ref().map(|player| player.token.clone());
+        Some(Record{
+            id: room.record?,
+            options: room.options,
+            code: Some(code.to_string()),
+            players: PerColor{ black: token(&room.seats.black), white: token(&room.seats.white) },
+            moves: Vec::new(),
+            finished: room.clocks.as_ref().is_some_and(|clocks| clocks.flagged.is_some())
+        })
+    }
+
+    /* Whether both seats of a room are taken */
+    pub fn ready(&self, code: &str) -> bool {
+        self.rooms.get(code).is_some_and(|room| room.seats.black.is_some() && room.seats.white.is_some())
+    }
+
+    /* Fails if the clock of the player to move ran out by `now` - or if either player's did before */
+    pub fn check_clock(&self, code: &str, color: Color, now: Instant) -> Result<(), String> {
+        match self.rooms.get(code).and_then(|room| room.clocks.as_ref()) {
+            Some(Clocks{ flagged: Some(flagged), .. }) if *flagged != color => Err(String::from("Your opponent ran out of time")),
+            Some(Clocks{ flagged: Some(_), .. }) => Err(String::from("You ran out of time")),
+            Some(Clocks{ left, since: Some(since), .. }) if now.saturating_duration_since(*since) > *left.get(&color) => Err(String::from("You ran out of time")),
+            _ => Ok(())
+        }
+    }
+
+    /*
+    Ends the game if the player to move ran out of time by `now`, telling both players and the spectators -
+    so a player can't hold the game up by never moving again. Returns whether the game ended just now.
+    */
+    pub fn flag(&mut self, code: &str, now: Instant) -> bool {
+        let room = match self.rooms.get_mut(code) { Some(room) => room, None => return false };
+        let game = room.game.lock().expect("Impossible event: a thread panicked while holding a game (Rooms::flag)");
+        let player = game.current_player();
+        let finished = game.finished();
+        drop(game);
+        let clocks = match &mut room.clocks {
+            Some(clocks) if clocks.flagged.is_none() && !finished => clocks,
+            _ => return false
+        };
+        match clocks.since {
+            Some(since) if now.saturating_duration_since(since) > *clocks.left.get(&player) => clocks.flagged = Some(player),
+            _ => return false
+        }
+        room.send(Color::Black, Event::OutOfTime(player));
+        room.send(Color::White, Event::OutOfTime(player));
+        room.send_spectators(Event::OutOfTime(player));
+        true
+    }
+
+    /* Stops the clock of the player who just moved at `now`, adding the increment, and starts the opponent's */
+    pub fn punch_clock(&mut self, code: &str, color: Color, now: Instant) {
+        if let Some(Clocks{ left, increment, since: Some(since), .. }) = self.rooms.get_mut(code).and_then(|room| room.clocks.as_mut()) {
+            let left = left.get_mut(&color);
+            *left = left.saturating_sub(now.saturating_duration_since(*since)) + *increment;
+            *since = now;
+        }
+    }
+
+    /*
+    Passes on a move the player seated at `color` made at `now`: punches their clock, and pushes the changes to the opponent and the spectators.
+    An opponent left without a legal move passes at once, as the game would be stuck otherwise -
+    the changes returned, and pushed, leave the turn to whoever plays next.
+    */
+    pub fn moved(&mut self, code: &str, color: Color, mut change_set: ChangeSet, now: Instant) -> ChangeSet {
+        self.punch_clock(code, color, now);
+        if let Some(room) = self.rooms.get(code) {
+            let mut game = room.game.lock().expect("Impossible event: a thread panicked while holding a game (Rooms::moved)");
+            if !game.finished() && game.pass().is_ok() { change_set.player = game.current_player(); }
+        }
+        self.notify(code, color, Event::Changed(change_set.clone()));
+        change_set
+    }
+
+    /* Pushes an event to the opponent of the player seated at `from`, and to the spectators */
+    pub fn notify(&self, code: &str, from: Color, event: Event) {
+        if let Some(room) = self.rooms.get(code) {
+            room.send_spectators(event.clone());
+            room.send(from.opposite(), event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::board::{Board, Color};
+    use super::super::position::Position;
+    use super::super::position::Size;
+    use super::super::game::Game;
+    use super::{Event, GameOptions, Player, Rooms, TimeControl, CODE_LENGTH};
+    use std::sync::mpsc::{channel, Sender};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    fn player(outbox: Sender<Event>) -> Player {
+        Player{ outbox, token: String::from("token") }
+    }
+
+    #[test]
+    fn join_test() {
+        let mut rooms = Rooms::new();
+        let (black_outbox, black_events) = channel();
+        let (white_outbox, white_events) = channel();
+        let (code, game) = rooms.create(player(black_outbox), GameOptions::default(), true, None).unwrap();
+        assert_eq!(code.len(), CODE_LENGTH);
+        assert!(!rooms.ready(&code));
+
+        let (joined_code, color, joined_game) = rooms.join(&code.to_ascii_lowercase(), player(white_outbox.clone())).unwrap();
+        assert_eq!((joined_code.as_str(), color), (code.as_str(), Color::White));
+        assert!(Arc::ptr_eq(&game, &joined_game));
+        assert!(rooms.ready(&code));
+        assert_eq!(black_events.try_recv(), Ok(Event::Joined));
+        assert_eq!(rooms.join(&code, player(white_outbox)).map(|(_, color, _)| color), Err(String::from("The game is full")));
+        assert_eq!(rooms.join("NOPE", player(channel().0)).map(|(_, color, _)| color), Err(String::from("No game with code NOPE")));
+
+        let change_set = game.lock().unwrap().do_turn(Position{x: 4, y: 3}).unwrap();
+        rooms.notify(&code, Color::Black, Event::Changed(change_set.clone()));
+        assert_eq!(white_events.try_recv(), Ok(Event::Changed(change_set)));
+        assert!(black_events.try_recv().is_err());
+    }
+
+    #[test]
+    fn moved_test_pass() {
+        let mut rooms = Rooms::new();
+        let (white_outbox, white_events) = channel();
+        let (spectator_outbox, spectator_events) = channel();
+        let small = GameOptions{ size: Size{ width: 4, height: 4 }, ..GameOptions::default() };
+        let (code, game) = rooms.create(player(channel().0), small, false, None).unwrap();
+        rooms.join(&code, player(white_outbox)).unwrap();
+        rooms.watch(&code, spectator_outbox).unwrap();
+        while white_events.try_recv().is_ok() || spectator_events.try_recv().is_ok() {}
+
+        /* Once Black takes (2, 0), White has nothing left to flip, while Black can still play (0, 2) */
+        let mut board = Board::new(4, 4);
+        board.set(&Position{x: 0, y: 0}, &Color::Black);
+        board.set(&Position{x: 1, y: 0}, &Color::White);
+        board.set(&Position{x: 0, y: 1}, &Color::White);
+        *game.lock().unwrap() = Game::with_board(board, Color::Black);
+        let change_set = game.lock().unwrap().do_turn(Position{x: 2, y: 0}).unwrap();
+        assert_eq!(change_set.player, Color::White);
+
+        let passed = rooms.moved(&code, Color::Black, change_set, Instant::now());
+        assert_eq!(passed.player, Color::Black);
+        assert_eq!(game.lock().unwrap().current_player(), Color::Black);
+        assert!(!game.lock().unwrap().finished());
+        assert_eq!(white_events.try_recv(), Ok(Event::Changed(passed.clone())));
+        assert_eq!(spectator_events.try_recv(), Ok(Event::Changed(passed)));
+
+        /* A move which ends the game doesn't pass - there's nobody to pass to */
+        let change_set = game.lock().unwrap().do_turn(Position{x: 0, y: 2}).unwrap();
+        assert!(game.lock().unwrap().finished());
+        assert_eq!(rooms.moved(&code, Color::Black, change_set.clone(), Instant::now()), change_set);
+    }
+
+    #[test]
+    fn leave_test() {
+        let mut rooms = Rooms::new();
+        let (black_outbox, _black_events) = channel();
+        let (code, _) = rooms.create(player(black_outbox), GameOptions::default(), true, None).unwrap();
+        let (white_outbox, white_events) = channel();
+        rooms.join(&code, player(white_outbox)).unwrap();
+
+        rooms.leave(&code, Color::Black);
+        assert_eq!(white_events.try_recv(), Ok(Event::Left));
+        assert!(!rooms.ready(&code));
+        /* The seat left is nobody else's to take */
+        assert_eq!(rooms.join(&code, player(channel().0)).map(|(_, color, _)| color), Err(String::from("The game already started")));
+        assert!(white_events.try_recv().is_err());
+
+        rooms.leave(&code, Color::White);
+        assert!(rooms.join(&code, player(channel().0)).is_err());
+    }
+
+    #[test]
+    fn lobby_test() {
+        let mut rooms = Rooms::new();
+        let small = GameOptions{ size: Size{ width: 6, height: 6 }, ..GameOptions::default() };
+        let (listed, _) = rooms.create(player(channel().0), small, true, None).unwrap();
+        let (queued, _) = rooms.create(player(channel().0), small, false, None).unwrap();
+        let (other, _) = rooms.create(player(channel().0), GameOptions::default(), true, None).unwrap();
+        assert_eq!(rooms.open(), vec![(listed.clone(), small), (other.clone(), GameOptions::default())]);
+        assert!(rooms.create(player(channel().0), GameOptions{ size: Size{ width: 5, height: 6 }, ..small }, true, None).is_err());
+
+        assert_eq!(rooms.find_match(&small), Some(listed.clone()));
+        rooms.join(&listed, player(channel().0)).unwrap();
+        assert_eq!(rooms.find_match(&small), Some(queued));
+        assert_eq!(rooms.open(), vec![(other, GameOptions::default())]);
+        assert_eq!(rooms.find_match(&GameOptions{ size: Size{ width: 8, height: 8 }, ..small }), None);
+    }
+
+    #[test]
+    fn clock_test() {
+        let mut rooms = Rooms::new();
+        let options = GameOptions{ time_control: Some(TimeControl{ initial: 10, increment: 2 }), ..GameOptions::default() };
+        let (code, _) = rooms.create(player(channel().0), options, false, None).unwrap();
+        assert!(rooms.create(player(channel().0), GameOptions{ time_control: Some(TimeControl{ initial: 0, increment: 2 }), ..options }, false, None).is_err());
+        rooms.join(&code, player(channel().0)).unwrap();
+
+        let start = Instant::now();
+        assert_eq!(rooms.check_clock(&code, Color::Black, start + Duration::from_secs(11)), Err(String::from("You ran out of time")));
+        assert_eq!(rooms.check_clock(&code, Color::Black, start + Duration::from_secs(9)), Ok(()));
+        rooms.punch_clock(&code, Color::Black, start + Duration::from_secs(9));
+        /* Black has 3 seconds left now, White's clock runs from the punch on */
+        assert_eq!(rooms.check_clock(&code, Color::White, start + Duration::from_secs(19)), Ok(()));
+        rooms.punch_clock(&code, Color::White, start + Duration::from_secs(19));
+        assert!(rooms.check_clock(&code, Color::Black, start + Duration::from_secs(23)).is_err());
+    }
+
+    #[test]
+    fn flag_test() {
+        let mut rooms = Rooms::new();
+        let options = GameOptions{ time_control: Some(TimeControl{ initial: 10, increment: 0 }), ..GameOptions::default() };
+        let (black_outbox, black_events) = channel();
+        let (white_outbox, white_events) = channel();
+        let (code, _) = rooms.create(player(black_outbox), options, false, Some(1)).unwrap();
+        rooms.join(&code, player(white_outbox)).unwrap();
+        let (spectator_outbox, spectator_events) = channel();
+        rooms.watch(&code, spectator_outbox).unwrap();
+        while black_events.try_recv().is_ok() || white_events.try_recv().is_ok() || spectator_events.try_recv().is_ok() {}
+
+        let start = Instant::now();
+        assert!(!rooms.flag(&code, start + Duration::from_secs(9)));
+        assert!(black_events.try_recv().is_err());
+        /* Black never moved, so White gets to claim the game */
+        assert!(rooms.flag(&code, start + Duration::from_secs(11)));
+        assert_eq!(black_events.try_recv(), Ok(Event::OutOfTime(Color::Black)));
+        assert_eq!(white_events.try_recv(), Ok(Event::OutOfTime(Color::Black)));
+        assert_eq!(spectator_events.try_recv(), Ok(Event::OutOfTime(Color::Black)));
+        assert!(!rooms.flag(&code, start + Duration::from_secs(12)));
+        assert!(white_events.try_recv().is_err());
+
+        assert_eq!(rooms.check_clock(&code, Color::Black, start), Err(String::from("You ran out of time")));
+        assert_eq!(rooms.check_clock(&code, Color::White, start), Err(String::from("Your opponent ran out of time")));
+        assert!(rooms.record(&code).unwrap().finished);
+    }
+
+    #[test]
+    fn watch_test() {
+        let mut rooms = Rooms::new();
+        let (black_outbox, black_events) = channel();
+        let (code, game) = rooms.create(player(black_outbox), GameOptions::default(), true, None).unwrap();
+        let (spectator_outbox, spectator_events) = channel();
+        let (watched_code, id, watched_game) = rooms.watch(&code.to_ascii_lowercase(), spectator_outbox).unwrap();
+        assert_eq!(watched_code, code);
+        assert!(Arc::ptr_eq(&game, &watched_game));
+        assert_eq!(black_events.try_recv(), Ok(Event::Spectators(1)));
+        assert_eq!(spectator_events.try_recv(), Ok(Event::Spectators(1)));
+        assert_eq!(rooms.watch("NOPE", channel().0).map(|(_, id, _)| id), Err(String::from("No game with code NOPE")));
+
+        let (white_outbox, white_events) = channel();
+        rooms.join(&code, player(white_outbox)).unwrap();
+        assert!(spectator_events.try_recv().is_err());
+        let (other_outbox, _other_events) = channel();
+        let (_, other, _) = rooms.watch(&code, other_outbox).unwrap();
+        assert_ne!(id, other);
+        assert_eq!(white_events.try_recv(), Ok(Event::Spectators(2)));
+
+        let change_set = game.lock().unwrap().do_turn(Position{x: 4, y: 3}).unwrap();
+        rooms.notify(&code, Color::Black, Event::Changed(change_set.clone()));
+        assert_eq!(spectator_events.try_recv(), Ok(Event::Spectators(2)));
+        assert_eq!(spectator_events.try_recv(), Ok(Event::Changed(change_set)));
+
+        rooms.unwatch(&code, other);
+        assert_eq!(spectator_events.try_recv(), Ok(Event::Spectators(1)));
+        rooms.leave(&code, Color::White);
+        assert_eq!(spectator_events.try_recv(), Ok(Event::Left));
+        rooms.leave(&code, Color::Black);
+        assert_eq!(spectator_events.try_recv(), Ok(Event::Closed));
+        assert!(rooms.watch(&code, channel().0).is_err());
+    }
+
+    #[test]
+    fn restore_test() {
+        let mut rooms = Rooms::new();
+        let (code, _) = rooms.create(player(channel().0), GameOptions::default(), true, Some(7)).unwrap();
+        let record = rooms.record(&code).unwrap();
+        assert_eq!((record.id, record.code.as_deref(), record.players.black.as_deref(), record.players.white), (7, Some(code.as_str()), Some("token"), None));
+        assert!(rooms.create(player(channel().0), GameOptions::default(), true, None).map(|(code, _)| rooms.record(&code)).unwrap().is_none());
+
+        let game = Arc::new(Mutex::new(Game::new()));
+        let (black_outbox, black_events) = channel();
+        rooms.restore("K7Q2MZ", Color::Black, player(black_outbox), Arc::clone(&game), GameOptions::default(), 3);
+        assert!(!rooms.ready("K7Q2MZ"));
+        assert_eq!(rooms.find_match(&GameOptions::default()), Some(code));
+        rooms.restore("K7Q2MZ", Color::White, Player{ outbox: channel().0, token: String::from("white") }, Arc::new(Mutex::new(Game::new())), GameOptions::default(), 3);
+        assert!(rooms.ready("K7Q2MZ"));
+        assert_eq!(rooms.record("K7Q2MZ").map(|record| record.players.white), Some(Some(String::from("white"))));
+        assert!(rooms.open().iter().all(|(code, _)| code != "K7Q2MZ"));
+
+        let (outbox, _events) = channel();
+        assert!(rooms.rebind("K7Q2MZ", Color::White, outbox));
+        rooms.leave("K7Q2MZ", Color::White);
+        assert_eq!(black_events.try_recv(), Ok(Event::Left));
+        assert!(!rooms.rebind("K7Q2MZ", Color::White, channel().0));
+    }
+}
@@ -0,0 +1,411 @@
+use super::board::{Board, Color, Move};
+use super::position::Position;
+
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+use std::time::Instant;
+
+/*
+Alpha-beta (negamax) search over a Board.
+Parallelism is done Lazy SMP style: every worker runs its own iterative deepening over the same root,
+sharing a lock-free transposition table. Helper workers order root moves differently so they explore
+different subtrees first, and the table lets the main worker profit from what they found.
+Only the main worker's result is returned, and with a single thread no helpers are spawned - so the
+single-threaded search is fully deterministic.
+*/
+
+const WIN_SCORE: i32 = 10_000;                      /* Per disc of final difference - dwarfs any heuristic score */
+const INFINITY: i32 = i32::MAX - 1;
+const MOBILITY_WEIGHT: i32 = 5;
+const TABLE_SIZE: usize = 1 << 18;
+
+/* Plies - enough to reach the end of a game on the largest board (128 tiles), and small enough for the table's depths */
+pub const MAX_SEARCH_DEPTH: u32 = 128;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct SearchConfig {
+    pub depth: u32,                                 /* Plies to look ahead */
+    pub threads: usize                              /* Number of workers. 1 means a deterministic, single-threaded search */
+}
+
+impl Default for SearchConfig {
+    fn default() -> SearchConfig {
+        SearchConfig {
+            depth: 4,
+            threads: thread::available_parallelism().map_or(1, |threads| threads.get())
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct SearchResult {
+    pub position: Position,
+    pub score: i32                                  /* From the point of view of the searching player */
+}
+
+impl SearchResult {
+    /* The score as a disc difference - exact when the search saw the end of the game, estimated otherwise */
+    pub fn discs(&self) -> i32 {
+        if self.score.abs() >= WIN_SCORE { self.score / WIN_SCORE } else { self.score }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Bound { Exact, Lower, Upper }
+
+#[derive(Clone, Copy)]
+struct Entry {
+    score: i32,
+    depth: u8,
+    bound: Bound,
+    best: Option<u8>                                /* Index of the best move found, if it fits in a byte */
+}
+
+impl Entry {
+    fn pack(&self) -> u64 {
+        let bound = match self.bound { Bound::Exact => 0u64, Bound::Lower => 1, Bound::Upper => 2 };
+        (self.score as u32 as u64) | (self.depth as u64) << 32 | bound << 40 | (self.best.unwrap_or(u8::MAX) as u64) << 48
+    }
+
+    fn unpack(data: u64) -> Entry {
+        let best = (data >> 48) as u8;
+        Entry {
+            score: data as u32 as i32,
+            depth: (data >> 32) as u8,
+            bound: match (data >> 40) & 3 { 0 => Bound::Exact, 1 => Bound::Lower, _ => Bound::Upper },
+            best: if best == u8::MAX { None } else { Some(best) }
+        }
+    }
+}
+
+/*
+Each slot holds the data and the key xor-ed with the data, so a slot torn by two concurrent writers
+simply fails verification on probe instead of returning garbage.
+*/
+struct TranspositionTable {
+    slots: Vec<(AtomicU64, AtomicU64)>
+}
+
+impl TranspositionTable {
+    fn new(size: usize) -> TranspositionTable {
+        TranspositionTable { slots: (0..size).map(|_| (AtomicU64::new(0), AtomicU64::new(0))).collect() }
+    }
+
+    fn probe(&self, key: u64) -> Option<Entry> {
+        let (check, data) = &self.slots[key as usize % self.slots.len()];
+        let data = data.load(Ordering::Relaxed);
+        if check.load(Ordering::Relaxed) ^ data == key { Some(Entry::unpack(data)) } else { None }
+    }
+
+    fn store(&self, key: u64, entry: Entry) {
+        let (check, data) = &self.slots[key as usize % self.slots.len()];
+        let packed = entry.pack();
+        check.store(key ^ packed, Ordering::Relaxed);
+        data.store(packed, Ordering::Relaxed);
+    }
+}
+
+fn mix(seed: u64) -> u64 {
+    let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+fn tile_key(index: usize, color: Color) -> u64 {
+    mix(index as u64 * 2 + if color == Color::Black { 0 } else { 1 })
+}
+
+fn side_key(player: Color) -> u64 {
+    if player == Color::Black { 0 } else { mix(u64::MAX) }
+}
+
+fn hash(board: &Board, player: Color) -> u64 {
+    board.iter_all_positions()
+         .filter_map(|pos| board.get(&pos).0.map(|color| tile_key(board.index(&pos), color)))
+         .fold(side_key(player), |acc, key| acc ^ key)
+}
+
+fn hash_after(board: &Board, key: u64, mv: &Move) -> u64 {
+    let flipped = mv.flips.iter()
+                          .map(|idx| tile_key(idx, mv.player) ^ tile_key(idx, mv.player.opposite()))
+                          .fold(0, |acc, key| acc ^ key);
+    key ^ flipped ^ side_key(mv.player) ^ side_key(mv.player.opposite()) ^ tile_key(board.index(&mv.position), mv.player)
+}
+
+fn square_weight(pos: &Position, board: &Board) -> i32 {
+    let edge = |value: usize, limit: usize| value == 0 || value == limit - 1;
+    let near_edge = |value: usize, limit: usize| value == 1 || value + 2 == limit;
+    let (edge_x, edge_y) = (edge(pos.x, board.size.width), edge(pos.y, board.size.height));
+    let (near_x, near_y) = (near_edge(pos.x, board.size.width), near_edge(pos.y, board.size.height));
+    if edge_x && edge_y { 25 }
+    else if (edge_x || near_x) && (edge_y || near_y) { -8 }    /* Squares giving away a corner */
+    else if edge_x || edge_y { 4 }
+    else { 1 }
+}
+
+fn evaluate(board: &Board, player: Color) -> i32 {
+    let material: i32 = board.iter_all_positions()
+                             .map(|pos| match board.get(&pos).0 {
+                                 Some(color) if color == player => square_weight(&pos, board),
+                                 Some(_) => -square_weight(&pos, board),
+                                 None => 0
+                             })
+                             .sum();
+    let mobility = board.legal_moves(player).count() as i32 - board.legal_moves(player.opposite()).count() as i32;
+    material + MOBILITY_WEIGHT * mobility
+}
+
+fn final_score(board: &Board, player: Color) -> i32 {
+    let difference: i32 = board.iter_all_positions()
+                               .map(|pos| match board.get(&pos).0 {
+                                   Some(color) if color == player => 1,
+                                   Some(_) => -1,
+                                   None => 0
+                               })
+                               .sum();
+    difference * WIN_SCORE
+}
+
+/* The move playing the tile at index, if it's a legal one */
+fn move_at(board: &Board, index: usize, player: Color) -> Option<Move> {
+    let position = board.position(index);
+    if board.taken(&position) { return None; }
+    let flips = board.calculate_flip_mask(&position, &player);
+    if flips.is_empty() { None } else { Some(Move{ position, player, flips }) }
+}
+
+struct Worker<'a> {
+    table: &'a TranspositionTable,
+    stop: &'a AtomicBool,
+    skew: usize,                                    /* Rotation of the root moves, so helpers diverge from the main worker */
+    deadline: Option<Instant>                       /* When to raise stop - "None" to search until done */
+}
+
+impl<'a> Worker<'a> {
+    /*
+    The board is modified in place and restored before returning, and moves are generated lazily,
+    with the transposition table's best move tried first - so no heap allocation happens per node.
+    */
+    fn negamax(&self, board: &mut Board, key: u64, player: Color, depth: u32, mut alpha: i32, beta: i32) -> i32 {
+        if self.deadline.is_some_and(|deadline| Instant::now() >= deadline) { self.stop.store(true, Ordering::Relaxed); }
+        if self.stop.load(Ordering::Relaxed) { return 0; }
+
+        if board.legal_moves(player).next().is_none() {
+            if board.legal_moves(player.opposite()).next().is_none() { return final_score(board, player); }
+            let passed_key = key ^ side_key(player) ^ side_key(player.opposite());
+            return -self.negamax(board, passed_key, player.opposite(), depth, -beta, -alpha);
+        }
+        if depth == 0 { return evaluate(board, player); }
+
+        let entry = self.table.probe(key);
+        if let Some(entry) = entry.filter(|entry| entry.depth as u32 >= depth) {
+            match entry.bound {
+                Bound::Exact => return entry.score,
+                Bound::Lower if entry.score >= beta => return entry.score,
+                Bound::Upper if entry.score <= alpha => return entry.score,
+                _ => {}
+            }
+        }
+
+        let first = entry.and_then(|entry| entry.best).map(|idx| idx as usize);
+        let tiles = board.size.width * board.size.height;
+        let original_alpha = alpha;
+        let mut best: Option<(i32, usize)> = None;
+        for idx in first.into_iter().chain((0..tiles).filter(|idx| Some(*idx) != first)) {
+            let mv = match move_at(board, idx, player) { Some(mv) => mv, None => continue };
+            board.apply(&mv);
+            let score = -self.negamax(board, hash_after(board, key, &mv), player.opposite(), depth - 1, -beta, -alpha);
+            board.revert(&mv);
+            if best.is_none_or(|(best_score, _)| score > best_score) { best = Some((score, idx)); }
+            alpha = alpha.max(score);
+            if alpha >= beta { break; }
+        }
+
+        let (score, best_idx) = best.expect("Impossible event: player has a legal move (Worker::negamax)");
+        if !self.stop.load(Ordering::Relaxed) {
+            let bound = if score <= original_alpha { Bound::Upper } else if score >= beta { Bound::Lower } else { Bound::Exact };
+            self.table.store(key, Entry { score, depth: depth.min(u8::MAX as u32) as u8, bound, best: u8::try_from(best_idx).ok() });
+        }
+        score
+    }
+
+    fn search_root(&self, board: &mut Board, player: Color, depth: u32) -> Option<SearchResult> {
+        let key = hash(board, player);
+        let mut moves: Vec<Move> = board.legal_moves(player).collect();
+        if moves.is_empty() { return None; }
+        let skew = self.skew % moves.len();
+        moves.rotate_left(skew);
+        if let Some(best) = self.table.probe(key).and_then(|entry| entry.best) {
+            if let Some(idx) = moves.iter().position(|mv| board.index(&mv.position) == best as usize) {
+                moves[..=idx].rotate_right(1);
+            }
+        }
+
+        let mut alpha = -INFINITY;
+        let mut best: Option<SearchResult> = None;
+        for mv in moves.iter() {
+            board.apply(mv);
+            let score = -self.negamax(board, hash_after(board, key, mv), player.opposite(), depth - 1, -INFINITY, -alpha);
+            board.revert(mv);
+            if best.is_none_or(|result| score > result.score) { best = Some(SearchResult { position: mv.position, score }); }
+            alpha = alpha.max(score);
+        }
+
+        if let Some(result) = best.filter(|_| !self.stop.load(Ordering::Relaxed)) {
+            let best_idx = u8::try_from(board.index(&result.position)).ok();
+            self.table.store(key, Entry { score: result.score, depth: depth.min(u8::MAX as u32) as u8, bound: Bound::Exact, best: best_idx });
+        }
+        best
+    }
+
+    fn iterate(&self, board: &Board, player: Color, first_depth: u32, last_depth: u32) -> Option<SearchResult> {
+        let mut board = board.clone();
+        let mut result = None;
+        for depth in first_depth..=last_depth {
+            if self.stop.load(Ordering::Relaxed) { break; }
+            let found = self.search_root(&mut board, player, depth);
+            /* An iteration cut short is worth less than the last one completed */
+            if self.stop.load(Ordering::Relaxed) && result.is_some() { break; }
+            result = found;
+        }
+        result
+    }
+}
+
+/* Finds the best move for `player`, or None when it has no legal move */
+pub fn best_move(board: &Board, player: Color, config: &SearchConfig) -> Option<SearchResult> {
+    search(board, player, config.depth.max(1), config.threads, None)
+}
+
+/*
+Finds the best move for `player` by the deadline, searching as deep as time allows - config.depth is ignored.
+The move is the one the deepest completed iteration found. None when it has no legal move
+*/
+pub fn best_move_until(board: &Board, player: Color, config: &SearchConfig, deadline: Instant) -> Option<SearchResult> {
+    let depth = (board.size.width * board.size.height) as u32;        /* Deep enough to reach the end of any game */
+    search(board, player, depth, config.threads, Some(deadline))
+}
+
+fn search(board: &Board, player: Color, depth: u32, threads: usize, deadline: Option<Instant>) -> Option<SearchResult> {
+    let table = TranspositionTable::new(TABLE_SIZE);
+    let stop = AtomicBool::new(false);
+    let never = AtomicBool::new(false);
+    /* Without a deadline, the main worker always completes its search */
+    let main = Worker { table: &table, stop: if deadline.is_some() { &stop } else { &never }, skew: 0, deadline };
+
+    thread::scope(|scope| {
+        for helper in 1..threads {
+            let (table, stop) = (&table, &stop);
+            scope.spawn(move || {
+                let worker = Worker { table, stop, skew: helper, deadline: None };
+                worker.iterate(board, player, 1 + helper as u32 % 2, depth + 1);
+            });
+        }
+        let result = main.iterate(board, player, 1, depth);
+        stop.store(true, Ordering::Relaxed);
+        result
+    })
+}
+
+/*
+Every legal move for `player`, each with its score from a full-window search, best first - ties keep the board's order.
+Scoring every move exactly costs more than finding the best one, so this runs on a single thread
+*/
+pub fn ranked_moves(board: &Board, player: Color, config: &SearchConfig) -> Vec<SearchResult> {
+    let depth = config.depth.max(1);
+    let table = TranspositionTable::new(TABLE_SIZE);
+    let never = AtomicBool::new(false);
+    let worker = Worker { table: &table, stop: &never, skew: 0, deadline: None };
+    /* Shallower iterations fill the table first, so the searches below find good moves to try early */
+    if depth > 1 { worker.iterate(board, player, 1, depth - 1); }
+
+    let mut board = board.clone();
+    let key = hash(&board, player);
+    let moves: Vec<Move> = board.legal_moves(player).collect();
+    let mut results: Vec<SearchResult> = moves.iter().map(|mv| {
+        board.apply(mv);
+        let child = hash_after(&board, key, mv);
+        let score = -worker.negamax(&mut board, child, player.opposite(), depth - 1, -INFINITY, INFINITY);
+        board.revert(mv);
+        SearchResult { position: mv.position, score }
+    }).collect();
+    results.sort_by_key(|result| std::cmp::Reverse(result.score));
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::board::{Board, Color};
+    use super::super::position::Position;
+    use super::{best_move, best_move_until, ranked_moves, SearchConfig, SearchResult, WIN_SCORE};
+    use std::time::{Duration, Instant};
+
+    fn opening() -> Board {
+        let mut board = Board::new(6, 6);
+        board.set(&Position{x: 2, y: 2}, &Color::White);
+        board.set(&Position{x: 2, y: 3}, &Color::Black);
+        board.set(&Position{x: 3, y: 2}, &Color::Black);
+        board.set(&Position{x: 3, y: 3}, &Color::White);
+        board
+    }
+
+    #[test]
+    fn best_move_test_no_moves() {
+        let board = Board::new(4, 4);
+        assert_eq!(best_move(&board, Color::Black, &SearchConfig{depth: 3, threads: 1}), None);
+    }
+
+    #[test]
+    fn best_move_test_takes_corner() {
+        let mut board = Board::new(4, 4);
+        board.set(&Position{x: 1, y: 1}, &Color::White);
+        board.set(&Position{x: 2, y: 2}, &Color::Black);
+        board.set(&Position{x: 1, y: 2}, &Color::White);
+        board.set(&Position{x: 0, y: 3}, &Color::Black);
+        let result = best_move(&board, Color::Black, &SearchConfig{depth: 2, threads: 1}).unwrap();
+        assert_eq!(result.position, Position{x: 0, y: 0});
+    }
+
+    #[test]
+    fn best_move_test_single_thread_deterministic() {
+        let config = SearchConfig{depth: 4, threads: 1};
+        let first = best_move(&opening(), Color::Black, &config);
+        assert!(first.is_some());
+        assert_eq!(best_move(&opening(), Color::Black, &config), first);
+    }
+
+    #[test]
+    fn best_move_test_parallel_is_legal() {
+        let board = opening();
+        let result = best_move(&board, Color::Black, &SearchConfig{depth: 4, threads: 4}).unwrap();
+        assert!(!board.taken(&result.position));
+        assert!(!board.calculate_flip_positions(&result.position, &Color::Black).is_empty());
+    }
+
+    #[test]
+    fn ranked_moves_test() {
+        let config = SearchConfig{depth: 3, threads: 1};
+        let ranked = ranked_moves(&opening(), Color::Black, &config);
+        assert_eq!(ranked.len(), 4);
+        assert!(ranked.windows(2).all(|pair| pair[0].score >= pair[1].score));
+        assert_eq!(Some(ranked[0].score), best_move(&opening(), Color::Black, &config).map(|result| result.score));
+        assert!(ranked_moves(&Board::new(4, 4), Color::Black, &config).is_empty());
+    }
+
+    #[test]
+    fn best_move_until_test() {
+        let start = Instant::now();
+        let result = best_move_until(&opening(), Color::Black, &SearchConfig{depth: 1, threads: 2}, start + Duration::from_millis(50)).unwrap();
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert!(!opening().calculate_flip_positions(&result.position, &Color::Black).is_empty());
+    }
+
+    #[test]
+    fn discs_test() {
+        let position = Position{x: 0, y: 0};
+        assert_eq!(SearchResult{position, score: -3 * WIN_SCORE}.discs(), -3);
+        assert_eq!(SearchResult{position, score: 17}.discs(), 17);
+    }
+}
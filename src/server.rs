@@ -1,56 +1,434 @@
-use super::handler::Handler;
+use super::handler::{Handler, Encoding, Response};
+use super::binary_parser::{HEADER_SIZE, payload_len};
+use super::rooms::{Event, Rooms};
+use super::sessions::Sessions;
+use super::storage::Storage;
+use super::search::SearchConfig;
+use super::pool::{self, Pool};
 
-use std::net::{TcpListener, TcpStream};
-use std::io::{Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex, MutexGuard};
 use std::thread;
+use std::time::{Duration, Instant};
 
-fn read_request(stream: &mut TcpStream) -> Result<String, String> {
-    let mut request_buffer = [0u8; 1024];
-    match stream.read(&mut request_buffer) {
-        Ok(0) => Err(String::from("End of stream")),
-        Ok(len) => {
-            println!("Successfully read {} bytes", len);
-            Ok(std::str::from_utf8(&request_buffer[..len]).unwrap().to_string())
-        },
-        Err(e) => Err(e.to_string())
+/*
+Requests and responses are framed by newlines - each one is a single line, terminated by "\n" (a preceding "\r" is ignored).
+A single read may hold several requests, or only a part of one, so reading is buffered.
+Connections switched to the binary encoding use its length-prefixed frames instead.
+A connection taking longer than the idle timeout to send a whole request is told so and closed - its game is parked, as if it had dropped.
+The time runs from the previous response, so a request trickling in byte by byte doesn't keep the connection open.
+Events pushed by other connections are written by the connection's own worker, in between waiting for its requests.
+*/
+pub const MAX_FRAME_SIZE: usize = 64 * 1024;
+
+/* How long a worker waits for a request before it looks for events to pass on */
+const EVENT_TICK: Duration = Duration::from_millis(50);
+
+/* How often parked sessions are checked for an expired grace period */
+const EXPIRY_TICK: Duration = Duration::from_secs(1);
+
+/* What a connection which went quiet for too long is told */
+pub(crate) const IDLE: &str = "No request for too long - send Ping to stay connected";
+
+/* How long a connection may take to send a request, and how long a write to it may block - "None" for no limit */
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct Timeouts {
+    pub idle: Option<Duration>,
+    pub write: Option<Duration>
+}
+
+impl Timeouts {
+    /* Only the write timeout is the socket's - reads are timed per request, by Incoming */
+    pub(crate) fn apply(&self, stream: &TcpStream) -> std::io::Result<()> {
+        stream.set_write_timeout(self.write)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum ReadError {
+    Idle,                               /* No whole request within the idle timeout */
+    Failed(String)                      /* The connection ended or broke, with the reason */
+}
+
+/* Reads which timed out report the connection as idle */
+pub(crate) fn read_error(e: std::io::Error) -> ReadError {
+    match e.kind() {
+        ErrorKind::WouldBlock | ErrorKind::TimedOut => ReadError::Idle,
+        _ => ReadError::Failed(e.to_string())
+    }
+}
+
+enum Frame {
+    Request(String),
+    Binary(Vec<u8>),                    /* A request in the binary encoding */
+    Rejected(String)                    /* A frame which can't be handled, with the reason. The connection goes on after it */
+}
+
+/* Drops everything up to and including the next newline */
+fn skip_line(reader: &mut impl BufRead) -> std::io::Result<()> {
+    loop {
+        let (found, len) = {
+            let buffer = reader.fill_buf()?;
+            if buffer.is_empty() { return Ok(()); }
+            match buffer.iter().position(|byte| *byte == b'\n') {
+                Some(idx) => (true, idx + 1),
+                None => (false, buffer.len())
+            }
+        };
+        reader.consume(len);
+        if found { return Ok(()); }
+    }
+}
+
+fn read_request(reader: &mut impl BufRead) -> Result<Frame, ReadError> {
+    let mut request_buffer: Vec<u8> = Vec::new();
+    /* Room for a whole frame, terminated by "\r\n" */
+    let len = reader.by_ref()
+                    .take(MAX_FRAME_SIZE as u64 + 2)
+                    .read_until(b'\n', &mut request_buffer)
+                    .map_err(read_error)?;
+    if len == 0 { return Err(ReadError::Failed(String::from("End of stream"))); }
+
+    let terminated = request_buffer.last() == Some(&b'\n');
+    if terminated {
+        request_buffer.pop();
+        if request_buffer.last() == Some(&b'\r') { request_buffer.pop(); }
+    }
+    if request_buffer.len() > MAX_FRAME_SIZE {
+        /* The rest of a frame cut short is dropped as well */
+        if !terminated { skip_line(reader).map_err(read_error)?; }
+        return Ok(Frame::Rejected(format!("Request is longer than {} bytes", MAX_FRAME_SIZE)));
+    }
+
+    match String::from_utf8(request_buffer) {
+        Ok(request) => Ok(Frame::Request(request)),
+        Err(_) => Ok(Frame::Rejected(String::from("Request is not valid UTF-8")))
     }
 }
 
-fn write_response(stream: &mut TcpStream, response: &String) -> std::io::Result<()> {
-    stream.write_all(response.as_bytes())?;
+fn read_binary_request(reader: &mut impl Read) -> Result<Vec<u8>, ReadError> {
+    let mut header = [0u8; HEADER_SIZE];
+    reader.read_exact(&mut header).map_err(read_error)?;
+    let mut request = header.to_vec();
+    request.resize(HEADER_SIZE + payload_len(&header), 0);
+    reader.read_exact(&mut request[HEADER_SIZE..]).map_err(read_error)?;
+    Ok(request)
+}
+
+fn write_response(stream: &mut impl Write, response: &[u8], encoding: Encoding) -> std::io::Result<()> {
+    let mut framed = response.to_vec();
+    if encoding != Encoding::Binary { framed.push(b'\n'); }
+    stream.write_all(&framed)?;
     stream.flush()?;
     Ok(())
 }
 
-fn serve_single(mut stream: TcpStream) {
-    let mut handler = Handler::new();
+pub(crate) fn lock_handler(handler: &Mutex<Handler>) -> MutexGuard<'_, Handler> {
+    handler.lock().expect("Impossible event: a thread panicked while holding a handler (server::lock_handler)")
+}
+
+/* Passes on the events the handler was sent so far - what the other player in a shared game does - in the connection's current encoding */
+pub(crate) fn pass_on_events(handler: &Mutex<Handler>, events: &Receiver<Event>,
+                             mut write: impl FnMut(&[u8], Encoding) -> std::io::Result<()>) -> std::io::Result<()> {
+    for event in events.try_iter() {
+        let mut handler = lock_handler(handler);
+        let message = handler.handle_event(event);
+        write(&message, handler.encoding())?;
+    }
+    Ok(())
+}
+
+/*
+The reading half of a connection. While waiting for a request, it wakes up every EVENT_TICK to call `pass_on`,
+so events go out without a thread of their own.
+Each request has until the idle timeout from start_request to arrive whole - reads fail with ErrorKind::TimedOut past that, however many bytes came in.
+*/
+pub(crate) struct Incoming<F> {
+    stream: TcpStream,
+    idle: Option<Duration>,
+    deadline: Option<Instant>,          /* "None" when there's no idle timeout */
+    tick: Option<Duration>,             /* "None" for a connection with no events to pass on */
+    pass_on: F
+}
+
+impl<F: FnMut() -> std::io::Result<()>> Incoming<F> {
+    pub(crate) fn new(stream: TcpStream, idle: Option<Duration>, pass_on: F) -> Incoming<F> {
+        Incoming{ stream, idle, deadline: idle.map(|idle| Instant::now() + idle), tick: Some(EVENT_TICK), pass_on }
+    }
+
+    /* Starts the time the next request has to arrive in */
+    pub(crate) fn start_request(&mut self) {
+        self.deadline = self.idle.map(|idle| Instant::now() + idle);
+    }
+}
+
+impl Incoming<fn() -> std::io::Result<()>> {
+    pub(crate) fn without_events(stream: TcpStream, idle: Option<Duration>) -> Self {
+        Incoming{ tick: None, ..Incoming::new(stream, idle, || Ok(())) }
+    }
+}
+
+impl<F: FnMut() -> std::io::Result<()>> Read for Incoming<F> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            (self.pass_on)()?;
+            let left = match self.deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(left) if !left.is_zero() => Some(left),
+                    _ => return Err(ErrorKind::TimedOut.into())
+                },
+                None => None
+            };
+            let wait = match (left, self.tick) {
+                (Some(left), Some(tick)) => Some(left.min(tick)),
+                (left, tick) => left.or(tick)
+            };
+            self.stream.set_read_timeout(wait)?;
+            match self.stream.read(buf) {
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted) => continue,
+                read => return read
+            }
+        }
+    }
+}
+
+/* Parks the game of a connection which dropped, for expire_sessions to close unless it's resumed within the grace period */
+pub(crate) fn hang_up(handler: &Mutex<Handler>) {
+    lock_handler(handler).park();
+}
+
+/* Closes the games nobody resumed within their grace period, for as long as the server runs */
+pub fn expire_sessions(rooms: Arc<Mutex<Rooms>>, sessions: Arc<Mutex<Sessions>>) {
     loop {
-        let request = match read_request(&mut stream) {
-            Err(e) => { println!("Error while reading: {}", e); break; },
-            Ok(req) => req
+        thread::sleep(EXPIRY_TICK);
+        let expired = sessions.lock().expect("Impossible event: a thread panicked while holding the sessions (server::expire_sessions)").expire(Instant::now());
+        if expired.is_empty() { continue; }
+        let mut rooms = rooms.lock().expect("Impossible event: a thread panicked while holding the rooms (server::expire_sessions)");
+        for session in expired { session.close(&mut rooms); }
+    }
+}
+
+/* Brings back the unfinished games kept in storage, as if their players had just dropped. Returns how many */
+pub fn restore(rooms: &Arc<Mutex<Rooms>>, sessions: &Arc<Mutex<Sessions>>, storage: &Arc<Storage>) -> usize {
+    let mut restored = 0;
+    for record in storage.unfinished() {
+        match Handler::restored(Arc::clone(rooms), Arc::clone(sessions), Some(Arc::clone(storage)), &record) {
+            Ok(handlers) => {
+                for handler in handlers { hang_up(&Mutex::new(handler)); }
+                restored += 1;
+            },
+            Err(e) => println!("Error while restoring game {}: {}", record.id, e)
+        }
+    }
+    restored
+}
+
+fn serve_single(stream: TcpStream, rooms: Arc<Mutex<Rooms>>, sessions: Arc<Mutex<Sessions>>, storage: Option<Arc<Storage>>, search_config: SearchConfig, timeouts: Timeouts) {
+    let (read_half, mut event_writer) = match timeouts.apply(&stream).and_then(|_| Ok((stream.try_clone()?, stream.try_clone()?))) {
+        Ok(halves) => halves,
+        Err(e) => { println!("Error while setting up stream: {}", e); return; }
+    };
+    let mut writer = stream;
+    let (outbox, events) = mpsc::channel();
+    let mut handler = Handler::connected(rooms, sessions, storage, outbox);
+    handler.search_with(search_config);
+    let handler = Mutex::new(handler);
+    let pass_on = || pass_on_events(&handler, &events, |message, encoding| write_response(&mut event_writer, message, encoding));
+    let mut reader = BufReader::new(Incoming::new(read_half, timeouts.idle, pass_on));
+
+    loop {
+        reader.get_mut().start_request();
+        let encoding = lock_handler(&handler).encoding();
+        let frame = match encoding {
+            Encoding::Binary => read_binary_request(&mut reader).map(Frame::Binary),
+            Encoding::Text | Encoding::Json => read_request(&mut reader)
+        };
+
+        let mut locked = lock_handler(&handler);
+        let response = match frame {
+            Err(ReadError::Idle) => {
+                println!("Closing idle stream");
+                let response = locked.reject(String::from(IDLE));
+                let _ = write_response(&mut writer, &response, locked.encoding());
+                break;
+            },
+            Err(ReadError::Failed(e)) => { println!("Error while reading: {}", e); break; },
+            Ok(Frame::Request(request)) => locked.handle_message(request.as_bytes()),
+            Ok(Frame::Binary(request)) => locked.handle_message(&request),
+            Ok(Frame::Rejected(reason)) => locked.reject(reason)
         };
-        
-        let response = handler.handle_raw(request);
 
-        match write_response(&mut stream, &response) {
-            Err(e) => { println!("Error while writing: {}", e); break; },
-            _ => {}
+        let written = write_response(&mut writer, &response, locked.encoding());
+        drop(locked);
+        /* Events the request itself caused follow its response right away, even with more requests already buffered */
+        if let Err(e) = written.and_then(|_| pass_on_events(&handler, &events, |message, encoding| write_response(&mut writer, message, encoding))) {
+            println!("Error while writing: {}", e);
+            break;
         }
     }
-    
+
+    let _ = writer.shutdown(Shutdown::Both);
+    hang_up(&handler);
     println!("Done!");
 }
 
-pub fn serve_incoming() {
-    let listener = TcpListener::bind("127.0.0.1:5040").unwrap_or_else(|e| panic!("Error while binding: {}", e));
-
+/* Connections beyond what the pool can serve are answered with an error, in the text encoding - they never got to pick another */
+pub fn serve_incoming(listener: TcpListener, rooms: Arc<Mutex<Rooms>>, sessions: Arc<Mutex<Sessions>>, storage: Option<Arc<Storage>>, search_config: SearchConfig, pool: Arc<Pool>, timeouts: Timeouts) {
     for incoming_stream in listener.incoming() {
         match incoming_stream {
-            Ok(valid_stream) => { 
-                println!("Handling valid stream");
-                thread::spawn(|| serve_single(valid_stream));
+            Ok(valid_stream) => match pool.claim() {
+                Some(worker) => {
+                    println!("Handling valid stream");
+                    let (rooms, sessions, storage) = (Arc::clone(&rooms), Arc::clone(&sessions), storage.clone());
+                    worker.run(move || serve_single(valid_stream, rooms, sessions, storage, search_config, timeouts));
+                },
+                None => {
+                    println!("Turning away stream, all {} workers are busy", pool.size());
+                    let response = format!("{}\n", Response::Error(String::from(pool::REJECTION)).stringify());
+                    pool::turn_away(valid_stream, response.as_bytes());
+                }
             },
             Err(e) => println!("Error while handling incoming stream: {}", e)
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_binary_request, read_request, write_response, Frame, Incoming, ReadError, MAX_FRAME_SIZE};
+    use super::super::handler::Encoding;
+    use std::io::{BufReader, Cursor, ErrorKind, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+    use std::time::Duration;
+
+    fn next(reader: &mut impl std::io::BufRead) -> Result<String, ReadError> {
+        match read_request(reader)? {
+            Frame::Request(request) => Ok(format!("Request {}", request)),
+            Frame::Binary(request) => Ok(format!("Binary {:?}", request)),
+            Frame::Rejected(reason) => Ok(format!("Rejected {}", reason))
+        }
+    }
+
+    #[test]
+    fn read_request_test_several_in_one_read() {
+        let mut reader = Cursor::new("Start\nDoTurn;3,4\r\nCancel\n");
+        assert_eq!(next(&mut reader), Ok(String::from("Request Start")));
+        assert_eq!(next(&mut reader), Ok(String::from("Request DoTurn;3,4")));
+        assert_eq!(next(&mut reader), Ok(String::from("Request Cancel")));
+        assert_eq!(next(&mut reader), Err(ReadError::Failed(String::from("End of stream"))));
+    }
+
+    #[test]
+    fn read_request_test_split_across_reads() {
+        let mut reader = BufReader::with_capacity(4, Cursor::new("DoTurn;3,4\nHint\n"));
+        assert_eq!(next(&mut reader), Ok(String::from("Request DoTurn;3,4")));
+        assert_eq!(next(&mut reader), Ok(String::from("Request Hint")));
+    }
+
+    #[test]
+    fn read_request_test_unterminated_last_request() {
+        let mut reader = Cursor::new("Start");
+        assert_eq!(next(&mut reader), Ok(String::from("Request Start")));
+        assert_eq!(next(&mut reader), Err(ReadError::Failed(String::from("End of stream"))));
+    }
+
+    #[test]
+    fn read_request_test_too_large() {
+        let source = format!("{}\nCancel\n", "x".repeat(MAX_FRAME_SIZE + 10));
+        let mut reader = Cursor::new(source);
+        assert_eq!(next(&mut reader), Ok(format!("Rejected Request is longer than {} bytes", MAX_FRAME_SIZE)));
+        assert_eq!(next(&mut reader), Ok(String::from("Request Cancel")));
+    }
+
+    #[test]
+    fn read_request_test_exactly_max_size() {
+        let source = format!("{}\n", "x".repeat(MAX_FRAME_SIZE));
+        let mut reader = Cursor::new(source);
+        assert_eq!(next(&mut reader), Ok(format!("Request {}", "x".repeat(MAX_FRAME_SIZE))));
+    }
+
+    #[test]
+    fn read_request_test_max_size_boundary() {
+        let source = format!("{}\r\n{}\nCancel\n", "x".repeat(MAX_FRAME_SIZE), "y".repeat(MAX_FRAME_SIZE + 1));
+        let mut reader = Cursor::new(source);
+        assert_eq!(next(&mut reader), Ok(format!("Request {}", "x".repeat(MAX_FRAME_SIZE))));
+        assert_eq!(next(&mut reader), Ok(format!("Rejected Request is longer than {} bytes", MAX_FRAME_SIZE)));
+        assert_eq!(next(&mut reader), Ok(String::from("Request Cancel")));
+    }
+
+    #[test]
+    fn read_request_test_invalid_utf8() {
+        let mut reader = Cursor::new(vec![0xffu8, 0xfe, b'\n', b'O', b'k', b'\n']);
+        assert_eq!(next(&mut reader), Ok(String::from("Rejected Request is not valid UTF-8")));
+        assert_eq!(next(&mut reader), Ok(String::from("Request Ok")));
+    }
+
+    #[test]
+    fn write_response_test() {
+        let mut output: Vec<u8> = Vec::new();
+        write_response(&mut output, b"Ok", Encoding::Text).unwrap();
+        write_response(&mut output, b"Hint;", Encoding::Text).unwrap();
+        write_response(&mut output, &[0x85, 0, 0, 0, 0], Encoding::Binary).unwrap();
+        assert_eq!(output, b"Ok\nHint;\n\x85\0\0\0\0");
+    }
+
+    #[test]
+    fn read_binary_request_test() {
+        let mut reader = BufReader::with_capacity(3, Cursor::new(vec![0x02u8, 0, 1, 0, 2, 3, 4, 0x03, 0, 0, 0, 0, 0x02, 0]));
+        assert_eq!(read_binary_request(&mut reader), Ok(vec![0x02, 0, 1, 0, 2, 3, 4]));
+        assert_eq!(read_binary_request(&mut reader), Ok(vec![0x03, 0, 0, 0, 0]));
+        assert!(read_binary_request(&mut reader).is_err());
+    }
+
+    /* A stream whose read timeout ran out */
+    struct Quiet;
+
+    impl Read for Quiet {
+        fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+            Err(ErrorKind::WouldBlock.into())
+        }
+    }
+
+    #[test]
+    fn read_request_test_idle() {
+        assert_eq!(next(&mut BufReader::new(Quiet)), Err(ReadError::Idle));
+        assert_eq!(read_binary_request(&mut Quiet), Err(ReadError::Idle));
+        assert_eq!(next(&mut BufReader::new(Cursor::new(Vec::new()))), Err(ReadError::Failed(String::from("End of stream"))));
+    }
+
+    #[test]
+    fn incoming_test() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let mut ticks = 0;
+        let mut incoming = BufReader::new(Incoming::new(stream, Some(Duration::from_millis(300)), || { ticks += 1; Ok(()) }));
+        client.write_all(b"Ping\n").unwrap();
+        assert_eq!(next(&mut incoming), Ok(String::from("Request Ping")));
+        /* Waiting for a request that never comes, events are looked for again and again until the connection is idle */
+        assert_eq!(next(&mut incoming), Err(ReadError::Idle));
+        drop(incoming);
+        assert!(ticks > 2);
+    }
+
+    #[test]
+    fn incoming_test_trickling_request() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let mut incoming = BufReader::new(Incoming::without_events(stream, Some(Duration::from_millis(300))));
+        let trickle = thread::spawn(move || {
+            for byte in b"Ping" {
+                thread::sleep(Duration::from_millis(100));
+                if client.write_all(&[*byte]).is_err() { break; }
+            }
+            client
+        });
+        /* Every byte comes in well within the idle timeout, but the whole request doesn't */
+        assert_eq!(next(&mut incoming), Err(ReadError::Idle));
+        trickle.join().unwrap();
+    }
+}
@@ -0,0 +1,121 @@
+use super::handler::Session;
+
+use std::collections::BTreeMap;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, Instant};
+
+/*
+Games outliving their connections. Every connection is issued a token, and when it drops, its game is parked under that token.
+Another connection presenting the token within the grace period takes the game over - otherwise it's closed.
+Games kept in storage are parked the same way on startup, so their players can resume them after a restart.
+Parked sessions don't expire by themselves - one thread expires them all (see server::expire_sessions).
+*/
+
+const TOKEN_LENGTH: usize = 32;                     /* Hex digits, two 64 bit hashes */
+
+/* How many sessions may wait to be resumed at once - the game of a connection dropping beyond that is closed right away */
+pub const MAX_PARKED: usize = 1024;
+
+/* A parked session, with the end of its grace period */
+struct Parked {
+    session: Session,
+    deadline: Instant
+}
+
+pub struct Sessions {
+    parked: BTreeMap<String, Parked>,
+    keys: RandomState,                              /* Randomly seeded, so tokens can't be guessed */
+    issued: u64,                                    /* Hashes issued for tokens so far */
+    grace: Duration                                 /* How long a parked session waits to be resumed */
+}
+
+impl Sessions {
+    pub fn new(grace: Duration) -> Sessions {
+        Sessions{ parked: BTreeMap::new(), keys: RandomState::new(), issued: 0, grace }
+    }
+
+    fn next(&mut self) -> u64 {
+        let mut hasher = self.keys.build_hasher();
+        hasher.write_u64(self.issued);
+        self.issued += 1;
+        hasher.finish()
+    }
+
+    /* A token for a new connection */
+    pub fn new_token(&mut self) -> String {
+        let token = format!("{:016x}{:016x}", self.next(), self.next());
+        debug_assert_eq!(token.len(), TOKEN_LENGTH);
+        token
+    }
+
+    /* Whether parking another session under a token would go beyond MAX_PARKED */
+    pub fn full(&self, token: &str) -> bool {
+        self.parked.len() >= MAX_PARKED && !self.parked.contains_key(token)
+    }
+
+    /* Parks a session until it's resumed, or the grace period from `now` is over */
+    pub fn park(&mut self, token: &str, session: Session, now: Instant) {
+        self.parked.insert(token.to_string(), Parked{ session, deadline: now + self.grace });
+    }
+
+    /* Takes over the session parked under a token */
+    pub fn resume(&mut self, token: &str) -> Option<Session> {
+        self.parked.remove(token).map(|parked| parked.session)
+    }
+
+    /* Takes out the sessions whose grace period is over by `now`, for the caller to close */
+    pub fn expire(&mut self, now: Instant) -> Vec<Session> {
+        let expired: Vec<String> = self.parked.iter()
+                                              .filter(|(_, parked)| parked.deadline <= now)
+                                              .map(|(token, _)| token.clone())
+                                              .collect();
+        expired.iter().filter_map(|token| self.resume(token)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::handler::Handler;
+    use super::{Sessions, MAX_PARKED, TOKEN_LENGTH};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn park_test() {
+        let mut sessions = Sessions::new(Duration::from_secs(1));
+        let token = sessions.new_token();
+        assert_eq!(token.len(), TOKEN_LENGTH);
+        assert_ne!(token, sessions.new_token());
+
+        let mut handler = Handler::new();
+        handler.handle_raw(String::from("Start"));
+        let session = handler.session().unwrap();
+        let start = Instant::now();
+        sessions.park(&token, session, start);
+        let session = sessions.resume(&token).unwrap();
+        assert!(sessions.resume(&token).is_none());
+        /* Parked again later, the session gets a new grace period */
+        let later = start + Duration::from_secs(5);
+        sessions.park(&token, session, later);
+        assert!(sessions.expire(later).is_empty());
+        assert_eq!(sessions.expire(later + Duration::from_secs(1)).len(), 1);
+        assert!(sessions.resume(&token).is_none());
+    }
+
+    #[test]
+    fn park_test_full() {
+        let mut sessions = Sessions::new(Duration::from_secs(1));
+        let mut handler = Handler::new();
+        let mut last = String::new();
+        for _ in 0..MAX_PARKED {
+            assert!(!sessions.full(&last));
+            handler.handle_raw(String::from("Start"));
+            last = sessions.new_token();
+            sessions.park(&last, handler.session().unwrap(), Instant::now());
+        }
+        let token = sessions.new_token();
+        assert!(sessions.full(&token));
+        /* Parking again under a token replaces its session */
+        assert!(!sessions.full(&last));
+    }
+}
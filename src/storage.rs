@@ -0,0 +1,242 @@
+use super::game::Game;
+use super::board::Color;
+use super::position::Position;
+use super::analysis::PerColor;
+use super::rooms::GameOptions;
+use super::json::Json;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/*
+Games kept on disk, so they survive restarts. Every game is a JSON file in the storage directory, named after its id:
+    {"id": 3, "options": {...}, "code": "K7Q2MZ", "players": {"black": "<token>", "white": null}, "moves": [...], "finished": false}
+A game is written anew after every change. Unfinished games are brought back on startup, for their players to resume with their session tokens.
+Which games are kept, and which of them are finished, is read once on opening and kept in memory from then on.
+Beyond MAX_STORED games, the oldest are deleted - finished ones first.
+Storage is shared by all connections and locks itself, only ever briefly - files are written and read without holding it, only renamed and deleted.
+*/
+
+pub const MAX_STORED: usize = 1024;
+
+/* A game as kept in storage */
+#[derive(Debug, PartialEq, Clone)]
+pub struct Record {
+    pub id: u64,
+    pub options: GameOptions,
+    pub code: Option<String>,                       /* The room of a shared game - "None" for a game played alone */
+    pub players: PerColor<Option<String>>,          /* The session tokens of the players. A game played alone is Black's */
+    pub moves: Vec<(Position, Color)>,              /* Oldest first, each with the player who made it */
+    pub finished: bool
+}
+
+impl Record {
+    /* The game, played up to where the record ends */
+    pub fn replay(&self) -> Result<Game, String> {
+        let mut game = Game::start(self.options.size, self.options.variant)?;
+        for (position, player) in self.moves.iter() {
+            if *player != game.current_player() { game.pass()?; }
+            game.make_move(*position)?;
+        }
+        Ok(game)
+    }
+}
+
+/* A game kept on disk, as known without reading it */
+struct Stored {
+    finished: Option<GameOptions>,                  /* The options of a finished game - "None" while it's going on */
+    version: u64                                    /* The version last written - earlier ones arriving late are dropped */
+}
+
+struct Index {
+    next_id: u64,
+    stored: BTreeMap<u64, Stored>
+}
+
+pub struct Storage {
+    dir: PathBuf,
+    index: Mutex<Index>,
+    versions: AtomicU64                             /* Numbers the versions of all games, in the order they're taken */
+}
+
+impl Storage {
+    /* Keeps games in dir, which is created if need be. Ids go on from the highest one found there */
+    pub fn open(dir: impl Into<PathBuf>) -> io::Result<Storage> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        let storage = Storage{ dir, index: Mutex::new(Index{ next_id: 1, stored: BTreeMap::new() }), versions: AtomicU64::new(1) };
+        let mut ids = storage.ids()?;
+        ids.sort_unstable();
+        let mut stored = BTreeMap::new();
+        for id in ids.iter() {
+            match storage.load(*id) {
+                Ok(record) => { stored.insert(*id, Stored{ finished: Some(record.options).filter(|_| record.finished), version: 0 }); },
+                Err(e) => println!("Error while reading stored game {}: {}", id, e)
+            }
+        }
+        *storage.lock_index() = Index{ next_id: ids.last().map_or(1, |id| id + 1), stored };
+        Ok(storage)
+    }
+
+    fn lock_index(&self) -> MutexGuard<'_, Index> {
+        self.index.lock().expect("Impossible event: a thread panicked while holding the storage index (Storage::lock_index)")
+    }
+
+    pub fn new_id(&self) -> u64 {
+        let mut index = self.lock_index();
+        let id = index.next_id;
+        index.next_id += 1;
+        id
+    }
+
+    /* A number for the version of a game about to be saved - taken while the game is held, so versions follow its changes */
+    pub fn version(&self) -> u64 {
+        self.versions.fetch_add(1, Ordering::Relaxed)
+    }
+
+    fn path(&self, id: u64) -> PathBuf {
+        self.dir.join(format!("{}.json", id))
+    }
+
+    /* The ids of all games kept, in no particular order */
+    fn ids(&self) -> io::Result<Vec<u64>> {
+        let mut ids = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let name = entry?.file_name();
+            if let Some(id) = name.to_str().and_then(|name| name.strip_suffix(".json")).and_then(|id| id.parse::<u64>().ok()) {
+                ids.push(id);
+            }
+        }
+        Ok(ids)
+    }
+
+    /*
+    Written to a temporary file first, so a crash midway leaves the previous version in place.
+    A version older than the one already written is dropped, so a slow write can't undo a later one
+    */
+    pub fn save(&self, record: &Record, version: u64) -> io::Result<()> {
+        let temporary = self.dir.join(format!("{}.json.{}.tmp", record.id, version));
+        fs::write(&temporary, record.stringify_json().stringify())?;
+        let mut index = self.lock_index();
+        if index.stored.get(&record.id).is_some_and(|stored| stored.version > version) { return fs::remove_file(&temporary); }
+        fs::rename(&temporary, self.path(record.id))?;
+        index.stored.insert(record.id, Stored{ finished: Some(record.options).filter(|_| record.finished), version });
+        self.prune(&mut index, record.id);
+        Ok(())
+    }
+
+    /* Deletes the oldest games beyond MAX_STORED - finished ones first, and never the one just saved */
+    fn prune(&self, index: &mut Index, saved: u64) {
+        while index.stored.len() > MAX_STORED {
+            let oldest = |finished: bool| index.stored.iter().find(|(id, stored)| **id != saved && stored.finished.is_some() == finished).map(|(id, _)| *id);
+            let id = match oldest(true).or_else(|| oldest(false)) { Some(id) => id, None => return };
+            index.stored.remove(&id);
+            if let Err(e) = fs::remove_file(self.path(id)) { println!("Error while deleting stored game {}: {}", id, e); }
+        }
+    }
+
+    pub fn load(&self, id: u64) -> Result<Record, String> {
+        let source = fs::read_to_string(self.path(id)).map_err(|e| e.to_string())?;
+        let value = Json::parse(&source)?;
+        Record::parse_json(&value).map_err(|e| e.to_string())
+    }
+
+    /* The finished games kept, by id - without reading any of them */
+    pub fn finished(&self) -> Vec<(u64, GameOptions)> {
+        self.lock_index().stored.iter().filter_map(|(id, stored)| stored.finished.map(|options| (*id, options))).collect()
+    }
+
+    /* Every unfinished game kept, by id. Games which can't be read are reported and left out */
+    pub fn unfinished(&self) -> Vec<Record> {
+        let ids: Vec<u64> = self.lock_index().stored.iter().filter(|(_, stored)| stored.finished.is_none()).map(|(id, _)| *id).collect();
+        ids.into_iter().filter_map(|id| match self.load(id) {
+            Ok(record) => Some(record),
+            Err(e) => { println!("Error while reading stored game {}: {}", id, e); None }
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::board::Color;
+    use super::super::position::Position;
+    use super::super::analysis::PerColor;
+    use super::super::rooms::GameOptions;
+    use super::{Record, Storage, MAX_STORED};
+    use std::fs;
+
+    fn record(id: u64) -> Record {
+        let moves = vec![(Position{x: 3, y: 4}, Color::Black), (Position{x: 3, y: 3}, Color::White)];
+        Record{ id, options: GameOptions::default(), code: None, players: PerColor{ black: Some(String::from("token")), white: None }, moves, finished: false }
+    }
+
+    #[test]
+    fn replay_test() {
+        let game = record(1).replay().unwrap();
+        assert_eq!(game.history().len(), 2);
+        assert_eq!(game.current_player(), Color::Black);
+        let mut invalid = record(1);
+        invalid.moves.push((Position{x: 0, y: 0}, Color::Black));
+        assert_eq!(invalid.replay().map(|_| ()), Err(String::from("You must flip at least one tile")));
+    }
+
+    #[test]
+    fn save_and_load_test() {
+        let dir = std::env::temp_dir().join(format!("reversi_storage_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let storage = Storage::open(&dir).unwrap();
+        assert_eq!(storage.new_id(), 1);
+        let mut finished = record(4);
+        finished.code = Some(String::from("K7Q2MZ"));
+        finished.finished = true;
+        storage.save(&record(2), storage.version()).unwrap();
+        storage.save(&finished, storage.version()).unwrap();
+        assert_eq!(storage.finished(), vec![(4, finished.options)]);
+        fs::write(dir.join("3.json"), "{").unwrap();
+
+        let reopened = Storage::open(&dir).unwrap();
+        assert_eq!(reopened.new_id(), 5);
+        assert_eq!(reopened.load(4), Ok(finished.clone()));
+        assert!(reopened.load(7).is_err());
+        assert_eq!(reopened.unfinished(), vec![record(2)]);
+        assert_eq!(reopened.finished(), vec![(4, finished.options)]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn save_test_late_version() {
+        let dir = std::env::temp_dir().join(format!("reversi_storage_version_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let storage = Storage::open(&dir).unwrap();
+        let (earlier, later) = (storage.version(), storage.version());
+        let mut moved = record(1);
+        moved.moves.pop();
+        storage.save(&moved, later).unwrap();
+        storage.save(&record(1), earlier).unwrap();
+        assert_eq!(storage.load(1), Ok(moved));
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn save_test_prune() {
+        let dir = std::env::temp_dir().join(format!("reversi_storage_prune_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let storage = Storage::open(&dir).unwrap();
+        let finished = Record{ finished: true, ..record(2) };
+        storage.save(&record(1), storage.version()).unwrap();
+        storage.save(&finished, storage.version()).unwrap();
+        for id in 3..=MAX_STORED as u64 + 2 { storage.save(&record(id), storage.version()).unwrap(); }
+        /* The finished game goes first, then the oldest unfinished one */
+        assert!(storage.load(2).is_err());
+        assert!(storage.load(1).is_err());
+        assert!(storage.load(3).is_ok());
+        assert_eq!(storage.unfinished().len(), MAX_STORED);
+        assert!(storage.finished().is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
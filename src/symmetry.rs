@@ -0,0 +1,155 @@
+use super::board::{Board, Color};
+use super::position::{Position, Size};
+
+/*
+The 8 rotations and reflections of a rectangle. Rotating by a quarter turn or reflecting over a diagonal swaps
+width and height, so on non-square boards only 4 of them map the board onto itself.
+Rotations are clockwise, in a coordinate system where y grows downwards.
+*/
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Symmetry {
+    Identity, Rotate90, Rotate180, Rotate270, FlipHorizontal, FlipVertical, FlipDiagonal, FlipAntiDiagonal
+}
+
+static ALL_SYMMETRIES: [Symmetry; 8] = [
+    Symmetry::Identity,
+    Symmetry::Rotate90,
+    Symmetry::Rotate180,
+    Symmetry::Rotate270,
+    Symmetry::FlipHorizontal,
+    Symmetry::FlipVertical,
+    Symmetry::FlipDiagonal,
+    Symmetry::FlipAntiDiagonal
+];
+
+impl Symmetry {
+    pub fn iter_all() -> impl Iterator<Item=&'static Symmetry> {
+        ALL_SYMMETRIES.iter()
+    }
+
+    pub fn inverse(&self) -> Symmetry {
+        match self {
+            Symmetry::Rotate90 => Symmetry::Rotate270,
+            Symmetry::Rotate270 => Symmetry::Rotate90,
+            other => *other
+        }
+    }
+
+    fn swaps_axes(&self) -> bool {
+        matches!(self, Symmetry::Rotate90 | Symmetry::Rotate270 | Symmetry::FlipDiagonal | Symmetry::FlipAntiDiagonal)
+    }
+
+    /* The size of a board of the given size after the transformation */
+    pub fn transform_size(&self, size: &Size) -> Size {
+        if self.swaps_axes() { Size{ width: size.height, height: size.width } } else { *size }
+    }
+
+    /* The symmetries mapping a board of the given size onto a board of the same size */
+    pub fn iter_preserving(size: &Size) -> impl Iterator<Item=&'static Symmetry> {
+        let square = size.width == size.height;
+        Symmetry::iter_all().filter(move |symmetry| square || !symmetry.swaps_axes())
+    }
+}
+
+impl Position {
+    /* Where this position goes when a board of the given size is transformed */
+    pub fn transform(&self, symmetry: &Symmetry, size: &Size) -> Position {
+        let (max_x, max_y) = (size.width - 1, size.height - 1);
+        let (x, y) = (self.x, self.y);
+        match symmetry {
+            Symmetry::Identity => Position{x, y},
+            Symmetry::Rotate90 => Position{x: max_y - y, y: x},
+            Symmetry::Rotate180 => Position{x: max_x - x, y: max_y - y},
+            Symmetry::Rotate270 => Position{x: y, y: max_x - x},
+            Symmetry::FlipHorizontal => Position{x: max_x - x, y},
+            Symmetry::FlipVertical => Position{x, y: max_y - y},
+            Symmetry::FlipDiagonal => Position{x: y, y: x},
+            Symmetry::FlipAntiDiagonal => Position{x: max_y - y, y: max_x - x}
+        }
+    }
+}
+
+fn tile_rank(board: &Board, pos: &Position) -> u8 {
+    match board.get(pos).0 {
+        None => 0,
+        Some(Color::Black) => 1,
+        Some(Color::White) => 2
+    }
+}
+
+impl Board {
+    pub fn transform(&self, symmetry: &Symmetry) -> Board {
+        let size = symmetry.transform_size(&self.size);
+        let mut transformed = Board::new(size.width, size.height);
+        for pos in self.iter_all_positions() {
+            if let Some(color) = self.get(&pos).0 {
+                transformed.set(&pos.transform(symmetry, &self.size), &color);
+            }
+        }
+        transformed
+    }
+
+    /*
+    The representative of this board's symmetry class - the same for every board that's a rotation or reflection of this one.
+    Also returns the symmetry leading from this board to the canonical one, so moves can be mapped there (and back with its inverse).
+    */
+    pub fn canonical(&self) -> (Board, Symmetry) {
+        let ranks = |board: &Board| board.iter_all_positions().map(|pos| tile_rank(board, &pos)).collect::<Vec<u8>>();
+        Symmetry::iter_preserving(&self.size)
+                 .map(|symmetry| (self.transform(symmetry), *symmetry))
+                 .min_by_key(|(board, _)| ranks(board))
+                 .expect("Impossible event: Identity always preserves the size (Board::canonical)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::board::{Board, Color};
+    use super::super::position::{Position, Size};
+    use super::Symmetry;
+
+    #[test]
+    fn transform_position_test() {
+        let size = Size{width: 4, height: 3};
+        let pos = Position{x: 1, y: 0};
+        assert_eq!(pos.transform(&Symmetry::Identity, &size), Position{x: 1, y: 0});
+        assert_eq!(pos.transform(&Symmetry::Rotate90, &size), Position{x: 2, y: 1});
+        assert_eq!(pos.transform(&Symmetry::Rotate180, &size), Position{x: 2, y: 2});
+        assert_eq!(pos.transform(&Symmetry::Rotate270, &size), Position{x: 0, y: 2});
+        assert_eq!(pos.transform(&Symmetry::FlipHorizontal, &size), Position{x: 2, y: 0});
+        assert_eq!(pos.transform(&Symmetry::FlipVertical, &size), Position{x: 1, y: 2});
+        assert_eq!(pos.transform(&Symmetry::FlipDiagonal, &size), Position{x: 0, y: 1});
+        assert_eq!(pos.transform(&Symmetry::FlipAntiDiagonal, &size), Position{x: 2, y: 2});
+    }
+
+    #[test]
+    fn transform_position_inverse_test() {
+        let size = Size{width: 4, height: 3};
+        for symmetry in Symmetry::iter_all() {
+            let transformed_size = symmetry.transform_size(&size);
+            for pos in Board::new(4, 3).iter_all_positions() {
+                let transformed = pos.transform(symmetry, &size);
+                assert!(transformed.x < transformed_size.width && transformed.y < transformed_size.height);
+                assert_eq!(transformed.transform(&symmetry.inverse(), &transformed_size), pos, "{:?}", symmetry);
+            }
+        }
+    }
+
+    #[test]
+    fn preserving_symmetries_test() {
+        assert_eq!(Symmetry::iter_preserving(&Size{width: 8, height: 8}).count(), 8);
+        assert_eq!(Symmetry::iter_preserving(&Size{width: 8, height: 6}).count(), 4);
+    }
+
+    #[test]
+    fn canonical_test() {
+        let mut board = Board::new(4, 4);
+        board.set(&Position{x: 1, y: 0}, &Color::Black);
+        board.set(&Position{x: 3, y: 3}, &Color::White);
+        let (canonical, symmetry) = board.canonical();
+        assert_eq!(board.transform(&symmetry), canonical);
+        for other in Symmetry::iter_all() {
+            assert_eq!(board.transform(other).canonical().0, canonical);
+        }
+    }
+}
@@ -0,0 +1,463 @@
+use super::handler::{Handler, Encoding};
+use super::http;
+use super::rooms::Rooms;
+use super::sessions::Sessions;
+use super::storage::Storage;
+use super::search::SearchConfig;
+use super::pool::{self, Pool};
+use super::server::{hang_up, lock_handler, pass_on_events, read_error, Incoming, ReadError, Timeouts, IDLE, MAX_FRAME_SIZE};
+
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::sync::{mpsc, Arc, Mutex};
+
+/*
+A WebSocket (RFC 6455) endpoint for browsers, which can't open raw TCP sockets.
+Every message is a single request, answered by a single message - the same requests and responses as the TCP server.
+Responses are text messages, or binary ones once the connection switched to the binary encoding.
+Connections sending no message for longer than the idle timeout are closed with "Going Away" - pings, ours or WebSocket's, keep them open.
+*/
+
+const HANDSHAKE_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const PROTOCOL_VERSION: &str = "13";
+const MAX_CONTROL_PAYLOAD: u64 = 125;
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xA;
+
+const CLOSE_NORMAL: u16 = 1000;
+const CLOSE_GOING_AWAY: u16 = 1001;
+const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+const CLOSE_INVALID_DATA: u16 = 1007;
+const CLOSE_TOO_BIG: u16 = 1009;
+
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x6745_2301, 0xEFCD_AB89, 0x98BA_DCFE, 0x1032_5476, 0xC3D2_E1F0];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 { message.push(0); }
+    message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+
+    for chunk in message.chunks(64) {
+        let mut words = [0u32; 80];
+        for (idx, word) in chunk.chunks(4).enumerate() {
+            words[idx] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for idx in 16..80 {
+            words[idx] = (words[idx - 3] ^ words[idx - 8] ^ words[idx - 14] ^ words[idx - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (idx, word) in words.iter().enumerate() {
+            let (f, k) = match idx {
+                0..=19 => ((b & c) | (!b & d), 0x5A82_7999),
+                20..=39 => (b ^ c ^ d, 0x6ED9_EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1B_BCDC),
+                _ => (b ^ c ^ d, 0xCA62_C1D6)
+            };
+            let temp = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (value, addition) in state.iter_mut().zip([a, b, c, d, e].iter()) {
+            *value = value.wrapping_add(*addition);
+        }
+    }
+
+    let mut digest = [0u8; 20];
+    for (idx, value) in state.iter().enumerate() {
+        digest[idx * 4..idx * 4 + 4].copy_from_slice(&value.to_be_bytes());
+    }
+    digest
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::new();
+    for chunk in data.chunks(3) {
+        let bytes = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let group = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        for idx in 0..4 {
+            if idx <= chunk.len() {
+                encoded.push(ALPHABET[(group >> (18 - 6 * idx) & 0x3F) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+fn accept_key(key: &str) -> String {
+    base64(&sha1(format!("{}{}", key, HANDSHAKE_GUID).as_bytes()))
+}
+
+/*
+Reads the HTTP upgrade request, and answers it - with "101 Switching Protocols" if it's a valid one.
+Only version 13 of the protocol is spoken - other versions are told so with "426 Upgrade Required", as the RFC asks.
+*/
+fn handshake(reader: &mut impl BufRead, writer: &mut impl Write) -> Result<(), String> {
+    let request = match http::read_request(reader) {
+        Ok(request) => request,
+        Err(message) => {
+            let _ = http::write_response(writer, 400, None);
+            return Err(message);
+        }
+    };
+    let is_upgrade = request.header("Upgrade").is_some_and(|value| value.eq_ignore_ascii_case("websocket"));
+    if is_upgrade && request.header("Sec-WebSocket-Version") != Some(PROTOCOL_VERSION) {
+        let response = format!("HTTP/1.1 426 Upgrade Required\r\nSec-WebSocket-Version: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", PROTOCOL_VERSION);
+        let _ = writer.write_all(response.as_bytes()).and_then(|_| writer.flush());
+        return Err(String::from("Unsupported WebSocket version"));
+    }
+
+    match request.header("Sec-WebSocket-Key") {
+        Some(key) if request.method == "GET" && is_upgrade => {
+            let response = format!("HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n", accept_key(key));
+            writer.write_all(response.as_bytes()).map_err(|e| e.to_string())?;
+            writer.flush().map_err(|e| e.to_string())
+        },
+        _ => {
+            let _ = http::write_response(writer, 400, None);
+            Err(String::from("Not a WebSocket upgrade request"))
+        }
+    }
+}
+
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    payload: Vec<u8>
+}
+
+enum FrameError {
+    Io(String),
+    Close(u16, &'static str)            /* The connection must be closed with this status code */
+}
+
+fn read_frame(reader: &mut impl Read) -> Result<Frame, FrameError> {
+    let io = |e: std::io::Error| match read_error(e) {
+        ReadError::Idle => FrameError::Close(CLOSE_GOING_AWAY, IDLE),
+        ReadError::Failed(e) => FrameError::Io(e)
+    };
+    let mut header = [0u8; 2];
+    reader.read_exact(&mut header).map_err(io)?;
+    let fin = header[0] & 0x80 != 0;
+    let opcode = header[0] & 0x0F;
+    if header[0] & 0x70 != 0 { return Err(FrameError::Close(CLOSE_PROTOCOL_ERROR, "Reserved bits are set")); }
+    if header[1] & 0x80 == 0 { return Err(FrameError::Close(CLOSE_PROTOCOL_ERROR, "Client frames must be masked")); }
+
+    let len = match header[1] & 0x7F {
+        126 => {
+            let mut extended = [0u8; 2];
+            reader.read_exact(&mut extended).map_err(io)?;
+            u16::from_be_bytes(extended) as u64
+        },
+        127 => {
+            let mut extended = [0u8; 8];
+            reader.read_exact(&mut extended).map_err(io)?;
+            u64::from_be_bytes(extended)
+        },
+        len => len as u64
+    };
+    if len > MAX_FRAME_SIZE as u64 { return Err(FrameError::Close(CLOSE_TOO_BIG, "Message too big")); }
+    if opcode >= OPCODE_CLOSE && (!fin || len > MAX_CONTROL_PAYLOAD) {
+        return Err(FrameError::Close(CLOSE_PROTOCOL_ERROR, "Control frames must be short and unfragmented"));
+    }
+
+    let mut mask = [0u8; 4];
+    reader.read_exact(&mut mask).map_err(io)?;
+    let mut payload = vec![0u8; len as usize];
+    reader.read_exact(&mut payload).map_err(io)?;
+    for (idx, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[idx % 4];
+    }
+    Ok(Frame{ fin, opcode, payload })
+}
+
+/* Server frames are never masked or fragmented */
+fn write_frame(writer: &mut impl Write, opcode: u8, payload: &[u8]) -> std::io::Result<()> {
+    let mut header = vec![0x80 | opcode];
+    match payload.len() {
+        len if len < 126 => header.push(len as u8),
+        len if len <= u16::MAX as usize => {
+            header.push(126);
+            header.extend_from_slice(&(len as u16).to_be_bytes());
+        },
+        len => {
+            header.push(127);
+            header.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    header.extend_from_slice(payload);
+    writer.write_all(&header)?;
+    writer.flush()
+}
+
+fn write_close(writer: &mut impl Write, code: u16, reason: &str) -> std::io::Result<()> {
+    let mut payload = code.to_be_bytes().to_vec();
+    payload.extend_from_slice(reason.as_bytes());
+    write_frame(writer, OPCODE_CLOSE, &payload)
+}
+
+/* Closes the connection from this side - there is no message to return anymore */
+fn close_with(writer: &mut impl Write, code: u16, reason: &str) -> Result<Option<Vec<u8>>, String> {
+    write_close(writer, code, reason).map(|_| None).map_err(|e| e.to_string())
+}
+
+/*
+Reads frames until a whole message has arrived, answering pings on the way - each of which gives the reader a new idle timeout, through `pinged`.
+Text messages are checked to be valid UTF-8. Returns None once the connection was closed - by the client or because of a protocol error.
+*/
+fn read_message<R: Read>(reader: &mut R, writer: &mut impl Write, pinged: impl Fn(&mut R)) -> Result<Option<Vec<u8>>, String> {
+    let mut message: Option<(u8, Vec<u8>)> = None;                          /* The opcode of its first frame, and the payload so far */
+    loop {
+        let frame = match read_frame(reader) {
+            Ok(frame) => frame,
+            Err(FrameError::Io(e)) => return Err(e),
+            Err(FrameError::Close(code, reason)) => return close_with(writer, code, reason)
+        };
+
+        match frame.opcode {
+            OPCODE_PING => {
+                write_frame(writer, OPCODE_PONG, &frame.payload).map_err(|e| e.to_string())?;
+                pinged(reader);
+            },
+            OPCODE_PONG => {},
+            OPCODE_CLOSE => {
+                let code = if frame.payload.len() >= 2 { u16::from_be_bytes([frame.payload[0], frame.payload[1]]) } else { CLOSE_NORMAL };
+                return close_with(writer, code, "");
+            },
+            OPCODE_TEXT | OPCODE_BINARY if message.is_none() => message = Some((frame.opcode, frame.payload)),
+            OPCODE_CONTINUATION if message.is_some() => {
+                let (_, pending) = message.as_mut().expect("Impossible event: continuation without a message (websocket::read_message)");
+                if pending.len() + frame.payload.len() > MAX_FRAME_SIZE { return close_with(writer, CLOSE_TOO_BIG, "Message too big"); }
+                pending.extend_from_slice(&frame.payload);
+            },
+            _ => return close_with(writer, CLOSE_PROTOCOL_ERROR, "Unexpected frame")
+        }
+
+        if frame.fin && frame.opcode < OPCODE_CLOSE {
+            match message.take() {
+                Some((OPCODE_TEXT, payload)) if std::str::from_utf8(&payload).is_err() => {
+                    return close_with(writer, CLOSE_INVALID_DATA, "Message is not valid UTF-8");
+                },
+                Some((_, payload)) => return Ok(Some(payload)),
+                None => {}
+            }
+        }
+    }
+}
+
+fn write_message(writer: &mut impl Write, message: &[u8], encoding: Encoding) -> std::io::Result<()> {
+    write_frame(writer, if encoding == Encoding::Binary { OPCODE_BINARY } else { OPCODE_TEXT }, message)
+}
+
+fn serve_single(stream: TcpStream, rooms: Arc<Mutex<Rooms>>, sessions: Arc<Mutex<Sessions>>, storage: Option<Arc<Storage>>, search_config: SearchConfig, timeouts: Timeouts) {
+    let (read_half, mut event_writer) = match timeouts.apply(&stream).and_then(|_| Ok((stream.try_clone()?, stream.try_clone()?))) {
+        Ok(halves) => halves,
+        Err(e) => { println!("Error while setting up WebSocket stream: {}", e); return; }
+    };
+    let mut writer = stream;
+    let (outbox, events) = mpsc::channel();
+    let mut handler = Handler::connected(rooms, sessions, storage, outbox);
+    handler.search_with(search_config);
+    let handler = Mutex::new(handler);
+    let pass_on = || pass_on_events(&handler, &events, |message, encoding| write_message(&mut event_writer, message, encoding));
+    let mut reader = BufReader::new(Incoming::new(read_half, timeouts.idle, pass_on));
+    if let Err(e) = handshake(&mut reader, &mut writer) {
+        println!("Error during WebSocket handshake: {}", e);
+        return;
+    }
+
+    loop {
+        reader.get_mut().start_request();
+        let request = match read_message(&mut reader, &mut writer, |reader| reader.get_mut().start_request()) {
+            Ok(Some(request)) => request,
+            Ok(None) => break,
+            Err(e) => { println!("Error while reading WebSocket message: {}", e); break; }
+        };
+
+        let mut locked = lock_handler(&handler);
+        let response = locked.handle_message(&request);
+        let written = write_message(&mut writer, &response, locked.encoding());
+        drop(locked);
+        if let Err(e) = written.and_then(|_| pass_on_events(&handler, &events, |message, encoding| write_message(&mut writer, message, encoding))) {
+            println!("Error while writing WebSocket message: {}", e);
+            break;
+        }
+    }
+
+    let _ = writer.shutdown(Shutdown::Both);
+    hang_up(&handler);
+    println!("WebSocket done!");
+}
+
+/* Connections beyond what the pool can serve are refused before the handshake, with "503 Service Unavailable" */
+pub fn serve_incoming(listener: TcpListener, rooms: Arc<Mutex<Rooms>>, sessions: Arc<Mutex<Sessions>>, storage: Option<Arc<Storage>>, search_config: SearchConfig, pool: Arc<Pool>, timeouts: Timeouts) {
+    for incoming_stream in listener.incoming() {
+        match incoming_stream {
+            Ok(valid_stream) => match pool.claim() {
+                Some(worker) => {
+                    println!("Handling valid WebSocket stream");
+                    let (rooms, sessions, storage) = (Arc::clone(&rooms), Arc::clone(&sessions), storage.clone());
+                    worker.run(move || serve_single(valid_stream, rooms, sessions, storage, search_config, timeouts));
+                },
+                None => {
+                    println!("Turning away WebSocket stream, all {} workers are busy", pool.size());
+                    pool::turn_away(valid_stream, &http::rejection());
+                }
+            },
+            Err(e) => println!("Error while handling incoming WebSocket stream: {}", e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{accept_key, base64, handshake, read_message, sha1, write_frame, OPCODE_TEXT};
+    use std::io::{BufReader, Cursor, Read};
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    /* A frame as a browser would send it - always masked */
+    fn client_frame(fin: bool, opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x12u8, 0x34, 0x56, 0x78];
+        let mut frame = vec![if fin { 0x80 } else { 0 } | opcode];
+        if payload.len() < 126 {
+            frame.push(0x80 | payload.len() as u8);
+        } else {
+            frame.push(0x80 | 126);
+            frame.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        }
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(idx, byte)| byte ^ mask[idx % 4]));
+        frame
+    }
+
+    #[test]
+    fn sha1_test() {
+        assert_eq!(hex(&sha1(b"")), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(hex(&sha1(b"abc")), "a9993e364706816aba3e25717850c26c9cd0d89d");
+        assert_eq!(hex(&sha1(&[b'a'; 1000])), "291e9a6c66994949b57ba5e650361e98fc36b1ba");
+    }
+
+    #[test]
+    fn base64_test() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+    }
+
+    #[test]
+    fn accept_key_test() {
+        assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[test]
+    fn handshake_test() {
+        let request = "GET /chat HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+                       sec-websocket-key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n";
+        let mut output: Vec<u8> = Vec::new();
+        assert_eq!(handshake(&mut Cursor::new(request), &mut output), Ok(()));
+        assert_eq!(String::from_utf8(output).unwrap(),
+                   "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\r\n");
+    }
+
+    #[test]
+    fn handshake_test_not_upgrade() {
+        let mut output: Vec<u8> = Vec::new();
+        assert!(handshake(&mut Cursor::new("GET / HTTP/1.1\r\nHost: localhost\r\n\r\n"), &mut output).is_err());
+        assert!(String::from_utf8(output).unwrap().starts_with("HTTP/1.1 400 Bad Request"));
+    }
+
+    #[test]
+    fn handshake_test_version() {
+        let request = "GET /chat HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+                       Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 8\r\n\r\n";
+        let mut output: Vec<u8> = Vec::new();
+        assert_eq!(handshake(&mut Cursor::new(request), &mut output), Err(String::from("Unsupported WebSocket version")));
+        assert_eq!(String::from_utf8(output).unwrap(),
+                   "HTTP/1.1 426 Upgrade Required\r\nSec-WebSocket-Version: 13\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+    }
+
+    #[test]
+    fn handshake_test_endless_line() {
+        let mut reader = BufReader::new(b"GET /".chain(std::io::repeat(b'a')));
+        let mut output: Vec<u8> = Vec::new();
+        assert_eq!(handshake(&mut reader, &mut output), Err(String::from("Request head too large")));
+        assert!(String::from_utf8(output).unwrap().starts_with("HTTP/1.1 400 Bad Request"));
+    }
+
+    #[test]
+    fn read_message_test_fragmented_with_ping() {
+        let mut input = client_frame(false, 0x1, b"DoTu");
+        input.extend(client_frame(true, 0x9, b"hi"));
+        input.extend(client_frame(true, 0x0, b"rn;3,4"));
+        input.extend(client_frame(true, 0x1, &[b'x'; 300]));
+        let mut reader = Cursor::new(input);
+        let mut output: Vec<u8> = Vec::new();
+        assert_eq!(read_message(&mut reader, &mut output, |_| {}), Ok(Some(b"DoTurn;3,4".to_vec())));
+        assert_eq!(output, vec![0x8A, 2, b'h', b'i']);
+        assert_eq!(read_message(&mut reader, &mut output, |_| {}), Ok(Some(vec![b'x'; 300])));
+    }
+
+    #[test]
+    fn read_message_test_binary() {
+        let mut input = client_frame(true, 0x2, &[0x01, 0, 0, 0, 0]);
+        input.extend(client_frame(true, 0x1, &[0xff, 0xfe]));
+        let mut reader = Cursor::new(input);
+        let mut output: Vec<u8> = Vec::new();
+        assert_eq!(read_message(&mut reader, &mut output, |_| {}), Ok(Some(vec![0x01, 0, 0, 0, 0])));
+        assert_eq!(read_message(&mut reader, &mut output, |_| {}), Ok(None));
+        assert_eq!(&output[..4], &[0x88, 28, 0x03, 0xEF]);
+    }
+
+    #[test]
+    fn read_message_test_close() {
+        let mut reader = Cursor::new(client_frame(true, 0x8, &1000u16.to_be_bytes()));
+        let mut output: Vec<u8> = Vec::new();
+        assert_eq!(read_message(&mut reader, &mut output, |_| {}), Ok(None));
+        assert_eq!(output, vec![0x88, 2, 0x03, 0xE8]);
+    }
+
+    #[test]
+    fn read_message_test_invalid_control_frames() {
+        for frame in [client_frame(true, 0x9, &[b'x'; 126]), client_frame(false, 0x9, b"hi")].iter() {
+            let mut output: Vec<u8> = Vec::new();
+            assert_eq!(read_message(&mut Cursor::new(frame), &mut output, |_| {}), Ok(None));
+            assert_eq!(&output[..4], &[0x88, 47, 0x03, 0xEA]);
+        }
+    }
+
+    #[test]
+    fn read_message_test_unmasked() {
+        let mut reader = Cursor::new(vec![0x81u8, 2, b'h', b'i']);
+        let mut output: Vec<u8> = Vec::new();
+        assert_eq!(read_message(&mut reader, &mut output, |_| {}), Ok(None));
+        assert_eq!(&output[..4], &[0x88, 30, 0x03, 0xEA]);
+    }
+
+    #[test]
+    fn write_frame_test() {
+        let mut output: Vec<u8> = Vec::new();
+        write_frame(&mut output, OPCODE_TEXT, b"Ok").unwrap();
+        assert_eq!(output, vec![0x81, 2, b'O', b'k']);
+
+        let mut output: Vec<u8> = Vec::new();
+        write_frame(&mut output, OPCODE_TEXT, &[b'x'; 200]).unwrap();
+        assert_eq!(&output[..4], &[0x81, 126, 0, 200]);
+        assert_eq!(output.len(), 204);
+    }
+}